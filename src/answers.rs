@@ -0,0 +1,196 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result, anyhow};
+
+use crate::config;
+use crate::lock;
+
+// Template variable values checked in for reproducible runs, as a flat TOML or JSON map (JSON
+// when the file ends in `.json`). Non-string values are used in their textual form.
+pub fn load(path: &Path) -> Result<HashMap<String, String>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Unable to read answers file {}", path.display()))?;
+    let invalid = || {
+        format!(
+            "Answers file {} is not a map of variable values",
+            path.display()
+        )
+    };
+    if is_json(path) {
+        let map: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_str(&content).with_context(invalid)?;
+        Ok(map
+            .into_iter()
+            .map(|(name, value)| {
+                let value = match value {
+                    serde_json::Value::String(value) => value,
+                    serde_json::Value::Null => String::new(),
+                    other => other.to_string(),
+                };
+                (name, value)
+            })
+            .collect())
+    } else {
+        let table: toml::Table = toml::from_str(&content).with_context(invalid)?;
+        table
+            .into_iter()
+            .map(|(name, value)| match value {
+                toml::Value::String(value) => Ok((name, value)),
+                toml::Value::Table(_) | toml::Value::Array(_) => Err(anyhow!(
+                    "Answers file {}: {} must be a single value",
+                    path.display(),
+                    name
+                )),
+                other => Ok((name, other.to_string())),
+            })
+            .collect()
+    }
+}
+
+pub fn save(path: &Path, values: &BTreeMap<String, String>) -> Result<()> {
+    let content = if is_json(path) {
+        serde_json::to_string_pretty(values)? + "\n"
+    } else {
+        toml::to_string_pretty(values)?
+    };
+    lock::write_atomic(path, content)
+        .with_context(|| format!("Unable to write answers file {}", path.display()))
+}
+
+// Names matching one of `patterns` (case-insensitive globs) are never written to an answers file.
+pub fn is_secret(name: &str, patterns: &[String]) -> bool {
+    let name = name.to_ascii_lowercase();
+    patterns
+        .iter()
+        .any(|pattern| config::glob_match(&pattern.to_ascii_lowercase(), &name))
+}
+
+// Answer names the template never mentions, sorted so the warning reads the same every run.
+pub fn unused<'a>(answers: &'a HashMap<String, String>, used: &[String]) -> Vec<&'a str> {
+    let mut unused = answers
+        .keys()
+        .filter(|name| !used.contains(name))
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+    unused.sort_unstable();
+    unused
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    fn values(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn toml_and_json_answers_read_scalars_as_text() {
+        let tmp = TempDir::new("answers-load");
+        let toml_path = tmp.path().join("answers.toml");
+        fs::write(
+            &toml_path,
+            "service = \"billing\"\nretries = 3\nverbose = true\n",
+        )
+        .unwrap();
+        let json_path = tmp.path().join("answers.JSON");
+        fs::write(
+            &json_path,
+            r#"{"service": "billing", "retries": 3, "verbose": true, "owner": null}"#,
+        )
+        .unwrap();
+
+        let expected = values(&[
+            ("service", "billing"),
+            ("retries", "3"),
+            ("verbose", "true"),
+        ]);
+        assert_eq!(load(&toml_path).unwrap(), expected);
+        let mut expected = expected;
+        expected.insert("owner".to_string(), String::new());
+        assert_eq!(load(&json_path).unwrap(), expected);
+    }
+
+    #[test]
+    fn nested_or_malformed_answers_are_rejected() {
+        let tmp = TempDir::new("answers-invalid");
+        let cases = [
+            (
+                "nested.toml",
+                "[service]\nname = \"billing\"\n",
+                "service must be a single value",
+            ),
+            (
+                "list.toml",
+                "owners = [\"a\"]\n",
+                "owners must be a single value",
+            ),
+            (
+                "broken.toml",
+                "service = \n",
+                "is not a map of variable values",
+            ),
+            ("list.json", "[1, 2]", "is not a map of variable values"),
+        ];
+        for (name, content, message) in cases {
+            let path = tmp.path().join(name);
+            fs::write(&path, content).unwrap();
+            let err = format!("{:#}", load(&path).unwrap_err());
+            assert!(err.contains(message), "{}: {}", name, err);
+        }
+        assert!(load(&tmp.path().join("missing.toml")).is_err());
+    }
+
+    #[test]
+    fn saved_answers_load_back_in_both_formats() {
+        let tmp = TempDir::new("answers-save");
+        let saved = BTreeMap::from([
+            ("service".to_string(), "billing \"eu\"".to_string()),
+            ("notes".to_string(), "line one\nline two".to_string()),
+        ]);
+        for name in ["answers.toml", "answers.json"] {
+            let path = tmp.path().join(name);
+            save(&path, &saved).unwrap();
+            assert_eq!(
+                load(&path).unwrap().into_iter().collect::<BTreeMap<_, _>>(),
+                saved,
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn secret_names_match_case_insensitive_globs() {
+        let patterns = ["*token*".to_string(), "PASSWORD".to_string()];
+        for (name, secret) in [
+            ("github_token", true),
+            ("API_TOKEN", true),
+            ("password", true),
+            ("password_hint", false),
+            ("service", false),
+        ] {
+            assert_eq!(is_secret(name, &patterns), secret, "{}", name);
+        }
+        assert!(!is_secret("token", &[]));
+    }
+
+    #[test]
+    fn unused_answers_are_listed_in_name_order() {
+        let answers = values(&[("zone", "a"), ("service", "b"), ("extra", "c")]);
+        let used = ["service".to_string()];
+        assert_eq!(unused(&answers, &used), ["extra", "zone"]);
+        assert!(unused(&HashMap::new(), &used).is_empty());
+    }
+}
@@ -0,0 +1,239 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+
+use crate::fs_caps;
+use crate::lock::{FileLock, read_state, write_atomic};
+
+pub const ARCHIVE_DIR: &str = ".archive";
+const MANIFEST_FILENAME: &str = "manifest.toml";
+const NOTES_DIR: &str = "notes";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveEntry {
+    pub name: String,
+    pub branch: Option<String>,
+    pub original_path: PathBuf,
+    pub archived_at: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    entries: Vec<ArchiveEntry>,
+}
+
+pub struct Archive {
+    dir: PathBuf,
+}
+
+impl Archive {
+    pub fn new(worktree_base: &Path) -> Self {
+        Self {
+            dir: worktree_base.join(ARCHIVE_DIR),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn entry_path(&self, entry: &ArchiveEntry) -> PathBuf {
+        self.dir.join(&entry.name)
+    }
+
+    pub fn entries(&self) -> Result<Vec<ArchiveEntry>> {
+        Ok(self.read_manifest()?.entries)
+    }
+
+    pub fn store(&self, worktree_path: &Path, branch: Option<&str>) -> Result<ArchiveEntry> {
+        fs::create_dir_all(&self.dir).with_context(|| {
+            format!("Unable to create archive directory {}", self.dir.display())
+        })?;
+
+        let slug = worktree_path
+            .file_name()
+            .ok_or_else(|| anyhow!("Invalid worktree path {}", worktree_path.display()))?
+            .to_string_lossy()
+            .to_string();
+        let archived_at = now_secs();
+        let entry = ArchiveEntry {
+            name: format!("{}-{}", slug, archived_at),
+            branch: branch.map(|b| b.to_string()),
+            original_path: worktree_path.to_path_buf(),
+            archived_at,
+        };
+
+        move_dir(worktree_path, &self.entry_path(&entry))?;
+
+        let _lock = FileLock::acquire(&self.manifest_path())?;
+        let mut manifest = self.read_manifest()?;
+        manifest.entries.push(entry.clone());
+        self.write_manifest(&manifest)?;
+        Ok(entry)
+    }
+
+    pub fn forget(&self, entry: &ArchiveEntry) -> Result<()> {
+        let path = self.entry_path(entry);
+        if path.exists() {
+            fs::remove_dir_all(&path)
+                .with_context(|| format!("Unable to remove archived copy {}", path.display()))?;
+        }
+        let _lock = FileLock::acquire(&self.manifest_path())?;
+        let mut manifest = self.read_manifest()?;
+        manifest
+            .entries
+            .retain(|candidate| candidate.name != entry.name);
+        self.write_manifest(&manifest)
+    }
+
+    // Drops manifest entries whose archived copy was deleted by hand.
+    pub fn drop_missing(&self) -> Result<Vec<ArchiveEntry>> {
+        if self
+            .entries()?
+            .iter()
+            .all(|entry| self.entry_path(entry).exists())
+        {
+            return Ok(Vec::new());
+        }
+        let _lock = FileLock::acquire(&self.manifest_path())?;
+        let mut manifest = self.read_manifest()?;
+        let (kept, missing) = std::mem::take(&mut manifest.entries)
+            .into_iter()
+            .partition(|entry| self.entry_path(entry).exists());
+        manifest.entries = kept;
+        self.write_manifest(&manifest)?;
+        Ok(missing)
+    }
+
+    // Keeps the notes of a worktree that is deleted rather than archived.
+    pub fn save_notes(&self, worktree_path: &Path, notes: &str) -> Result<PathBuf> {
+        let dir = self.dir.join(NOTES_DIR);
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Unable to create notes directory {}", dir.display()))?;
+        let slug = worktree_path
+            .file_name()
+            .ok_or_else(|| anyhow!("Invalid worktree path {}", worktree_path.display()))?
+            .to_string_lossy();
+        let path = dir.join(format!("{}-{}.md", slug, now_secs()));
+        fs::write(&path, notes)
+            .with_context(|| format!("Unable to write notes {}", path.display()))?;
+        Ok(path)
+    }
+
+    pub fn expired(&self, retention_days: u64) -> Result<Vec<ArchiveEntry>> {
+        let cutoff = now_secs().saturating_sub(retention_days * 86_400);
+        Ok(self
+            .entries()?
+            .into_iter()
+            .filter(|entry| entry.archived_at <= cutoff)
+            .collect())
+    }
+
+    fn manifest_path(&self) -> PathBuf {
+        self.dir.join(MANIFEST_FILENAME)
+    }
+
+    fn read_manifest(&self) -> Result<Manifest> {
+        read_state(&self.manifest_path(), "Archive manifest")
+    }
+
+    fn write_manifest(&self, manifest: &Manifest) -> Result<()> {
+        let path = self.manifest_path();
+        let body = toml::to_string_pretty(manifest)?;
+        write_atomic(&path, body)
+            .with_context(|| format!("Unable to write archive manifest {}", path.display()))
+    }
+}
+
+// Copies every file except the `.git` pointer, which belongs to the new worktree.
+pub fn restore_files(archived: &Path, worktree_path: &Path) -> Result<()> {
+    for entry in fs::read_dir(archived)
+        .with_context(|| format!("Unable to read archived copy {}", archived.display()))?
+    {
+        let entry = entry?;
+        if entry.file_name() == ".git" {
+            continue;
+        }
+        copy_recursive(&entry.path(), &worktree_path.join(entry.file_name()))?;
+    }
+    Ok(())
+}
+
+pub fn format_age(archived_at: u64) -> String {
+    let days = now_secs().saturating_sub(archived_at) / 86_400;
+    match days {
+        0 => "today".to_string(),
+        1 => "1 day ago".to_string(),
+        n => format!("{} days ago", n),
+    }
+}
+
+fn move_dir(from: &Path, to: &Path) -> Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    // Renaming fails across filesystems; fall back to copy + delete.
+    copy_recursive(from, to)?;
+    fs::remove_dir_all(from)
+        .with_context(|| format!("Unable to remove {} after archiving", from.display()))
+}
+
+fn copy_recursive(from: &Path, to: &Path) -> Result<()> {
+    let file_type = fs::symlink_metadata(from)?.file_type();
+    if file_type.is_dir() {
+        fs::create_dir_all(to)
+            .with_context(|| format!("Unable to create directory {}", to.display()))?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_recursive(&entry.path(), &to.join(entry.file_name()))?;
+        }
+    } else if file_type.is_symlink() {
+        fs_caps::link_or_copy(from, to)?;
+    } else {
+        fs::copy(from, to)
+            .with_context(|| format!("Unable to copy {} to {}", from.display(), to.display()))?;
+    }
+    Ok(())
+}
+
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    #[test]
+    fn drop_missing_forgets_entries_whose_copy_was_deleted() {
+        let tmp = TempDir::new("archive-drop-missing");
+        let base = tmp.path().join("worktrees");
+        let archive = Archive::new(&base);
+        for name in ["kept", "deleted"] {
+            fs::create_dir_all(base.join(name)).unwrap();
+            fs::write(base.join(name).join("notes.txt"), name).unwrap();
+        }
+        let kept = archive
+            .store(&base.join("kept"), Some("agent/kept"))
+            .unwrap();
+        let deleted = archive.store(&base.join("deleted"), None).unwrap();
+        fs::remove_dir_all(archive.entry_path(&deleted)).unwrap();
+
+        let dropped = archive.drop_missing().unwrap();
+
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].name, deleted.name);
+        let remaining = archive.entries().unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].name, kept.name);
+        assert!(archive.drop_missing().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,143 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::config::ConfigState;
+use crate::git::GitBackend;
+use crate::metadata::WorktreeMetadata;
+use crate::picker;
+use crate::templates;
+use crate::ui;
+
+const SECTION_MARKER: &str = "<!-- agent-manager: attached files -->";
+const MAX_FILE_BYTES: u64 = 64 * 1024;
+const BINARY_SNIFF_BYTES: usize = 8_000;
+
+pub fn attached_files(worktree: &Path) -> Result<Vec<String>> {
+    Ok(WorktreeMetadata::load(worktree)?.attachments)
+}
+
+pub fn choose_and_attach(
+    repo: &dyn GitBackend,
+    cfg: &ConfigState,
+    worktree: &Path,
+    template: &Path,
+) -> Result<()> {
+    let items = repo
+        .tracked_files(worktree)?
+        .into_iter()
+        .map(|file| (file.clone(), file))
+        .collect::<Vec<_>>();
+    let files = picker::Picker::new("Attach (Tab to mark)> ")
+        .items(items)
+        .pick_many()?;
+    if files.is_empty() {
+        ui::notice!("No file selected, nothing attached.");
+        return Ok(());
+    }
+    attach(cfg, worktree, template, &files)
+}
+
+// Replaces any previous attachment section with the current content of `files`.
+pub fn attach(cfg: &ConfigState, worktree: &Path, template: &Path, files: &[String]) -> Result<()> {
+    let content = templates::read_template(cfg, template)?;
+    let (body, _) = split_section(&content);
+
+    let budget = cfg.config.template_token_warning.saturating_mul(4);
+    let mut used = body.len();
+    let mut section = String::new();
+    let mut attached = Vec::new();
+    for file in files {
+        let roots = [worktree.to_path_buf()];
+        let bytes = match templates::read_bounded(&worktree.join(file), MAX_FILE_BYTES, &roots) {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                ui::warning!("Skipping {}: {:#}", file, err);
+                continue;
+            }
+        };
+        if bytes.iter().take(BINARY_SNIFF_BYTES).any(|byte| *byte == 0) {
+            ui::warning!("Skipping {}: binary files cannot be attached", file);
+            continue;
+        }
+        let block = code_block(file, &String::from_utf8_lossy(&bytes));
+        if budget > 0 && used + block.len() > budget {
+            ui::warning!(
+                "Skipping {}: it would push the template over the {} token warning threshold",
+                file,
+                cfg.config.template_token_warning
+            );
+            continue;
+        }
+        used += block.len();
+        section.push_str(&block);
+        attached.push(file.clone());
+    }
+
+    let mut rendered = body.to_string();
+    if !attached.is_empty() {
+        if !rendered.ends_with('\n') {
+            rendered.push('\n');
+        }
+        rendered.push('\n');
+        rendered.push_str(SECTION_MARKER);
+        rendered.push('\n');
+        rendered.push_str(&section);
+    }
+    fs::write(template, rendered)
+        .with_context(|| format!("Unable to update template {}", template.display()))?;
+    WorktreeMetadata::update(worktree, |metadata| metadata.attachments = attached.clone())?;
+
+    if !attached.is_empty() {
+        ui::success!("Attached {} file(s) to the template", attached.len());
+    }
+    Ok(())
+}
+
+// Removes the attachment section from the template and returns it, so it can be put back verbatim.
+pub fn detach(cfg: &ConfigState, template: &Path) -> Result<Option<String>> {
+    let content = templates::read_template(cfg, template)?;
+    let (body, section) = split_section(&content);
+    let Some(section) = section else {
+        return Ok(None);
+    };
+    let section = section.to_string();
+    fs::write(template, body)
+        .with_context(|| format!("Unable to update template {}", template.display()))?;
+    Ok(Some(section))
+}
+
+pub fn reattach(cfg: &ConfigState, template: &Path, section: &str) -> Result<()> {
+    let mut content = templates::read_template(cfg, template)?;
+    content.push_str(section);
+    fs::write(template, content)
+        .with_context(|| format!("Unable to update template {}", template.display()))
+}
+
+fn split_section(content: &str) -> (&str, Option<&str>) {
+    match content.find(SECTION_MARKER) {
+        Some(start) => {
+            let body = content[..start].trim_end_matches('\n');
+            (body, Some(&content[body.len()..]))
+        }
+        None => (content, None),
+    }
+}
+
+fn code_block(file: &str, content: &str) -> String {
+    // Pick a fence longer than any backtick run inside the file.
+    let longest_run = content
+        .split(|ch| ch != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or(0);
+    let fence = "`".repeat(longest_run.max(2) + 1);
+    let mut block = format!("\n### {}\n\n{}\n{}", file, fence, content);
+    if !content.ends_with('\n') {
+        block.push('\n');
+    }
+    block.push_str(&fence);
+    block.push('\n');
+    block
+}
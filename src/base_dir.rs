@@ -0,0 +1,57 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::config::glob_match;
+use crate::git::Worktree;
+
+pub const MARKER_FILENAME: &str = ".agent-manager-base";
+
+// Records that AgentManager created the base directory, so it may remove it again later.
+pub fn mark_managed(base: &Path) -> Result<()> {
+    let marker = base.join(MARKER_FILENAME);
+    if marker.exists() {
+        return Ok(());
+    }
+    fs::write(
+        &marker,
+        "Created by AgentManager; the directory may be removed once empty.\n",
+    )
+    .with_context(|| format!("Unable to write base marker {}", marker.display()))
+}
+
+pub fn is_managed(base: &Path, default_base: &Path) -> bool {
+    base == default_base || base.join(MARKER_FILENAME).is_file()
+}
+
+// Entries that would be deleted with the base directory, or `None` when it still holds
+// a worktree or anything not covered by `ignorable` (the marker file is always ignorable).
+pub fn removable_leftovers(
+    base: &Path,
+    worktrees: &[Worktree],
+    ignorable: &[String],
+) -> Result<Option<Vec<PathBuf>>> {
+    if worktrees
+        .iter()
+        .any(|worktree| worktree.path.starts_with(base))
+    {
+        return Ok(None);
+    }
+
+    let mut leftovers = Vec::new();
+    for entry in fs::read_dir(base)
+        .with_context(|| format!("Unable to read worktree base {}", base.display()))?
+    {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let ignorable =
+            name == MARKER_FILENAME || ignorable.iter().any(|pattern| glob_match(pattern, &name));
+        if !ignorable {
+            return Ok(None);
+        }
+        leftovers.push(entry.path());
+    }
+    leftovers.sort();
+    Ok(Some(leftovers))
+}
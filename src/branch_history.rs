@@ -0,0 +1,124 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{self, ConfigState};
+use crate::lock::{FileLock, read_state, write_atomic};
+use crate::ui::MruList;
+
+const HISTORY_DIR: &str = "branch-history";
+const HISTORY_LEN: usize = 8;
+
+#[derive(Debug, Clone, Copy)]
+pub enum BranchPrompt {
+    Base,
+    MergeTarget,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct HistoryFile {
+    // Newest first.
+    base_branches: Vec<String>,
+    merge_targets: Vec<String>,
+}
+
+impl HistoryFile {
+    fn values(&mut self, prompt: BranchPrompt) -> &mut Vec<String> {
+        match prompt {
+            BranchPrompt::Base => &mut self.base_branches,
+            BranchPrompt::MergeTarget => &mut self.merge_targets,
+        }
+    }
+}
+
+// Branch names accepted in the base branch and merge target prompts, persisted per repository
+// under the config dir.
+pub struct BranchHistory {
+    path: PathBuf,
+}
+
+impl BranchHistory {
+    // None when the configuration directory cannot be written.
+    pub fn for_repo(cfg: &ConfigState, repo_root: &Path) -> Option<Self> {
+        if !cfg.persistence.is_writable() {
+            return None;
+        }
+        let config_dir = cfg.config_file.parent()?;
+        Some(Self {
+            path: config_dir
+                .join(HISTORY_DIR)
+                .join(format!("{}.toml", config::repo_key(repo_root))),
+        })
+    }
+
+    // Entries for branches `exists` no longer finds are dropped.
+    pub fn recent(&self, prompt: BranchPrompt, exists: impl Fn(&str) -> bool) -> Result<MruList> {
+        let mut list = MruList::new(self.read()?.values(prompt).clone(), HISTORY_LEN);
+        if list.retain(|branch| exists(branch)) {
+            let entries = list.entries().to_vec();
+            self.update(|file| *file.values(prompt) = entries)?;
+        }
+        Ok(list)
+    }
+
+    pub fn record(&self, prompt: BranchPrompt, branch: &str) -> Result<()> {
+        self.update(|file| {
+            let mut list = MruList::new(file.values(prompt).clone(), HISTORY_LEN);
+            list.record(branch);
+            *file.values(prompt) = list.entries().to_vec();
+        })
+    }
+
+    fn update(&self, change: impl FnOnce(&mut HistoryFile)) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Unable to create directory {}", dir.display()))?;
+        }
+        let _lock = FileLock::acquire(&self.path)?;
+        let mut file = self.read()?;
+        change(&mut file);
+        write_atomic(&self.path, toml::to_string_pretty(&file)?)
+            .with_context(|| format!("Unable to write branch history {}", self.path.display()))
+    }
+
+    fn read(&self) -> Result<HistoryFile> {
+        read_state(&self.path, "Branch history")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    #[test]
+    fn history_keeps_recent_branches_per_prompt_and_prunes_missing_ones() {
+        let tmp = TempDir::new("branch-history");
+        let cfg = ConfigState::load_from(&tmp.path().join("config")).unwrap();
+        let history = BranchHistory::for_repo(&cfg, &tmp.path().join("repo")).unwrap();
+
+        for branch in ["release/1", "release/2", "release/1"] {
+            history.record(BranchPrompt::Base, branch).unwrap();
+        }
+        history.record(BranchPrompt::MergeTarget, "main").unwrap();
+
+        let recent = history.recent(BranchPrompt::Base, |_| true).unwrap();
+        assert_eq!(recent.entries(), ["release/1", "release/2"]);
+        let recent = history
+            .recent(BranchPrompt::Base, |branch| branch != "release/2")
+            .unwrap();
+        assert_eq!(recent.entries(), ["release/1"]);
+
+        // The pruning is written back, and the other prompt is untouched.
+        let reopened = BranchHistory::for_repo(&cfg, &tmp.path().join("repo")).unwrap();
+        let recent = reopened.recent(BranchPrompt::Base, |_| true).unwrap();
+        assert_eq!(recent.entries(), ["release/1"]);
+        let recent = reopened
+            .recent(BranchPrompt::MergeTarget, |_| true)
+            .unwrap();
+        assert_eq!(recent.entries(), ["main"]);
+    }
+}
@@ -0,0 +1,132 @@
+// Inserts `entry` as the first line under `heading`. Returns None when the exact entry is
+// already present. A missing heading is created right after the first H1 (or at the top).
+pub fn insert_entry(content: &str, heading: &str, entry: &str) -> Option<String> {
+    let entry = entry.trim_end();
+    let lines = content.lines().collect::<Vec<_>>();
+    if lines.iter().any(|line| line.trim_end() == entry) {
+        return None;
+    }
+
+    let mut out: Vec<&str> = Vec::with_capacity(lines.len() + 4);
+    let rest = match lines.iter().position(|line| line.trim() == heading) {
+        Some(idx) => {
+            out.extend(&lines[..=idx]);
+            &lines[idx + 1..]
+        }
+        None => {
+            let after_title = lines
+                .iter()
+                .position(|line| line.starts_with("# "))
+                .map(|idx| idx + 1)
+                .unwrap_or(0);
+            out.extend(&lines[..after_title]);
+            if !out.is_empty() {
+                out.push("");
+            }
+            out.push(heading);
+            &lines[after_title..]
+        }
+    };
+    let rest = match rest.iter().position(|line| !line.trim().is_empty()) {
+        Some(first) => &rest[first..],
+        None => &[],
+    };
+
+    out.push("");
+    out.push(entry);
+    // Keep a blank line before whatever follows unless it continues the same list.
+    if let Some(next) = rest.first()
+        && !(next.starts_with("- ") || next.starts_with("* "))
+    {
+        out.push("");
+    }
+    out.extend(rest);
+
+    let mut updated = out.join("\n");
+    updated.push('\n');
+    Some(updated)
+}
+
+pub fn render_entry(template: &str, feature: &str, branch: &str, date: &str) -> String {
+    template
+        .replace("{feature}", feature)
+        .replace("{branch}", branch)
+        .replace("{date}", date)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADING: &str = "## Unreleased";
+    const ENTRY: &str = "- Login form";
+
+    #[test]
+    fn creates_the_heading_in_an_empty_file() {
+        assert_eq!(
+            insert_entry("", HEADING, ENTRY).unwrap(),
+            "## Unreleased\n\n- Login form\n"
+        );
+    }
+
+    #[test]
+    fn prepends_to_an_existing_list() {
+        let content = "# Changelog\n\n## Unreleased\n\n- Old\n\n## 1.0\n\n- First\n";
+        assert_eq!(
+            insert_entry(content, HEADING, ENTRY).unwrap(),
+            "# Changelog\n\n## Unreleased\n\n- Login form\n- Old\n\n## 1.0\n\n- First\n"
+        );
+    }
+
+    #[test]
+    fn continues_a_star_list_without_a_trailing_newline() {
+        assert_eq!(
+            insert_entry("## Unreleased\n* Old", HEADING, ENTRY).unwrap(),
+            "## Unreleased\n\n- Login form\n* Old\n"
+        );
+    }
+
+    #[test]
+    fn adds_the_heading_after_the_title() {
+        let content = "# Changelog\n\n## 1.0\n\n- First\n";
+        assert_eq!(
+            insert_entry(content, HEADING, ENTRY).unwrap(),
+            "# Changelog\n\n## Unreleased\n\n- Login form\n\n## 1.0\n\n- First\n"
+        );
+    }
+
+    #[test]
+    fn adds_the_heading_at_the_top_without_a_title() {
+        assert_eq!(
+            insert_entry("Some notes\n", HEADING, ENTRY).unwrap(),
+            "## Unreleased\n\n- Login form\n\nSome notes\n"
+        );
+    }
+
+    #[test]
+    fn separates_the_entry_from_a_following_paragraph() {
+        assert_eq!(
+            insert_entry("## Unreleased\nNothing yet.\n", HEADING, ENTRY).unwrap(),
+            "## Unreleased\n\n- Login form\n\nNothing yet.\n"
+        );
+    }
+
+    #[test]
+    fn skips_an_entry_already_present() {
+        let content = "## Unreleased\n\n- Login form  \n";
+        assert_eq!(insert_entry(content, HEADING, "- Login form\n"), None);
+    }
+
+    #[test]
+    fn renders_the_placeholders() {
+        assert_eq!(
+            render_entry(
+                "- {feature} ({branch}, {date})",
+                "Login",
+                "agent/login",
+                "2024-05-01"
+            ),
+            "- Login (agent/login, 2024-05-01)"
+        );
+    }
+}
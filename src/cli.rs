@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use anyhow::{Result, anyhow};
+
+use crate::config::Verbosity;
+
+pub const USAGE: &str = "Usage: AgentManager [--var NAME=VALUE]...
+       AgentManager presets
+
+Commands:
+  presets           List the built-in agent presets (for agent_preset) and what they expand to
+
+Options:
+  --var NAME=VALUE  Supply a template variable (overrides presets and prompts)
+  --answers FILE    Read template variable values from a TOML or JSON file (--var wins)
+  --template NAME   Use the template whose file name is or contains NAME for new features
+  -q, --quiet       Only print warnings, errors and prompts
+  -v, --verbose     Also log every external command (on stderr)
+  --dashboard       Open the worktree dashboard instead of the menu at startup
+  --once            Exit after the first action instead of returning to the menu
+  --no-hooks        Skip git hooks for commits and merges inside agent worktrees
+  --machine-errors  On failure, also print a JSON object with error_kind, exit_code and detail
+                    as the last line on stderr
+  -h, --help        Print this help
+
+Exit codes: 0 success, 1 other error, 2 aborted by the user, 3 git failure, 4 agent failure,
+5 configuration error, 6 merge conflict";
+
+#[derive(Debug, Default)]
+pub struct CliArgs {
+    pub variables: HashMap<String, String>,
+    pub answers: Option<PathBuf>,
+    pub template: Option<String>,
+    pub help: bool,
+    pub verbosity: Option<Verbosity>,
+    pub bypass_hooks: bool,
+    pub dashboard: bool,
+    pub once: bool,
+    pub machine_errors: bool,
+    pub list_presets: bool,
+}
+
+impl CliArgs {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut parsed = CliArgs::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => parsed.help = true,
+                "-q" | "--quiet" => parsed.verbosity = Some(Verbosity::Quiet),
+                "-v" | "--verbose" => parsed.verbosity = Some(Verbosity::Verbose),
+                "--no-hooks" => parsed.bypass_hooks = true,
+                "--dashboard" => parsed.dashboard = true,
+                "--once" => parsed.once = true,
+                "--machine-errors" => parsed.machine_errors = true,
+                "presets" => parsed.list_presets = true,
+                "--var" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| anyhow!("--var expects NAME=VALUE"))?;
+                    parsed.insert_variable(&value)?;
+                }
+                "--template" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| anyhow!("--template expects a template name"))?;
+                    parsed.template = Some(value);
+                }
+                "--answers" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| anyhow!("--answers expects a file path"))?;
+                    parsed.answers = Some(PathBuf::from(value));
+                }
+                other => {
+                    if let Some(value) = other.strip_prefix("--answers=") {
+                        parsed.answers = Some(PathBuf::from(value));
+                    } else if let Some(value) = other.strip_prefix("--template=") {
+                        parsed.template = Some(value.to_string());
+                    } else if let Some(value) = other.strip_prefix("--var=") {
+                        parsed.insert_variable(value)?;
+                    } else {
+                        return Err(anyhow!("Unknown argument {}\n\n{}", other, USAGE));
+                    }
+                }
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn insert_variable(&mut self, spec: &str) -> Result<()> {
+        let (name, value) = spec
+            .split_once('=')
+            .ok_or_else(|| anyhow!("--var expects NAME=VALUE, got {}", spec))?;
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(anyhow!("--var expects NAME=VALUE, got {}", spec));
+        }
+        self.variables.insert(name.to_string(), value.to_string());
+        Ok(())
+    }
+}
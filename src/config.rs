@@ -1,82 +1,486 @@
+use std::cell::OnceCell;
+use std::collections::BTreeMap;
+use std::fmt;
 use std::fs::{self, File};
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
 
 use anyhow::{Context, Result};
 use directories::ProjectDirs;
 use serde::{Deserialize, Serialize};
 
+use crate::lock::{FileLock, write_atomic};
+use crate::persistence::{self, Persistence};
+use crate::presets::{self, AgentPreset};
+use crate::ui;
+
+pub const PROJECT_CONFIG_FILENAME: &str = ".agent-manager.toml";
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(default)]
 pub struct Config {
+    // Name of a built-in preset (see `presets::PRESETS`) filling the agent fields below that the
+    // file leaves out.
+    pub agent_preset: Option<String>,
     pub agent_command: String,
     pub agent_args: Vec<String>,
+    pub agent_input: AgentInput,
+    pub agent_interactive: bool,
+    // Show the resolved command and ask before every agent launch.
+    pub confirm_agent_launch: bool,
+    // Optional limits for the agent process: niceness, CPU share (needs `cpulimit`) and
+    // address-space cap.
+    pub agent_nice: Option<i32>,
+    pub agent_cpu_limit_percent: Option<u32>,
+    pub agent_max_memory_mb: Option<u64>,
+    pub review_template: Option<String>,
+    pub review_agent_command: Option<String>,
+    pub review_agent_args: Option<Vec<String>>,
     pub merge_target: String,
+    // Branch new worktrees are cut from when it differs from `merge_target`.
+    pub default_base_branch: Option<String>,
+    pub merge_queue_strategy: MergeStrategy,
+    pub merge_review: bool,
+    pub merge_review_command: Option<String>,
+    pub changelog_file: Option<String>,
+    pub changelog_heading: String,
+    pub changelog_entry_template: String,
+    pub protected_branches: Vec<String>,
+    pub commit_denylist: Vec<String>,
+    pub default_answers_file: Option<PathBuf>,
+    pub answers_secret_keys: Vec<String>,
+    pub secret_patterns: Vec<String>,
+    pub agent_branch_pattern: String,
     pub template_editor: String,
     pub agent_display_name: String,
     pub worktree_base_override: Option<String>,
+    pub worktree_no_checkout: bool,
+    pub max_active_worktrees: Option<usize>,
+    pub recent_worktree_shortcuts: usize,
+    // Leave after the first menu action, e.g. when run in a terminal popup (`--once`).
+    pub exit_after_action: bool,
+    pub ignorable_leftovers: Vec<String>,
+    // Pathspecs (relative to the worktree root) whose changes do not make a worktree dirty, such
+    // as an agent's scratch directory.
+    pub dirty_ignore_paths: Vec<String>,
+    pub sparse_checkout_paths: Vec<String>,
+    pub init_submodules: Option<bool>,
+    pub clipboard_command: Option<String>,
+    pub template_env_passthrough: Vec<String>,
+    pub show_shadowed_templates: bool,
+    pub template_repos: Vec<String>,
+    pub instructions_filename: Option<String>,
+    pub instructions_sources: Vec<PathBuf>,
+    pub commit_instructions: bool,
+    pub auto_update_before_start: AutoUpdate,
+    pub fetch_on_startup: FetchOnStartup,
+    pub archive_retention_days: u64,
+    pub cleanup_policy: CleanupPolicy,
+    pub template_token_warning: usize,
+    pub template_max_bytes: u64,
+    pub token_estimate_command: Option<String>,
+    // Validator run on the rendered template before launch; a template's `post_render_command`
+    // front-matter key takes precedence.
+    pub post_render_command: Option<String>,
+    pub post_render_timeout_secs: u64,
+    pub picker_path_width: usize,
+    pub group_worktrees_by_prefix: bool,
+    pub worktree_group_delimiter: String,
+    pub diff_pager_command: Option<String>,
+    pub pr_status_command: Option<String>,
+    pub git_network_timeout_secs: Option<u64>,
+    pub git_command_prefix: Vec<String>,
+    pub git_path_map: BTreeMap<String, String>,
+    pub verbosity: Verbosity,
+    pub bypass_hooks_in_worktrees: bool,
+    pub git_identity: GitIdentity,
+    // Warn before starting an agent on a branch whose unpushed commits have another author.
+    pub warn_foreign_commits: bool,
+    pub task_types: BTreeMap<String, TaskType>,
+}
+
+// Per-task-type agent flags, e.g. `[task_types.bugfix] agent_args = ["--careful"]`.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TaskType {
+    pub agent_args: Vec<String>,
+    // Template file name listed first when picking a template.
+    pub template: Option<String>,
+    pub branch_prefix: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GitIdentity {
+    pub author_name: Option<String>,
+    pub author_email: Option<String>,
+}
+
+// Settings a repository pins for everyone working in it, read from `.agent-manager.toml` at its
+// root. Each key set here overrides the user configuration.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProjectConfig {
+    pub default_base_branch: Option<String>,
+}
+
+impl ProjectConfig {
+    pub fn load(project_root: &Path) -> Result<Self> {
+        let path = project_root.join(PROJECT_CONFIG_FILENAME);
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => {
+                return Err(err).with_context(|| format!("Unable to read {}", path.display()));
+            }
+        };
+        toml::from_str(&content)
+            .with_context(|| format!("Invalid project configuration in {}", path.display()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AgentInput {
+    #[default]
+    Arg,
+    Stdin,
+    None,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CleanupPolicy {
+    #[default]
+    Prompt,
+    Keep,
+    AutoOnStartup,
+    AutoOnStartupSilent,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verbosity {
+    Quiet,
+    #[default]
+    Normal,
+    Verbose,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeStrategy {
+    #[default]
+    Rebase,
+    Merge,
+}
+
+impl fmt::Display for MergeStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MergeStrategy::Rebase => "rebase",
+            MergeStrategy::Merge => "merge",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AutoUpdate {
+    #[default]
+    Ask,
+    Rebase,
+    Merge,
+    Never,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FetchOnStartup {
+    #[default]
+    Never,
+    Daily,
+    Always,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
+            agent_preset: None,
             agent_command: "codex".to_string(),
             agent_args: vec!["{template_content}".to_string()],
+            agent_input: AgentInput::default(),
+            agent_interactive: false,
+            confirm_agent_launch: true,
+            agent_nice: None,
+            agent_cpu_limit_percent: None,
+            agent_max_memory_mb: None,
+            review_template: None,
+            review_agent_command: None,
+            review_agent_args: None,
             merge_target: "main".to_string(),
+            default_base_branch: None,
+            merge_queue_strategy: MergeStrategy::default(),
+            merge_review: false,
+            merge_review_command: None,
+            changelog_file: None,
+            changelog_heading: "## Unreleased".to_string(),
+            changelog_entry_template: "- {feature} ({branch})".to_string(),
+            protected_branches: vec![
+                "main".to_string(),
+                "master".to_string(),
+                "develop".to_string(),
+            ],
+            commit_denylist: vec![
+                "*.pem".to_string(),
+                "*.key".to_string(),
+                ".env*".to_string(),
+                "id_rsa*".to_string(),
+            ],
+            default_answers_file: None,
+            answers_secret_keys: vec![
+                "*token*".to_string(),
+                "*secret*".to_string(),
+                "*password*".to_string(),
+                "*passwd*".to_string(),
+                "*api_key*".to_string(),
+                "*apikey*".to_string(),
+                "*credential*".to_string(),
+            ],
+            secret_patterns: vec![
+                r"\b(AKIA|ASIA)[0-9A-Z]{16}\b".to_string(),
+                r"-----BEGIN ([A-Z]+ )?PRIVATE KEY-----".to_string(),
+            ],
+            agent_branch_pattern: "agent/*".to_string(),
             template_editor: "vim".to_string(),
             agent_display_name: "Codex".to_string(),
             worktree_base_override: None,
+            worktree_no_checkout: false,
+            max_active_worktrees: None,
+            recent_worktree_shortcuts: 3,
+            exit_after_action: false,
+            ignorable_leftovers: vec![
+                ".DS_Store".to_string(),
+                "Thumbs.db".to_string(),
+                "desktop.ini".to_string(),
+                "*.log".to_string(),
+            ],
+            dirty_ignore_paths: Vec::new(),
+            sparse_checkout_paths: Vec::new(),
+            init_submodules: None,
+            clipboard_command: None,
+            template_env_passthrough: Vec::new(),
+            show_shadowed_templates: false,
+            template_repos: Vec::new(),
+            instructions_filename: None,
+            instructions_sources: Vec::new(),
+            commit_instructions: false,
+            auto_update_before_start: AutoUpdate::default(),
+            fetch_on_startup: FetchOnStartup::default(),
+            archive_retention_days: 30,
+            cleanup_policy: CleanupPolicy::default(),
+            template_token_warning: 32_000,
+            template_max_bytes: 1024 * 1024,
+            token_estimate_command: None,
+            post_render_command: None,
+            post_render_timeout_secs: 60,
+            picker_path_width: 60,
+            group_worktrees_by_prefix: false,
+            worktree_group_delimiter: "/".to_string(),
+            diff_pager_command: None,
+            pr_status_command: None,
+            git_network_timeout_secs: None,
+            git_command_prefix: Vec::new(),
+            git_path_map: BTreeMap::new(),
+            verbosity: Verbosity::default(),
+            bypass_hooks_in_worktrees: false,
+            git_identity: GitIdentity::default(),
+            warn_foreign_commits: true,
+            task_types: BTreeMap::new(),
         }
     }
 }
 
+impl Config {
+    // Unset means "initialize whenever the worktree declares submodules".
+    pub fn should_init_submodules(&self, worktree_path: &Path) -> bool {
+        self.init_submodules
+            .unwrap_or_else(|| crate::git::has_submodules(worktree_path))
+    }
+
+    // The project's `default_base_branch` wins over the user's; both fall back to `merge_target`.
+    pub fn base_branch<'a>(&'a self, project: &'a ProjectConfig) -> &'a str {
+        [&project.default_base_branch, &self.default_base_branch]
+            .into_iter()
+            .filter_map(|branch| branch.as_deref().map(str::trim))
+            .find(|branch| !branch.is_empty())
+            .unwrap_or(&self.merge_target)
+    }
+
+    pub fn protected_pattern(&self, branch: &str) -> Option<&str> {
+        self.protected_branches
+            .iter()
+            .find(|pattern| glob_match(pattern, branch))
+            .map(|pattern| pattern.as_str())
+    }
+}
+
+// File-name-safe key for per-repository state files under the config dir.
+pub fn repo_key(repo_root: &Path) -> String {
+    repo_root
+        .to_string_lossy()
+        .chars()
+        .map(|ch| if ch.is_ascii_alphanumeric() { ch } else { '-' })
+        .collect::<String>()
+        .trim_matches('-')
+        .to_string()
+}
+
+// Minimal glob matcher supporting `*` (any run of characters) and `?` (one character).
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            t = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|ch| *ch == '*')
+}
+
 pub struct ConfigState {
     pub config: Config,
-    pub templates_dir: PathBuf,
+    pub config_file: PathBuf,
+    pub persistence: Persistence,
+    // The config file was created by this run.
+    pub first_run: bool,
+    templates_dir: PathBuf,
+    templates_ready: OnceCell<()>,
 }
 
 impl ConfigState {
+    // Only reads (or seeds) the config file; the templates directory is prepared on first use.
+    // A read-only or full configuration directory falls back to the defaults instead of failing.
     pub fn load() -> Result<Self> {
-        let project_dirs = ProjectDirs::from("dev", "AgentManager", "AgentManager")
-            .context("Unable to locate the user configuration directory")?;
-        let config_dir = project_dirs.config_dir();
-        fs::create_dir_all(config_dir).with_context(|| {
-            format!("Unable to create configuration directory {:?}", config_dir)
-        })?;
+        let config_dir = match std::env::var_os(persistence::CONFIG_DIR_ENV) {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => ProjectDirs::from("dev", "AgentManager", "AgentManager")
+                .context("Unable to locate the user configuration directory")?
+                .config_dir()
+                .to_path_buf(),
+        };
+        Self::load_from(&config_dir)
+    }
 
+    // Reads (or seeds) `config.toml` in `config_dir` instead of the user configuration directory.
+    pub fn load_from(config_dir: &Path) -> Result<Self> {
         let config_file = config_dir.join("config.toml");
-        let config = if config_file.exists() {
-            let mut buf = String::new();
-            File::open(&config_file)?.read_to_string(&mut buf)?;
-            if buf.trim().is_empty() {
-                Config::default()
-            } else {
-                toml::from_str(&buf).context("Configuration file is invalid")?
-            }
+        let first_run = !config_file.exists();
+        let (config, persistence) = if !first_run {
+            (read_config(&config_file)?, Persistence::probe(config_dir)?)
         } else {
-            let config = Config::default();
-            write_config(&config_file, &config)?;
-            config
+            match seed_config(&config_file) {
+                Ok(config) => (config, Persistence::Writable),
+                Err(err) => (Config::default(), Persistence::degrade(config_dir, err)?),
+            }
         };
 
-        let templates_dir = config_dir.join("templates");
-        fs::create_dir_all(&templates_dir)
-            .with_context(|| format!("Unable to create templates directory {:?}", templates_dir))?;
-
-        ensure_default_template(&templates_dir)?;
-
         Ok(Self {
             config,
-            templates_dir,
+            config_file,
+            persistence,
+            first_run,
+            templates_dir: config_dir.join("templates"),
+            templates_ready: OnceCell::new(),
         })
     }
+
+    // Switches the configuration file to `preset`: the agent fields are removed from it so the
+    // preset provides them, and the in-memory configuration follows.
+    pub fn use_preset(&mut self, preset: &AgentPreset) -> Result<()> {
+        let _lock = FileLock::acquire(&self.config_file)?;
+        let mut table = match fs::read_to_string(&self.config_file) {
+            Ok(content) => {
+                toml::from_str::<toml::Table>(&content).context("Configuration file is invalid")?
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => toml::Table::new(),
+            Err(err) => return Err(err.into()),
+        };
+        for (key, _) in preset.values() {
+            table.remove(key);
+        }
+        table.insert("agent_preset".to_string(), preset.name.into());
+        write_atomic(&self.config_file, toml::to_string_pretty(&table)?)?;
+
+        preset.apply(&mut table);
+        self.config = table.try_into().context("Configuration file is invalid")?;
+        Ok(())
+    }
+
+    // Creates the directory and the default template the first time templates are needed.
+    pub fn templates_dir(&self) -> &Path {
+        self.templates_ready.get_or_init(|| {
+            if !self.persistence.is_writable() {
+                return;
+            }
+            if let Err(err) = ensure_dir(&self.templates_dir)
+                .and_then(|()| ensure_default_template(&self.templates_dir))
+            {
+                ui::warning!("Unable to prepare the templates directory: {:#}", err);
+            }
+        });
+        &self.templates_dir
+    }
+}
+
+// Writes the default configuration on first use.
+fn seed_config(config_file: &Path) -> Result<Config> {
+    if let Some(config_dir) = config_file.parent() {
+        ensure_dir(config_dir)?;
+    }
+    let config = Config::default();
+    write_config(config_file, &config)?;
+    Ok(config)
+}
+
+fn read_config(config_file: &Path) -> Result<Config> {
+    let mut buf = String::new();
+    File::open(config_file)?.read_to_string(&mut buf)?;
+    if buf.trim().is_empty() {
+        return Ok(Config::default());
+    }
+    let mut table: toml::Table = toml::from_str(&buf).context("Configuration file is invalid")?;
+    if let Some(name) = table.get("agent_preset").and_then(toml::Value::as_str) {
+        presets::find(name)?.apply(&mut table);
+    }
+    table.try_into().context("Configuration file is invalid")
+}
+
+// Stat first: creating an existing directory tree is slow on network home directories.
+fn ensure_dir(dir: &Path) -> Result<()> {
+    if dir.is_dir() {
+        return Ok(());
+    }
+    fs::create_dir_all(dir).with_context(|| format!("Unable to create directory {:?}", dir))
 }
 
 fn write_config(path: &Path, config: &Config) -> Result<()> {
     let body = toml::to_string_pretty(config)?;
-    let mut file = File::create(path)?;
-    file.write_all(body.as_bytes())?;
-    Ok(())
+    let _lock = FileLock::acquire(path)?;
+    write_atomic(path, body)
 }
 
 fn ensure_default_template(templates_dir: &Path) -> Result<()> {
@@ -94,3 +498,232 @@ ${feature}
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    #[test]
+    fn glob_match_table() {
+        let cases = [
+            ("main", "main", true),
+            ("main", "main2", false),
+            ("main", "mai", false),
+            ("release/*", "release/1.2", true),
+            ("release/*", "release/", true),
+            ("release/*", "release", false),
+            ("release/*", "hotfix/release/1.2", false),
+            // `*` also crosses `/`, unlike a path glob.
+            ("release/*", "release/1.x/rc", true),
+            ("*/wip", "agent/login/wip", true),
+            ("v?.?", "v1.2", true),
+            ("v?.?", "v1.22", false),
+            ("*", "", true),
+            ("", "", true),
+            ("", "main", false),
+            ("a*b*c", "aXXbYYc", true),
+            ("a*b*c", "aXXcYYb", false),
+            ("**", "anything", true),
+            ("caf\u{e9}-?", "caf\u{e9}-\u{4fee}", true),
+        ];
+        for (pattern, text, expected) in cases {
+            assert_eq!(
+                glob_match(pattern, text),
+                expected,
+                "{} vs {}",
+                pattern,
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn protected_pattern_names_the_first_matching_pattern() {
+        let mut config = Config::default();
+        assert_eq!(config.protected_pattern("main"), Some("main"));
+        assert_eq!(config.protected_pattern("develop"), Some("develop"));
+        assert_eq!(config.protected_pattern("agent/login"), None);
+
+        config.protected_branches = vec!["release/*".to_string(), "release/1.*".to_string()];
+        assert_eq!(config.protected_pattern("release/1.2"), Some("release/*"));
+        assert_eq!(config.protected_pattern("main"), None);
+    }
+
+    #[test]
+    fn default_template_is_created_on_first_templates_dir_access() {
+        let tmp = TempDir::new("config-lazy-templates");
+        let config_dir = tmp.path().join("config");
+
+        let cfg = ConfigState::load_from(&config_dir).unwrap();
+        assert!(cfg.first_run);
+        assert!(config_dir.join("config.toml").is_file());
+        let templates = config_dir.join("templates");
+        assert!(!templates.exists());
+
+        assert_eq!(cfg.templates_dir(), templates);
+        let default = fs::read_to_string(templates.join("default.md")).unwrap();
+        assert!(default.contains("${feature}"), "{}", default);
+    }
+
+    #[test]
+    fn an_edited_default_template_is_kept() {
+        let tmp = TempDir::new("config-kept-template");
+        let config_dir = tmp.path().join("config");
+        fs::create_dir_all(config_dir.join("templates")).unwrap();
+        fs::write(config_dir.join("templates/default.md"), "mine\n").unwrap();
+
+        let cfg = ConfigState::load_from(&config_dir).unwrap();
+        cfg.templates_dir();
+
+        assert_eq!(
+            fs::read_to_string(config_dir.join("templates/default.md")).unwrap(),
+            "mine\n"
+        );
+        let reloaded = ConfigState::load_from(&config_dir).unwrap();
+        assert!(!reloaded.first_run);
+    }
+
+    #[test]
+    fn base_branch_prefers_the_project_then_the_user_then_the_merge_target() {
+        let branch = |value: Option<&str>| value.map(str::to_string);
+        let cases = [
+            (Some("develop"), Some("next"), "develop"),
+            (None, Some("next"), "next"),
+            (None, None, "main"),
+            // Blank values count as unset at either level.
+            (Some("  "), Some("next"), "next"),
+            (Some(""), Some(" "), "main"),
+            (Some(" develop "), None, "develop"),
+        ];
+        for (project, user, expected) in cases {
+            let config = Config {
+                default_base_branch: branch(user),
+                ..Config::default()
+            };
+            let project_config = ProjectConfig {
+                default_base_branch: branch(project),
+            };
+            assert_eq!(
+                config.base_branch(&project_config),
+                expected,
+                "{:?}",
+                (project, user)
+            );
+        }
+    }
+
+    #[test]
+    fn project_config_is_optional_and_strict() {
+        let tmp = TempDir::new("project-config");
+        assert_eq!(
+            ProjectConfig::load(tmp.path()).unwrap().default_base_branch,
+            None
+        );
+
+        let path = tmp.path().join(PROJECT_CONFIG_FILENAME);
+        fs::write(&path, "default_base_branch = \"develop\"\n").unwrap();
+        assert_eq!(
+            ProjectConfig::load(tmp.path())
+                .unwrap()
+                .default_base_branch
+                .as_deref(),
+            Some("develop")
+        );
+
+        // A typo should not silently fall back to the user's base branch.
+        fs::write(&path, "default_base = \"develop\"\n").unwrap();
+        let err = format!("{:#}", ProjectConfig::load(tmp.path()).unwrap_err());
+        assert!(err.contains("Invalid project configuration"), "{}", err);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn load_from_a_read_only_dir_keeps_what_it_can_read() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = TempDir::new("config-read-only");
+        let existing = tmp.path().join("existing");
+        let empty = tmp.path().join("empty");
+        fs::create_dir(&existing).unwrap();
+        fs::create_dir(&empty).unwrap();
+        fs::write(existing.join("config.toml"), "merge_target = \"develop\"\n").unwrap();
+        for dir in [&existing, &empty] {
+            fs::set_permissions(dir, fs::Permissions::from_mode(0o555)).unwrap();
+        }
+        // Root ignores permission bits, so there is nothing to observe.
+        let ignored = fs::write(empty.join("root"), "").is_ok();
+        let loaded = (!ignored).then(|| {
+            (
+                ConfigState::load_from(&existing).unwrap(),
+                ConfigState::load_from(&empty).unwrap(),
+            )
+        });
+        for dir in [&existing, &empty] {
+            fs::set_permissions(dir, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        let Some((existing, empty)) = loaded else {
+            return;
+        };
+
+        assert_eq!(existing.config.merge_target, "develop");
+        assert!(!existing.first_run);
+        assert!(!existing.persistence.is_writable());
+        // A first run that cannot seed the file runs on the defaults.
+        assert_eq!(empty.config.merge_target, Config::default().merge_target);
+        assert!(empty.first_run);
+        assert!(!empty.persistence.is_writable());
+        assert!(!empty.config_file.exists());
+    }
+
+    #[test]
+    fn load_expands_the_preset_under_explicit_fields() {
+        let tmp = TempDir::new("config-preset");
+        fs::write(
+            tmp.path().join("config.toml"),
+            "agent_preset = \"codex\"\nagent_args = [\"exec\", \"{template_content}\"]\n",
+        )
+        .unwrap();
+        let state = ConfigState::load_from(tmp.path()).unwrap();
+        assert_eq!(state.config.agent_command, "codex");
+        assert_eq!(state.config.agent_display_name, "Codex");
+        assert_eq!(state.config.agent_args, ["exec", "{template_content}"]);
+        assert!(state.config.agent_interactive);
+
+        fs::write(
+            tmp.path().join("config.toml"),
+            "agent_preset = \"cursor\"\n",
+        )
+        .unwrap();
+        let err = ConfigState::load_from(tmp.path())
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.starts_with("Unknown agent_preset cursor"), "{}", err);
+    }
+
+    #[test]
+    fn use_preset_replaces_agent_fields_and_keeps_the_rest() {
+        let tmp = TempDir::new("config-use-preset");
+        let config_file = tmp.path().join("config.toml");
+        fs::write(
+            &config_file,
+            "agent_command = \"my-agent\"\nagent_display_name = \"Mine\"\nmerge_target = \"develop\"\n",
+        )
+        .unwrap();
+        let mut state = ConfigState::load_from(tmp.path()).unwrap();
+        state.use_preset(presets::find("aider").unwrap()).unwrap();
+
+        assert_eq!(state.config.agent_preset.as_deref(), Some("aider"));
+        assert_eq!(state.config.agent_command, "aider");
+        assert_eq!(state.config.agent_display_name, "Aider");
+        assert!(!state.config.agent_interactive);
+        assert_eq!(state.config.merge_target, "develop");
+        // The file names the preset instead of copying its values.
+        let saved: toml::Table =
+            toml::from_str(&fs::read_to_string(&config_file).unwrap()).unwrap();
+        let expected: toml::Table =
+            toml::from_str("agent_preset = \"aider\"\nmerge_target = \"develop\"\n").unwrap();
+        assert_eq!(saved, expected);
+    }
+}
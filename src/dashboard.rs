@@ -0,0 +1,195 @@
+use std::io::{self, Stdout};
+use std::sync::Once;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use ratatui::Terminal;
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{
+    EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode,
+};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::widgets::{Block, Borders, Cell, Paragraph, Row, Table, TableState};
+
+use crate::archive;
+use crate::git::Worktree;
+use crate::ui;
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(3);
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+pub struct WorktreeStatus {
+    pub worktree: Worktree,
+    // An agent flow is in progress and its process is still alive.
+    pub running: bool,
+    pub dirty: bool,
+    pub ahead: usize,
+    pub last_run_at: Option<u64>,
+    pub summary: Option<String>,
+}
+
+pub enum DashboardAction {
+    Start(Worktree),
+    Merge(Worktree),
+    Delete(Worktree),
+    Quit,
+}
+
+// Shows the dashboard until a key picks an action. The terminal is restored before returning so
+// the caller can run the regular flows, then call this again to come back.
+pub fn run(mut collect: impl FnMut() -> Result<Vec<WorktreeStatus>>) -> Result<DashboardAction> {
+    install_panic_hook();
+    let mut terminal = enter()?;
+    let result = event_loop(&mut terminal, &mut collect);
+    leave(&mut terminal)?;
+    result
+}
+
+fn event_loop(
+    terminal: &mut Terminal<CrosstermBackend<Stdout>>,
+    collect: &mut impl FnMut() -> Result<Vec<WorktreeStatus>>,
+) -> Result<DashboardAction> {
+    let mut rows = collect()?;
+    let mut refreshed = Instant::now();
+    let mut state = TableState::default().with_selected((!rows.is_empty()).then_some(0));
+
+    loop {
+        terminal.draw(|frame| {
+            let [table_area, help_area] =
+                Layout::vertical([Constraint::Min(3), Constraint::Length(1)]).areas(frame.area());
+            frame.render_stateful_widget(table(&rows), table_area, &mut state);
+            frame.render_widget(
+                Paragraph::new(
+                    "s start  m merge  d delete  r refresh  j/k move  q back to the menu",
+                )
+                .style(Style::default().fg(Color::DarkGray)),
+                help_area,
+            );
+        })?;
+
+        if event::poll(POLL_INTERVAL)?
+            && let Event::Key(key) = event::read()?
+            && key.kind == KeyEventKind::Press
+        {
+            let selected = state
+                .selected()
+                .and_then(|idx| rows.get(idx))
+                .map(|row| row.worktree.clone());
+            match (key.code, selected) {
+                (KeyCode::Char('q') | KeyCode::Esc, _) => return Ok(DashboardAction::Quit),
+                (KeyCode::Char('s'), Some(worktree)) => {
+                    return Ok(DashboardAction::Start(worktree));
+                }
+                (KeyCode::Char('m'), Some(worktree)) => {
+                    return Ok(DashboardAction::Merge(worktree));
+                }
+                (KeyCode::Char('d'), Some(worktree)) => {
+                    return Ok(DashboardAction::Delete(worktree));
+                }
+                (KeyCode::Down | KeyCode::Char('j'), _) => state.select_next(),
+                (KeyCode::Up | KeyCode::Char('k'), _) => state.select_previous(),
+                (KeyCode::Char('r'), _) => refreshed -= REFRESH_INTERVAL,
+                _ => {}
+            }
+        }
+
+        if refreshed.elapsed() >= REFRESH_INTERVAL {
+            rows = collect()?;
+            refreshed = Instant::now();
+            if rows.is_empty() {
+                state.select(None);
+            } else {
+                state.select(Some(state.selected().unwrap_or(0).min(rows.len() - 1)));
+            }
+        }
+    }
+}
+
+fn table(rows: &[WorktreeStatus]) -> Table<'static> {
+    let now = archive::now_secs();
+    let rows = rows.iter().map(|row| {
+        let (status, color) = if row.running {
+            ("running", Color::Green)
+        } else {
+            ("idle", Color::Gray)
+        };
+        Row::new([
+            Cell::from(
+                row.worktree
+                    .branch
+                    .clone()
+                    .unwrap_or_else(|| "<detached>".to_string()),
+            ),
+            Cell::from(status).style(Style::default().fg(color)),
+            Cell::from(if row.dirty { "dirty" } else { "" })
+                .style(Style::default().fg(Color::Yellow)),
+            Cell::from(format!("+{}", row.ahead)),
+            Cell::from(
+                row.last_run_at
+                    .map(|at| ui::format_ago(now.saturating_sub(at)))
+                    .unwrap_or_default(),
+            ),
+            Cell::from(row.summary.clone().unwrap_or_default()),
+        ])
+    });
+    Table::new(
+        rows,
+        [
+            Constraint::Percentage(25),
+            Constraint::Length(8),
+            Constraint::Length(6),
+            Constraint::Length(6),
+            Constraint::Length(10),
+            Constraint::Fill(1),
+        ],
+    )
+    .header(
+        Row::new(["Branch", "Status", "Tree", "Ahead", "Last run", "Notes"])
+            .style(Style::default().add_modifier(Modifier::BOLD)),
+    )
+    .block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(" AgentManager dashboard "),
+    )
+    .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+}
+
+fn enter() -> Result<Terminal<CrosstermBackend<Stdout>>> {
+    enable_raw_mode().context("Unable to switch the terminal to raw mode")?;
+    ACTIVE.store(true, Ordering::SeqCst);
+    execute!(io::stdout(), EnterAlternateScreen)?;
+    Terminal::new(CrosstermBackend::new(io::stdout())).context("Unable to start the dashboard")
+}
+
+fn leave(terminal: &mut Terminal<CrosstermBackend<Stdout>>) -> Result<()> {
+    restore();
+    terminal.show_cursor()?;
+    Ok(())
+}
+
+fn restore() {
+    if !ACTIVE.swap(false, Ordering::SeqCst) {
+        return;
+    }
+    let _ = disable_raw_mode();
+    let _ = execute!(io::stdout(), LeaveAlternateScreen);
+}
+
+// A panic while the dashboard is up must not leave the terminal raw.
+fn install_panic_hook() {
+    static HOOK: Once = Once::new();
+    HOOK.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            restore();
+            previous(info);
+        }));
+    });
+}
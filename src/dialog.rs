@@ -0,0 +1,193 @@
+use anyhow::Result;
+use dialoguer::theme::ColorfulTheme;
+use dialoguer::{Confirm, Input, MultiSelect, Select};
+
+// The questions App asks, behind a trait so its flows can also run from a script.
+pub trait Dialog {
+    fn confirm(&self, prompt: &str, default: bool) -> Result<bool>;
+
+    fn select(&self, prompt: &str, items: &[String], default: usize) -> Result<usize>;
+
+    // Indices of the toggled items; `defaults` pre-toggles items by position.
+    fn select_many(&self, prompt: &str, items: &[String], defaults: &[bool]) -> Result<Vec<usize>>;
+
+    // An empty answer gives `default` when there is one, and is refused unless `allow_empty`.
+    fn input(&self, prompt: &str, default: Option<&str>, allow_empty: bool) -> Result<String>;
+
+    // Free text pre-filled with `initial`, which may be cleared.
+    fn edit(&self, prompt: &str, initial: &str) -> Result<String>;
+}
+
+impl dyn Dialog + '_ {
+    pub fn choose(&self, prompt: &str, items: &[impl ToString], default: usize) -> Result<usize> {
+        self.select(prompt, &labels(items), default)
+    }
+
+    pub fn choose_many(
+        &self,
+        prompt: &str,
+        items: &[impl ToString],
+        defaults: &[bool],
+    ) -> Result<Vec<usize>> {
+        self.select_many(prompt, &labels(items), defaults)
+    }
+}
+
+fn labels(items: &[impl ToString]) -> Vec<String> {
+    items.iter().map(ToString::to_string).collect()
+}
+
+pub struct TerminalDialog {
+    theme: ColorfulTheme,
+}
+
+impl TerminalDialog {
+    pub fn new() -> Self {
+        Self {
+            theme: ColorfulTheme::default(),
+        }
+    }
+}
+
+impl Dialog for TerminalDialog {
+    fn confirm(&self, prompt: &str, default: bool) -> Result<bool> {
+        Ok(Confirm::with_theme(&self.theme)
+            .with_prompt(prompt)
+            .default(default)
+            .interact()?)
+    }
+
+    fn select(&self, prompt: &str, items: &[String], default: usize) -> Result<usize> {
+        Ok(Select::with_theme(&self.theme)
+            .with_prompt(prompt)
+            .items(items)
+            .default(default)
+            .interact()?)
+    }
+
+    fn select_many(&self, prompt: &str, items: &[String], defaults: &[bool]) -> Result<Vec<usize>> {
+        Ok(MultiSelect::with_theme(&self.theme)
+            .with_prompt(prompt)
+            .items(items)
+            .defaults(defaults)
+            .interact()?)
+    }
+
+    fn input(&self, prompt: &str, default: Option<&str>, allow_empty: bool) -> Result<String> {
+        let mut input = Input::<String>::with_theme(&self.theme)
+            .with_prompt(prompt)
+            .allow_empty(allow_empty);
+        if let Some(default) = default {
+            input = input.default(default.to_string());
+        }
+        Ok(input.interact_text()?)
+    }
+
+    fn edit(&self, prompt: &str, initial: &str) -> Result<String> {
+        Ok(Input::<String>::with_theme(&self.theme)
+            .with_prompt(prompt)
+            .with_initial_text(initial)
+            .allow_empty(true)
+            .interact_text()?)
+    }
+}
+
+#[cfg(test)]
+pub use scripted::{Answer, ScriptedDialog};
+
+#[cfg(test)]
+mod scripted {
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+
+    use anyhow::Result;
+
+    use super::Dialog;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum Answer {
+        Yes,
+        No,
+        Choice(usize),
+        Choices(Vec<usize>),
+        Text(String),
+    }
+
+    // Answers questions in order from a script and fails the test on any question it was not
+    // prepared for, or when answers are left over.
+    pub struct ScriptedDialog {
+        answers: RefCell<VecDeque<Answer>>,
+    }
+
+    impl ScriptedDialog {
+        pub fn new(answers: impl IntoIterator<Item = Answer>) -> Self {
+            Self {
+                answers: RefCell::new(answers.into_iter().collect()),
+            }
+        }
+
+        fn next(&self, prompt: &str) -> Answer {
+            self.answers
+                .borrow_mut()
+                .pop_front()
+                .unwrap_or_else(|| panic!("unexpected question: {}", prompt))
+        }
+    }
+
+    impl Drop for ScriptedDialog {
+        fn drop(&mut self) {
+            let answers = self.answers.get_mut();
+            if !std::thread::panicking() && !answers.is_empty() {
+                panic!("unused answers: {:?}", answers);
+            }
+        }
+    }
+
+    impl Dialog for ScriptedDialog {
+        fn confirm(&self, prompt: &str, _default: bool) -> Result<bool> {
+            match self.next(prompt) {
+                Answer::Yes => Ok(true),
+                Answer::No => Ok(false),
+                other => panic!("{:?} does not answer the confirmation {}", other, prompt),
+            }
+        }
+
+        fn select(&self, prompt: &str, items: &[String], _default: usize) -> Result<usize> {
+            match self.next(prompt) {
+                Answer::Choice(idx) if idx < items.len() => Ok(idx),
+                other => panic!("{:?} does not answer {} {:?}", other, prompt, items),
+            }
+        }
+
+        fn select_many(
+            &self,
+            prompt: &str,
+            items: &[String],
+            _defaults: &[bool],
+        ) -> Result<Vec<usize>> {
+            match self.next(prompt) {
+                Answer::Choices(chosen) if chosen.iter().all(|idx| *idx < items.len()) => {
+                    Ok(chosen)
+                }
+                other => panic!("{:?} does not answer {} {:?}", other, prompt, items),
+            }
+        }
+
+        fn input(&self, prompt: &str, default: Option<&str>, _allow_empty: bool) -> Result<String> {
+            match self.next(prompt) {
+                Answer::Text(text) if text.is_empty() => {
+                    Ok(default.unwrap_or_default().to_string())
+                }
+                Answer::Text(text) => Ok(text),
+                other => panic!("{:?} does not answer the question {}", other, prompt),
+            }
+        }
+
+        fn edit(&self, prompt: &str, _initial: &str) -> Result<String> {
+            match self.next(prompt) {
+                Answer::Text(text) => Ok(text),
+                other => panic!("{:?} does not answer the question {}", other, prompt),
+            }
+        }
+    }
+}
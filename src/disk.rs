@@ -0,0 +1,116 @@
+use std::path::Path;
+
+use crate::ui::TracedCommand;
+
+// Checked-out files take a bit more than their blob sizes (block rounding, index, build leftovers).
+const SIZE_MULTIPLIER_PERCENT: u64 = 120;
+const SAFETY_MARGIN_BYTES: u64 = 64 * 1024 * 1024;
+
+// Widened so a huge tree saturates instead of wrapping below its own size.
+pub fn required_space(tree_bytes: u64) -> u64 {
+    let scaled = (u128::from(tree_bytes) * u128::from(SIZE_MULTIPLIER_PERCENT)).div_ceil(100);
+    u64::try_from(scaled)
+        .unwrap_or(u64::MAX)
+        .saturating_add(SAFETY_MARGIN_BYTES)
+}
+
+pub fn fits(tree_bytes: u64, available_bytes: u64) -> bool {
+    required_space(tree_bytes) <= available_bytes
+}
+
+// Free space on the filesystem holding `path` (or its closest existing ancestor).
+// Returns `None` when it cannot be determined, in which case callers skip the check.
+#[cfg(unix)]
+pub fn available_space(path: &Path) -> Option<u64> {
+    let existing = path.ancestors().find(|candidate| candidate.exists())?;
+    let output = std::process::Command::new("df")
+        .arg("-Pk")
+        .arg(existing)
+        .traced_output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    // POSIX format: Filesystem 1024-blocks Used Available Capacity Mounted-on
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let kib = stdout
+        .lines()
+        .nth(1)?
+        .split_whitespace()
+        .nth(3)?
+        .parse::<u64>()
+        .ok()?;
+    Some(kib.saturating_mul(1024))
+}
+
+#[cfg(not(unix))]
+pub fn available_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIB: u64 = 1024 * 1024;
+
+    #[test]
+    fn required_space_adds_a_fifth_and_a_safety_margin() {
+        assert_eq!(required_space(0), SAFETY_MARGIN_BYTES);
+        assert_eq!(required_space(100 * MIB), 120 * MIB + SAFETY_MARGIN_BYTES);
+        // Rounded up, never down.
+        assert_eq!(required_space(1), 2 + SAFETY_MARGIN_BYTES);
+        assert_eq!(required_space(u64::MAX), u64::MAX);
+    }
+
+    #[test]
+    fn fits_is_inclusive_at_the_exact_requirement() {
+        let tree = 500 * MIB;
+        let needed = required_space(tree);
+        assert!(fits(tree, needed));
+        assert!(fits(tree, needed + 1));
+        assert!(!fits(tree, needed - 1));
+        assert!(!fits(0, 0));
+        assert!(!fits(u64::MAX, u64::MAX - 1));
+    }
+
+    #[test]
+    fn format_bytes_picks_the_largest_unit() {
+        let cases = [
+            (0, "0 B"),
+            (1023, "1023 B"),
+            (1024, "1.0 KiB"),
+            (1536, "1.5 KiB"),
+            (64 * MIB, "64.0 MiB"),
+            (3 * 1024 * MIB + 512 * MIB, "3.5 GiB"),
+            (2048 * 1024 * 1024 * MIB, "2048.0 TiB"),
+        ];
+        for (bytes, expected) in cases {
+            assert_eq!(format_bytes(bytes), expected, "{}", bytes);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn available_space_looks_at_the_closest_existing_ancestor() {
+        let tmp = std::env::temp_dir();
+        let existing = available_space(&tmp).expect("df works on the temp dir");
+        assert!(existing > 0);
+        assert!(available_space(&tmp.join("not/created/yet")).is_some());
+    }
+}
@@ -0,0 +1,252 @@
+use std::fmt;
+use std::io;
+
+use anyhow::Result;
+use serde_json::json;
+
+use crate::git::MergeError;
+
+// What the process exit code and `--machine-errors` report for a failure. Errors not tagged on the
+// way up are classified from their source chain, and fall back to `Other`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    Other,
+    Aborted,
+    Git,
+    Agent,
+    Config,
+    MergeConflict,
+}
+
+impl ErrorKind {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::Other => 1,
+            ErrorKind::Aborted => 2,
+            ErrorKind::Git => 3,
+            ErrorKind::Agent => 4,
+            ErrorKind::Config => 5,
+            ErrorKind::MergeConflict => 6,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorKind::Other => "error",
+            ErrorKind::Aborted => "user_abort",
+            ErrorKind::Git => "git_failure",
+            ErrorKind::Agent => "agent_failure",
+            ErrorKind::Config => "config_error",
+            ErrorKind::MergeConflict => "merge_conflict",
+        }
+    }
+}
+
+// Tags an error with its kind without changing how it reads.
+#[derive(Debug)]
+struct Classified {
+    kind: ErrorKind,
+    error: anyhow::Error,
+}
+
+impl fmt::Display for Classified {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The causes follow through `source`, so only the top message belongs here.
+        write!(f, "{}", self.error)
+    }
+}
+
+impl std::error::Error for Classified {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.error.source()
+    }
+}
+
+pub trait Failure<T> {
+    fn failure(self, kind: ErrorKind) -> Result<T>;
+}
+
+impl<T> Failure<T> for Result<T> {
+    fn failure(self, kind: ErrorKind) -> Result<T> {
+        self.map_err(|error| {
+            // The innermost tag is the most specific one.
+            if error.chain().any(|cause| cause.is::<Classified>()) {
+                error
+            } else {
+                anyhow::Error::new(Classified { kind, error })
+            }
+        })
+    }
+}
+
+pub fn classify(error: &anyhow::Error) -> ErrorKind {
+    for cause in error.chain() {
+        if let Some(classified) = cause.downcast_ref::<Classified>() {
+            // A merge conflict reached through a generic git step is still a conflict.
+            return match classify(&classified.error) {
+                ErrorKind::Other => classified.kind,
+                kind => kind,
+            };
+        }
+        if let Some(err) = cause.downcast_ref::<MergeError>() {
+            return match err {
+                MergeError::Conflicts { .. } => ErrorKind::MergeConflict,
+                _ => ErrorKind::Git,
+            };
+        }
+        if let Some(err) = cause.downcast_ref::<agent_manager::Error>() {
+            return match err {
+                agent_manager::Error::Config(_) => ErrorKind::Config,
+                agent_manager::Error::NotARepository { .. } | agent_manager::Error::Git(_) => {
+                    ErrorKind::Git
+                }
+                agent_manager::Error::Template(_) => ErrorKind::Other,
+                agent_manager::Error::Agent(_) => ErrorKind::Agent,
+            };
+        }
+        // Ctrl+C inside a prompt.
+        let interrupted = match cause.downcast_ref::<dialoguer::Error>() {
+            Some(dialoguer::Error::IO(err)) => err.kind() == io::ErrorKind::Interrupted,
+            None => cause
+                .downcast_ref::<io::Error>()
+                .is_some_and(|err| err.kind() == io::ErrorKind::Interrupted),
+        };
+        if interrupted {
+            return ErrorKind::Aborted;
+        }
+    }
+    ErrorKind::Other
+}
+
+// The final line on stderr for scripts and CI wrappers.
+pub fn machine_report(kind: ErrorKind, error: &anyhow::Error) -> String {
+    json!({
+        "error_kind": kind.name(),
+        "exit_code": kind.exit_code(),
+        "detail": format!("{:#}", error),
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{Context, anyhow};
+    use std::path::PathBuf;
+
+    fn conflict() -> anyhow::Error {
+        MergeError::Conflicts {
+            worktree: PathBuf::from("/repo"),
+            files: vec!["a.txt".to_string()],
+        }
+        .into()
+    }
+
+    fn kind_of(result: Result<()>) -> ErrorKind {
+        classify(&result.unwrap_err())
+    }
+
+    #[test]
+    fn exit_codes_and_names_are_distinct() {
+        let kinds = [
+            ErrorKind::Other,
+            ErrorKind::Aborted,
+            ErrorKind::Git,
+            ErrorKind::Agent,
+            ErrorKind::Config,
+            ErrorKind::MergeConflict,
+        ];
+        for (idx, kind) in kinds.iter().enumerate() {
+            for other in &kinds[idx + 1..] {
+                assert_ne!(kind.exit_code(), other.exit_code(), "{:?}", (kind, other));
+                assert_ne!(kind.name(), other.name(), "{:?}", (kind, other));
+            }
+        }
+        assert_eq!(ErrorKind::Other.exit_code(), 1);
+    }
+
+    #[test]
+    fn untagged_errors_are_classified_from_their_source_chain() {
+        let cases: [(Result<()>, ErrorKind); 10] = [
+            (Err(anyhow!("boom")), ErrorKind::Other),
+            (
+                Err(conflict()).context("Unable to land agent/a"),
+                ErrorKind::MergeConflict,
+            ),
+            (
+                Err(MergeError::SameRef {
+                    branch: "main".to_string(),
+                }
+                .into()),
+                ErrorKind::Git,
+            ),
+            (
+                Err(agent_manager::Error::Config(anyhow!("bad toml")).into()),
+                ErrorKind::Config,
+            ),
+            (
+                Err(agent_manager::Error::NotARepository {
+                    dir: PathBuf::from("/tmp"),
+                    source: anyhow!("not a repo"),
+                }
+                .into()),
+                ErrorKind::Git,
+            ),
+            (
+                Err(agent_manager::Error::Agent(anyhow!("exit 1")).into()),
+                ErrorKind::Agent,
+            ),
+            (
+                Err(agent_manager::Error::Template(anyhow!("bad")).into()),
+                ErrorKind::Other,
+            ),
+            (
+                Err(io::Error::from(io::ErrorKind::Interrupted)).context("Feature name"),
+                ErrorKind::Aborted,
+            ),
+            (
+                Err(dialoguer::Error::IO(io::Error::from(io::ErrorKind::Interrupted)).into()),
+                ErrorKind::Aborted,
+            ),
+            (
+                Err(io::Error::from(io::ErrorKind::NotFound).into()),
+                ErrorKind::Other,
+            ),
+        ];
+        for (result, expected) in cases {
+            let err = result.unwrap_err();
+            assert_eq!(classify(&err), expected, "{:#}", err);
+        }
+    }
+
+    #[test]
+    fn the_innermost_tag_wins_and_conflicts_beat_a_generic_git_tag() {
+        let tagged = Err::<(), _>(anyhow!("agent died"))
+            .failure(ErrorKind::Agent)
+            .context("Feature flow failed")
+            .failure(ErrorKind::Git);
+        assert_eq!(kind_of(tagged), ErrorKind::Agent);
+
+        let conflicted = Err::<(), _>(conflict()).failure(ErrorKind::Git);
+        assert_eq!(kind_of(conflicted), ErrorKind::MergeConflict);
+
+        // Tagging keeps the message and its causes.
+        let err = Err::<(), _>(anyhow!("inner"))
+            .context("outer")
+            .failure(ErrorKind::Git)
+            .unwrap_err();
+        assert_eq!(format!("{:#}", err), "outer: inner");
+    }
+
+    #[test]
+    fn machine_report_is_one_line_of_json() {
+        let err = anyhow!("inner").context("outer");
+        let report = machine_report(ErrorKind::Git, &err);
+        assert!(!report.contains('\n'));
+        let value: serde_json::Value = serde_json::from_str(&report).unwrap();
+        assert_eq!(
+            value,
+            json!({"error_kind": "git_failure", "exit_code": 3, "detail": "outer: inner"})
+        );
+    }
+}
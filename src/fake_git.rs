@@ -0,0 +1,538 @@
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use agent_manager::git::{
+    BranchRef, ChangedFile, CherryPickStep, CommitSummary, ConflictPrediction, DiffRange,
+    GitBackend, HealthIssue, MergeError, StashEntry, UpstreamDivergence, Worktree,
+};
+use anyhow::{Result, anyhow};
+
+// An in-memory repository for driving App flows in tests. Every call is recorded as
+// `name(args)`; worktrees, branches and the primary checkout are tracked, anything else answers
+// with an empty result. `fail` scripts the next call of a method to return an error.
+pub struct FakeGit {
+    root: PathBuf,
+    worktrees: RefCell<Vec<Worktree>>,
+    branches: RefCell<BTreeSet<String>>,
+    changed: Vec<ChangedFile>,
+    // Branches fully merged into any target, each with commits of its own.
+    merged: BTreeSet<String>,
+    dirty: BTreeSet<PathBuf>,
+    // Worktrees holding a merge stopped on conflicts.
+    merging: RefCell<BTreeSet<PathBuf>>,
+    failures: RefCell<HashMap<&'static str, VecDeque<anyhow::Error>>>,
+    calls: Rc<RefCell<Vec<String>>>,
+}
+
+impl FakeGit {
+    pub fn new(root: &Path, current: &str) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            worktrees: RefCell::new(vec![Worktree {
+                path: root.to_path_buf(),
+                branch: Some(current.to_string()),
+                locked: false,
+                prunable: false,
+            }]),
+            branches: RefCell::new(BTreeSet::from([current.to_string()])),
+            changed: Vec::new(),
+            merged: BTreeSet::new(),
+            dirty: BTreeSet::new(),
+            merging: RefCell::new(BTreeSet::new()),
+            failures: RefCell::new(HashMap::new()),
+            calls: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    pub fn with_worktree(self, path: &Path, branch: &str) -> Self {
+        self.worktrees.borrow_mut().push(Worktree {
+            path: path.to_path_buf(),
+            branch: Some(branch.to_string()),
+            locked: false,
+            prunable: false,
+        });
+        self.with_branch(branch)
+    }
+
+    pub fn with_branch(self, branch: &str) -> Self {
+        self.branches.borrow_mut().insert(branch.to_string());
+        self
+    }
+
+    pub fn with_locked_worktree(self, path: &Path, branch: &str) -> Self {
+        let fake = self.with_worktree(path, branch);
+        if let Some(worktree) = fake.worktrees.borrow_mut().last_mut() {
+            worktree.locked = true;
+        }
+        fake
+    }
+
+    pub fn with_merged(mut self, branch: &str) -> Self {
+        self.merged.insert(branch.to_string());
+        self
+    }
+
+    pub fn with_dirty(mut self, worktree_path: &Path) -> Self {
+        self.dirty.insert(worktree_path.to_path_buf());
+        self
+    }
+
+    // What `changed_files` reports for any worktree, as (path, untracked) pairs.
+    pub fn with_changed_files(mut self, files: &[(&str, bool)]) -> Self {
+        self.changed = files
+            .iter()
+            .map(|(path, untracked)| ChangedFile {
+                path: path.to_string(),
+                untracked: *untracked,
+            })
+            .collect();
+        self
+    }
+
+    pub fn fail(self, method: &'static str, message: &str) -> Self {
+        self.fail_with(method, anyhow!(message.to_string()))
+    }
+
+    pub fn fail_with(self, method: &'static str, error: anyhow::Error) -> Self {
+        self.failures
+            .borrow_mut()
+            .entry(method)
+            .or_default()
+            .push_back(error);
+        self
+    }
+
+    // Still readable once the fake is boxed into an App.
+    pub fn calls(&self) -> Rc<RefCell<Vec<String>>> {
+        Rc::clone(&self.calls)
+    }
+
+    fn call(&self, method: &'static str, args: &[&dyn Debug]) -> Result<()> {
+        let args = args
+            .iter()
+            .map(|arg| format!("{:?}", arg))
+            .collect::<Vec<_>>();
+        self.calls
+            .borrow_mut()
+            .push(format!("{}({})", method, args.join(", ")));
+        match self
+            .failures
+            .borrow_mut()
+            .get_mut(method)
+            .and_then(VecDeque::pop_front)
+        {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
+
+impl GitBackend for FakeGit {
+    fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn name(&self) -> &str {
+        "repo"
+    }
+
+    fn common_git_dir(&self) -> Result<PathBuf> {
+        Ok(self.root.join(".git"))
+    }
+
+    fn list_worktrees(&self) -> Result<Vec<Worktree>> {
+        Ok(self.worktrees.borrow().clone())
+    }
+
+    fn remove_worktree(&self, target_dir: &Path, force: bool) -> Result<()> {
+        self.call("remove_worktree", &[&target_dir, &force])?;
+        self.worktrees
+            .borrow_mut()
+            .retain(|worktree| worktree.path != target_dir);
+        Ok(())
+    }
+
+    fn delete_branch(&self, branch: &str, force: bool) -> Result<()> {
+        self.call("delete_branch", &[&branch, &force])?;
+        self.branches.borrow_mut().remove(branch);
+        Ok(())
+    }
+
+    fn branch_exists(&self, branch: &str) -> Result<bool> {
+        Ok(self.branches.borrow().contains(branch))
+    }
+
+    fn current_branch_in(&self, worktree_path: &Path) -> Result<Option<String>> {
+        Ok(self
+            .worktrees
+            .borrow()
+            .iter()
+            .find(|worktree| worktree.path == worktree_path)
+            .and_then(|worktree| worktree.branch.clone()))
+    }
+
+    fn checkout_branch(&self, branch: &str) -> Result<()> {
+        self.call("checkout_branch", &[&branch])?;
+        if let Some(primary) = self.worktrees.borrow_mut().first_mut() {
+            primary.branch = Some(branch.to_string());
+        }
+        Ok(())
+    }
+
+    // Branch names stand in for their commits.
+    fn rev_parse(&self, rev: &str) -> Result<Option<String>> {
+        Ok(self.branches.borrow().get(rev).cloned())
+    }
+
+    fn health_check(&self) -> Result<Vec<HealthIssue>> {
+        Ok(Vec::new())
+    }
+
+    fn create_worktree(
+        &self,
+        branch_name: &str,
+        target_dir: &Path,
+        base_branch: &str,
+        no_checkout: bool,
+    ) -> Result<()> {
+        self.call(
+            "create_worktree",
+            &[&branch_name, &target_dir, &base_branch, &no_checkout],
+        )?;
+        Ok(())
+    }
+
+    fn add_worktree_for_branch(&self, branch: &str, target_dir: &Path) -> Result<()> {
+        self.call("add_worktree_for_branch", &[&branch, &target_dir])?;
+        Ok(())
+    }
+
+    fn prune_worktrees(&self) -> Result<()> {
+        self.call("prune_worktrees", &[])?;
+        Ok(())
+    }
+
+    fn bundle_branch(&self, branch: &str, bundle: &Path) -> Result<()> {
+        self.call("bundle_branch", &[&branch, &bundle])?;
+        Ok(())
+    }
+
+    fn fetch_bundle(&self, bundle: &Path, branch: &str) -> Result<()> {
+        self.call("fetch_bundle", &[&bundle, &branch])?;
+        Ok(())
+    }
+
+    fn uncommitted_patch(&self, worktree_path: &Path, excluded: &[&str]) -> Result<Vec<u8>> {
+        self.call("uncommitted_patch", &[&worktree_path, &excluded])?;
+        Ok(Default::default())
+    }
+
+    fn apply_patch(&self, worktree_path: &Path, patch: &Path) -> Result<()> {
+        self.call("apply_patch", &[&worktree_path, &patch])?;
+        Ok(())
+    }
+
+    fn repair_worktrees(&self, paths: &[PathBuf]) -> Result<String> {
+        self.call("repair_worktrees", &[&paths])?;
+        Ok(Default::default())
+    }
+
+    fn top_level_dirs(&self, revision: &str) -> Result<Vec<String>> {
+        self.call("top_level_dirs", &[&revision])?;
+        Ok(Default::default())
+    }
+
+    fn tree_size(&self, revision: &str, paths: &[String]) -> Result<u64> {
+        self.call("tree_size", &[&revision, &paths])?;
+        Ok(Default::default())
+    }
+
+    fn sparse_checkout_init(&self, worktree_path: &Path) -> Result<()> {
+        self.call("sparse_checkout_init", &[&worktree_path])?;
+        Ok(())
+    }
+
+    fn sparse_checkout_set(&self, worktree_path: &Path, paths: &[String]) -> Result<()> {
+        self.call("sparse_checkout_set", &[&worktree_path, &paths])?;
+        Ok(())
+    }
+
+    fn checkout_worktree(&self, worktree_path: &Path) -> Result<()> {
+        self.call("checkout_worktree", &[&worktree_path])?;
+        Ok(())
+    }
+
+    fn init_submodules(&self, worktree_path: &Path) -> Result<()> {
+        self.call("init_submodules", &[&worktree_path])?;
+        Ok(())
+    }
+
+    fn tracked_files(&self, worktree_path: &Path) -> Result<Vec<String>> {
+        self.call("tracked_files", &[&worktree_path])?;
+        Ok(Default::default())
+    }
+
+    fn is_tracked(&self, worktree_path: &Path, file: &str) -> Result<bool> {
+        self.call("is_tracked", &[&worktree_path, &file])?;
+        Ok(Default::default())
+    }
+
+    fn is_worktree_dirty(&self, worktree_path: &Path, ignore: &[String]) -> Result<bool> {
+        self.call("is_worktree_dirty", &[&worktree_path, &ignore])?;
+        Ok(self.dirty.contains(worktree_path))
+    }
+
+    fn changed_files(&self, worktree_path: &Path, base: &str) -> Result<Vec<ChangedFile>> {
+        self.call("changed_files", &[&worktree_path, &base])?;
+        Ok(self.changed.clone())
+    }
+
+    fn working_diff(&self, worktree_path: &Path, base: &str) -> Result<String> {
+        self.call("working_diff", &[&worktree_path, &base])?;
+        Ok(Default::default())
+    }
+
+    // Like git, the target stays checked out in the primary worktree even when the merge fails;
+    // a scripted `MergeError::Conflicts` leaves the merge in progress there.
+    fn merge_branch(&self, source_branch: &str, target_branch: &str) -> Result<()> {
+        if let Some(primary) = self.worktrees.borrow_mut().first_mut() {
+            primary.branch = Some(target_branch.to_string());
+        }
+        self.call("merge_branch", &[&source_branch, &target_branch])
+            .inspect_err(|err| {
+                if let Some(MergeError::Conflicts { worktree, .. }) = err.downcast_ref() {
+                    self.merging.borrow_mut().insert(worktree.clone());
+                }
+            })
+    }
+
+    fn merge_branch_in(&self, worktree_path: &Path, source_branch: &str) -> Result<()> {
+        self.call("merge_branch_in", &[&worktree_path, &source_branch])?;
+        Ok(())
+    }
+
+    fn merge_no_commit(&self, source_branch: &str, target_branch: &str) -> Result<()> {
+        self.call("merge_no_commit", &[&source_branch, &target_branch])?;
+        Ok(())
+    }
+
+    fn commit_merge(&self, message: &str) -> Result<()> {
+        self.call("commit_merge", &[&message])?;
+        Ok(())
+    }
+
+    fn unmerged_paths(&self, worktree_path: &Path) -> Result<Vec<String>> {
+        self.call("unmerged_paths", &[&worktree_path])?;
+        Ok(Default::default())
+    }
+
+    fn staged_diffstat(&self) -> Result<String> {
+        self.call("staged_diffstat", &[])?;
+        Ok(Default::default())
+    }
+
+    fn merge_base(&self, first: &str, second: &str) -> Result<String> {
+        self.call("merge_base", &[&first, &second])?;
+        Ok(Default::default())
+    }
+
+    fn create_branch(&self, branch: &str, start_point: &str) -> Result<()> {
+        self.call("create_branch", &[&branch, &start_point])?;
+        Ok(())
+    }
+
+    fn remote_branch_exists(&self, remote: &str, branch: &str) -> Result<bool> {
+        self.call("remote_branch_exists", &[&remote, &branch])?;
+        Ok(Default::default())
+    }
+
+    fn create_tracking_branch(&self, branch: &str, upstream: &str) -> Result<()> {
+        self.call("create_tracking_branch", &[&branch, &upstream])?;
+        Ok(())
+    }
+
+    fn branches_matching(&self, pattern: &str, include_remote: bool) -> Result<Vec<BranchRef>> {
+        self.call("branches_matching", &[&pattern, &include_remote])?;
+        Ok(Default::default())
+    }
+
+    fn fetch(&self, remote: &str, timeout_secs: Option<u64>) -> Result<()> {
+        self.call("fetch", &[&remote, &timeout_secs])?;
+        Ok(())
+    }
+
+    fn commits_behind(&self, branch: &str, target: &str) -> Result<usize> {
+        self.call("commits_behind", &[&branch, &target])?;
+        Ok(Default::default())
+    }
+
+    fn is_merged_into(&self, branch: &str, target: &str) -> Result<bool> {
+        self.call("is_merged_into", &[&branch, &target])?;
+        Ok(self.merged.contains(branch))
+    }
+
+    fn has_branch_activity(&self, branch: &str) -> Result<bool> {
+        self.call("has_branch_activity", &[&branch])?;
+        Ok(self.merged.contains(branch))
+    }
+
+    fn rebase_onto(&self, worktree_path: &Path, target: &str) -> Result<()> {
+        self.call("rebase_onto", &[&worktree_path, &target])?;
+        Ok(())
+    }
+
+    fn rebase_in(&self, worktree_path: &Path, target: &str) -> Result<()> {
+        self.call("rebase_in", &[&worktree_path, &target])?;
+        Ok(())
+    }
+
+    fn abort_rebase_in(&self, worktree_path: &Path) -> Result<()> {
+        self.call("abort_rebase_in", &[&worktree_path])?;
+        Ok(())
+    }
+
+    fn merge_in_progress(&self, worktree_path: &Path) -> Result<bool> {
+        self.call("merge_in_progress", &[&worktree_path])?;
+        Ok(self.merging.borrow().contains(worktree_path))
+    }
+
+    fn rebase_in_progress(&self, worktree_path: &Path) -> Result<bool> {
+        self.call("rebase_in_progress", &[&worktree_path])?;
+        Ok(Default::default())
+    }
+
+    fn conclude_merge_in(&self, worktree_path: &Path) -> Result<()> {
+        self.call("conclude_merge_in", &[&worktree_path])?;
+        self.merging.borrow_mut().remove(worktree_path);
+        Ok(())
+    }
+
+    fn fast_forward_from_upstream(&self, branch: &str, timeout_secs: Option<u64>) -> Result<bool> {
+        self.call("fast_forward_from_upstream", &[&branch, &timeout_secs])?;
+        Ok(Default::default())
+    }
+
+    fn upstream_divergence(&self, worktree_path: &Path) -> Result<Option<UpstreamDivergence>> {
+        self.call("upstream_divergence", &[&worktree_path])?;
+        Ok(Default::default())
+    }
+
+    fn commit_subjects(&self, worktree_path: &Path, range: &str) -> Result<Vec<String>> {
+        self.call("commit_subjects", &[&worktree_path, &range])?;
+        Ok(Default::default())
+    }
+
+    fn commit_authors(
+        &self,
+        worktree_path: &Path,
+        range: &str,
+        limit: usize,
+    ) -> Result<Vec<String>> {
+        self.call("commit_authors", &[&worktree_path, &range, &limit])?;
+        Ok(Default::default())
+    }
+
+    fn merge_into_worktree(&self, worktree_path: &Path, source: &str) -> Result<()> {
+        self.call("merge_into_worktree", &[&worktree_path, &source])?;
+        Ok(())
+    }
+
+    fn commits_between(&self, base: &str, tip: &str) -> Result<Vec<CommitSummary>> {
+        self.call("commits_between", &[&base, &tip])?;
+        Ok(Default::default())
+    }
+
+    fn show_commit(&self, commit: &str) -> Result<String> {
+        self.call("show_commit", &[&commit])?;
+        Ok(Default::default())
+    }
+
+    fn cherry_pick(&self, worktree_path: &Path, commit: &str) -> Result<bool> {
+        self.call("cherry_pick", &[&worktree_path, &commit])?;
+        Ok(Default::default())
+    }
+
+    fn cherry_pick_step(&self, worktree_path: &Path, step: CherryPickStep) -> Result<bool> {
+        self.call("cherry_pick_step", &[&worktree_path, &step])?;
+        Ok(Default::default())
+    }
+
+    fn list_marked_stashes(&self) -> Result<Vec<StashEntry>> {
+        self.call("list_marked_stashes", &[])?;
+        Ok(Default::default())
+    }
+
+    fn apply_stash(&self, worktree_path: &Path, reference: &str) -> Result<()> {
+        self.call("apply_stash", &[&worktree_path, &reference])?;
+        Ok(())
+    }
+
+    fn drop_stash(&self, reference: &str) -> Result<()> {
+        self.call("drop_stash", &[&reference])?;
+        Ok(())
+    }
+
+    fn stash_diff(&self, reference: &str, color: bool) -> Result<Vec<u8>> {
+        self.call("stash_diff", &[&reference, &color])?;
+        Ok(Default::default())
+    }
+
+    fn config_value(&self, key: &str) -> Result<Option<String>> {
+        self.call("config_value", &[&key])?;
+        Ok(Default::default())
+    }
+
+    fn enable_worktree_config(&self) -> Result<()> {
+        self.call("enable_worktree_config", &[])?;
+        Ok(())
+    }
+
+    fn set_worktree_config(&self, worktree_path: &Path, key: &str, value: &str) -> Result<()> {
+        self.call("set_worktree_config", &[&worktree_path, &key, &value])?;
+        Ok(())
+    }
+
+    fn switch_branch_in(&self, worktree_path: &Path, branch: &str) -> Result<()> {
+        self.call("switch_branch_in", &[&worktree_path, &branch])?;
+        Ok(())
+    }
+
+    fn commit_file(&self, file: &str, message: &str) -> Result<()> {
+        self.call("commit_file", &[&file, &message])?;
+        Ok(())
+    }
+
+    fn diff(&self, worktree_path: &Path, range: DiffRange<'_>, color: bool) -> Result<Vec<u8>> {
+        self.call("diff", &[&worktree_path, &range, &color])?;
+        Ok(Default::default())
+    }
+
+    fn predict_conflicts(&self, target: &str, branch: &str) -> Result<Option<ConflictPrediction>> {
+        self.call("predict_conflicts", &[&target, &branch])?;
+        Ok(Default::default())
+    }
+
+    fn diff_stat(&self, from: &str, to: &str) -> Result<String> {
+        self.call("diff_stat", &[&from, &to])?;
+        Ok(Default::default())
+    }
+
+    fn branch_files(&self, target: &str, branch: &str) -> Result<Vec<String>> {
+        self.call("branch_files", &[&target, &branch])?;
+        Ok(Default::default())
+    }
+
+    fn abort_merge_in(&self, worktree_path: &Path) -> Result<()> {
+        self.call("abort_merge_in", &[&worktree_path])?;
+        self.merging.borrow_mut().remove(worktree_path);
+        Ok(())
+    }
+
+    fn mark_safe_directory(&self, path: &Path) -> Result<()> {
+        self.call("mark_safe_directory", &[&path])?;
+        Ok(())
+    }
+}
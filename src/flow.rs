@@ -0,0 +1,203 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::lock;
+use crate::templates;
+
+const FLOW_STATE_FILENAME: &str = "agent-flow.toml";
+const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FlowStep {
+    Created,
+    TemplateRendered,
+    AgentRun,
+    Merged,
+}
+
+impl FlowStep {
+    pub fn label(self) -> &'static str {
+        match self {
+            FlowStep::Created => "worktree created",
+            FlowStep::TemplateRendered => "template rendered",
+            FlowStep::AgentRun => "agent run",
+            FlowStep::Merged => "merged",
+        }
+    }
+}
+
+// Progress of a `new_feature_flow` run, kept next to the worktree metadata in its private git
+// dir so an interrupted run can be resumed. Removed once the flow completes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlowState {
+    pub version: u32,
+    pub step: FlowStep,
+    pub branch: String,
+    pub base_branch: String,
+    pub pid: u32,
+    pub started_at: u64,
+    pub updated_at: u64,
+}
+
+impl FlowState {
+    // Files written by a newer schema are ignored rather than misread.
+    pub fn load(worktree: &Path) -> Result<Option<Self>> {
+        let path = state_path(worktree)?;
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let state: Self = toml::from_str(&content)
+            .with_context(|| format!("Flow state {} is invalid", path.display()))?;
+        Ok((state.version == SCHEMA_VERSION).then_some(state))
+    }
+
+    pub fn start(worktree: &Path, branch: &str, base_branch: &str) -> Result<()> {
+        let now = now();
+        Self {
+            version: SCHEMA_VERSION,
+            step: FlowStep::Created,
+            branch: branch.to_string(),
+            base_branch: base_branch.to_string(),
+            pid: std::process::id(),
+            started_at: now,
+            updated_at: now,
+        }
+        .save(worktree)
+    }
+
+    // Takes over an interrupted flow, unless another instance claimed it since it was listed.
+    // The check and the claim happen under the state file's lock.
+    pub fn claim(worktree: &Path) -> Result<Option<Self>> {
+        let _lock = lock::FileLock::acquire(&state_path(worktree)?)?;
+        let Some(mut state) = Self::load(worktree)?.filter(Self::is_interrupted) else {
+            return Ok(None);
+        };
+        state.pid = std::process::id();
+        state.updated_at = now();
+        state.save(worktree)?;
+        Ok(Some(state))
+    }
+
+    // Records `step` and claims the flow for this process.
+    pub fn advance(worktree: &Path, step: FlowStep) -> Result<()> {
+        let _lock = lock::FileLock::acquire(&state_path(worktree)?)?;
+        let Some(mut state) = Self::load(worktree)? else {
+            return Ok(());
+        };
+        state.step = step;
+        state.pid = std::process::id();
+        state.updated_at = now();
+        state.save(worktree)
+    }
+
+    pub fn clear(worktree: &Path) -> Result<()> {
+        let path = state_path(worktree)?;
+        match fs::remove_file(&path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                Err(err).with_context(|| format!("Unable to remove flow state {}", path.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // A flow whose process is gone will never finish on its own.
+    pub fn is_interrupted(&self) -> bool {
+        !lock::process_alive(self.pid)
+    }
+
+    pub fn updated_ago(&self) -> u64 {
+        now().saturating_sub(self.updated_at)
+    }
+
+    fn save(&self, worktree: &Path) -> Result<()> {
+        let path = state_path(worktree)?;
+        lock::write_atomic(&path, toml::to_string_pretty(self)?)
+            .with_context(|| format!("Unable to write flow state {}", path.display()))
+    }
+}
+
+fn state_path(worktree: &Path) -> Result<PathBuf> {
+    Ok(templates::git_dir_for_worktree(worktree)?.join(FLOW_STATE_FILENAME))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+    use std::sync::Arc;
+    use std::thread;
+
+    // A worktree whose flow was left behind by a process that no longer exists.
+    fn interrupted(tmp: &TempDir) -> PathBuf {
+        let worktree = tmp.path().to_path_buf();
+        fs::create_dir_all(worktree.join(".git")).unwrap();
+        FlowState::start(&worktree, "agent/login", "main").unwrap();
+        let mut state = FlowState::load(&worktree).unwrap().unwrap();
+        state.pid = 4_000_000_000;
+        state.save(&worktree).unwrap();
+        worktree
+    }
+
+    #[test]
+    fn claim_takes_over_an_interrupted_flow_once() {
+        let tmp = TempDir::new("flow-claim");
+        let worktree = interrupted(&tmp);
+        assert!(
+            FlowState::load(&worktree)
+                .unwrap()
+                .unwrap()
+                .is_interrupted()
+        );
+
+        let claimed = FlowState::claim(&worktree).unwrap().unwrap();
+        assert_eq!(claimed.pid, std::process::id());
+        assert_eq!(claimed.step, FlowStep::Created);
+        assert!(
+            !FlowState::load(&worktree)
+                .unwrap()
+                .unwrap()
+                .is_interrupted()
+        );
+        assert!(FlowState::claim(&worktree).unwrap().is_none());
+    }
+
+    #[test]
+    fn concurrent_claims_have_a_single_winner() {
+        let tmp = TempDir::new("flow-claim-race");
+        let worktree = Arc::new(interrupted(&tmp));
+        let claims: Vec<_> = (0..4)
+            .map(|_| {
+                let worktree = Arc::clone(&worktree);
+                thread::spawn(move || FlowState::claim(&worktree).unwrap().is_some())
+            })
+            .collect();
+        let winners = claims
+            .into_iter()
+            .map(|claim| claim.join().unwrap())
+            .filter(|won| *won)
+            .count();
+        assert_eq!(winners, 1);
+    }
+
+    #[test]
+    fn nothing_to_claim_without_a_flow() {
+        let tmp = TempDir::new("flow-claim-none");
+        fs::create_dir_all(tmp.path().join(".git")).unwrap();
+        assert!(FlowState::claim(tmp.path()).unwrap().is_none());
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+
+use crate::ui;
+
+// Probe results per directory, so each filesystem is only tried once per session.
+static SYMLINK_SUPPORT: Mutex<Option<HashMap<PathBuf, bool>>> = Mutex::new(None);
+
+// Whether symlinks can be created under `dir` (or its closest existing ancestor), probed on first
+// use. The first unsupported directory gets a warning; later ones stay quiet.
+pub fn symlinks_supported(dir: &Path) -> bool {
+    let Some(existing) = dir.ancestors().find(|candidate| candidate.is_dir()) else {
+        return true;
+    };
+    let mut cache = SYMLINK_SUPPORT
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    let cache = cache.get_or_insert_with(HashMap::new);
+    if let Some(supported) = cache.get(existing) {
+        return *supported;
+    }
+    let supported = probe_symlinks(existing);
+    if !supported && !cache.values().any(|supported| !supported) {
+        ui::warning!(
+            "{} does not support symlinks: archived symlinks are restored as copies, and agent tools that create symlinks may fail there.",
+            existing.display()
+        );
+    }
+    cache.insert(existing.to_path_buf(), supported);
+    supported
+}
+
+// Creates and removes a throwaway symlink in `dir`. A directory that cannot be written to at all
+// counts as supporting them: nothing will be created there anyway.
+pub fn probe_symlinks(dir: &Path) -> bool {
+    let probe = dir.join(format!(".agent-symlink-probe-{}", std::process::id()));
+    let _ = fs::remove_file(&probe);
+    match symlink(Path::new("."), &probe) {
+        Ok(()) => {
+            let _ = fs::remove_file(&probe);
+            true
+        }
+        // exFAT and some SMB mounts refuse with EPERM, like an unwritable directory would.
+        Err(_) => {
+            let written = fs::write(&probe, b"").is_ok();
+            let _ = fs::remove_file(&probe);
+            !written
+        }
+    }
+}
+
+// Places what the symlink `from` points to at `to`: a symlink where supported, otherwise a copy of
+// the target's content. A dangling symlink cannot be copied and is skipped with a warning.
+pub fn link_or_copy(from: &Path, to: &Path) -> Result<()> {
+    let target = fs::read_link(from)?;
+    if fs::symlink_metadata(to).is_ok() {
+        fs::remove_file(to)?;
+    }
+    let parent = to.parent().unwrap_or(Path::new("."));
+    if symlinks_supported(parent) {
+        return symlink(&target, to)
+            .with_context(|| format!("Unable to recreate symlink {}", to.display()));
+    }
+    let resolved = match fs::canonicalize(from) {
+        Ok(resolved) => resolved,
+        Err(_) => {
+            ui::warning!(
+                "Skipping {}: it points to the missing {}",
+                from.display(),
+                target.display()
+            );
+            return Ok(());
+        }
+    };
+    copy_dir_or_file(&resolved, to)
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+// Windows has distinct file and directory symlinks; a relative target resolves from the link's
+// directory, and a dangling one is created as a file symlink.
+#[cfg(windows)]
+fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    let resolved = link.parent().unwrap_or(Path::new(".")).join(target);
+    if resolved.is_dir() {
+        std::os::windows::fs::symlink_dir(target, link)
+    } else {
+        std::os::windows::fs::symlink_file(target, link)
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn symlink(_target: &Path, _link: &Path) -> io::Result<()> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+fn copy_dir_or_file(from: &Path, to: &Path) -> Result<()> {
+    if from.is_dir() {
+        fs::create_dir_all(to)
+            .with_context(|| format!("Unable to create directory {}", to.display()))?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            // Following links below a copied directory could loop; keep only regular content.
+            if entry.file_type()?.is_symlink() {
+                continue;
+            }
+            copy_dir_or_file(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        Ok(())
+    } else {
+        fs::copy(from, to)
+            .map(|_| ())
+            .with_context(|| format!("Unable to copy {} to {}", from.display(), to.display()))
+    }
+}
+
+// The fixtures create unix symlinks.
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    // Makes `dir` read as a filesystem without symlinks, which tmpfs cannot be turned into.
+    fn without_symlinks(dir: &Path) {
+        fs::create_dir_all(dir).unwrap();
+        SYMLINK_SUPPORT
+            .lock()
+            .unwrap()
+            .get_or_insert_with(HashMap::new)
+            .insert(dir.to_path_buf(), false);
+    }
+
+    fn files(dir: &Path) -> Vec<(PathBuf, String)> {
+        let mut found = Vec::new();
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_symlink() {
+                continue;
+            } else if path.is_dir() {
+                found.extend(files(&path).into_iter().map(|(relative, content)| {
+                    (Path::new(path.file_name().unwrap()).join(relative), content)
+                }));
+            } else {
+                let content = fs::read_to_string(&path).unwrap();
+                found.push((PathBuf::from(path.file_name().unwrap()), content));
+            }
+        }
+        found.sort();
+        found
+    }
+
+    #[test]
+    fn probe_succeeds_on_a_temp_dir_and_cleans_up() {
+        let tmp = TempDir::new("fs-caps-probe");
+        assert!(probe_symlinks(tmp.path()));
+        assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 0);
+        assert!(symlinks_supported(&tmp.path().join("not/created/yet")));
+    }
+
+    #[test]
+    fn link_or_copy_recreates_the_symlink_where_supported() {
+        let tmp = TempDir::new("fs-caps-link");
+        fs::create_dir(tmp.path().join("shared")).unwrap();
+        let from = tmp.path().join("link");
+        std::os::unix::fs::symlink("shared", &from).unwrap();
+        fs::create_dir(tmp.path().join("restored")).unwrap();
+        let to = tmp.path().join("restored/link");
+
+        link_or_copy(&from, &to).unwrap();
+
+        assert_eq!(fs::read_link(&to).unwrap(), Path::new("shared"));
+    }
+
+    #[test]
+    fn link_or_copy_copies_the_target_without_symlinks() {
+        let tmp = TempDir::new("fs-caps-copy");
+        let shared = tmp.path().join("shared");
+        fs::create_dir_all(shared.join("nested")).unwrap();
+        fs::write(shared.join("a.txt"), "a\n").unwrap();
+        fs::write(shared.join("nested/b.txt"), "b\n").unwrap();
+        std::os::unix::fs::symlink(".", shared.join("loop")).unwrap();
+        let from = tmp.path().join("link");
+        std::os::unix::fs::symlink(&shared, &from).unwrap();
+        let restored = tmp.path().join("restored");
+        without_symlinks(&restored);
+
+        link_or_copy(&from, &restored.join("link")).unwrap();
+
+        let copy = restored.join("link");
+        assert!(!copy.is_symlink());
+        assert_eq!(files(&copy), files(&shared));
+        assert!(!copy.join("loop").exists());
+    }
+
+    #[test]
+    fn link_or_copy_skips_a_dangling_link_without_symlinks() {
+        let tmp = TempDir::new("fs-caps-dangling");
+        let from = tmp.path().join("link");
+        std::os::unix::fs::symlink("missing", &from).unwrap();
+        let restored = tmp.path().join("restored");
+        without_symlinks(&restored);
+
+        link_or_copy(&from, &restored.join("link")).unwrap();
+
+        assert!(fs::symlink_metadata(restored.join("link")).is_err());
+    }
+}
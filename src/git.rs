@@ -1,15 +1,162 @@
+use std::collections::{BTreeMap, BTreeSet};
 use std::ffi::OsStr;
-use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, Once, OnceLock};
+use std::time::Duration;
 
 use anyhow::{Context, Result, anyhow};
 
-use crate::config::ConfigState;
+use crate::config::{ConfigState, glob_match};
+use crate::fs_caps;
+use crate::templates;
+use crate::ui::{self, TracedCommand};
+
+// How git is launched: `git_command_prefix` wraps every invocation (e.g. `devcontainer exec`),
+// and `git_path_map` translates local path prefixes to the ones the wrapped git sees.
+#[derive(Debug, Default)]
+struct Invocation {
+    prefix: Vec<String>,
+    path_map: Vec<(PathBuf, PathBuf)>,
+}
+
+impl Invocation {
+    fn new(prefix: &[String], path_map: &BTreeMap<String, String>) -> Self {
+        Self {
+            prefix: prefix.to_vec(),
+            path_map: path_map
+                .iter()
+                .map(|(local, wrapped)| (PathBuf::from(local), PathBuf::from(wrapped)))
+                .collect(),
+        }
+    }
+
+    // With a prefix the working directory is passed as `git -C <dir>` since the wrapper may run
+    // git somewhere else entirely.
+    fn command(&self, dir: Option<&Path>) -> Command {
+        let Some((program, wrapper_args)) = self.prefix.split_first() else {
+            let mut command = Command::new("git");
+            if let Some(dir) = dir {
+                command.current_dir(dir);
+            }
+            return command;
+        };
+        let mut command = Command::new(program);
+        command.args(wrapper_args).arg("git");
+        if let Some(dir) = dir {
+            command.arg("-C").arg(self.map_path(dir));
+        }
+        command
+    }
+
+    fn map_path(&self, path: &Path) -> PathBuf {
+        self.remap(path, |(local, wrapped)| (local, wrapped))
+    }
+
+    fn unmap_path(&self, path: &Path) -> PathBuf {
+        self.remap(path, |(local, wrapped)| (wrapped, local))
+    }
+
+    // The longest matching prefix wins so nested mappings override their parents.
+    fn remap(
+        &self,
+        path: &Path,
+        direction: fn(&(PathBuf, PathBuf)) -> (&PathBuf, &PathBuf),
+    ) -> PathBuf {
+        self.path_map
+            .iter()
+            .map(direction)
+            .filter_map(|(from, to)| path.strip_prefix(from).ok().map(|rest| (from, to, rest)))
+            .max_by_key(|(from, _, _)| from.components().count())
+            .map(|(_, to, rest)| to.join(rest))
+            .unwrap_or_else(|| path.to_path_buf())
+    }
+}
+
+static INVOCATION: OnceLock<Invocation> = OnceLock::new();
+
+// Commands run before `configure_invocation` (repository discovery) use plain git.
+fn invocation() -> &'static Invocation {
+    static PLAIN: Invocation = Invocation {
+        prefix: Vec::new(),
+        path_map: Vec::new(),
+    };
+    INVOCATION.get().unwrap_or(&PLAIN)
+}
+
+// Must run before the first git command; later calls are ignored.
+pub fn configure_invocation(prefix: &[String], path_map: &BTreeMap<String, String>) {
+    let _ = INVOCATION.set(Invocation::new(prefix, path_map));
+}
+
+pub fn uses_command_prefix() -> bool {
+    !invocation().prefix.is_empty()
+}
+
+// Every git subprocess is built here.
+fn git_command(dir: Option<&Path>) -> Command {
+    invocation().command(dir)
+}
+
+// Local path -> path as seen by the wrapped git.
+pub fn map_path(path: &Path) -> PathBuf {
+    invocation().map_path(path)
+}
+
+// Path printed by the wrapped git -> local path.
+pub fn unmap_path(path: &Path) -> PathBuf {
+    invocation().unmap_path(path)
+}
+
+static BYPASS_HOOKS: AtomicBool = AtomicBool::new(false);
+
+// Only merges the tool runs inside agent worktrees skip hooks; merges into the target never do.
+pub fn set_bypass_hooks(bypass: bool) {
+    BYPASS_HOOKS.store(bypass, Ordering::Relaxed);
+}
+
+fn worktree_hook_args(worktree_path: &Path) -> &'static [&'static str] {
+    if !BYPASS_HOOKS.load(Ordering::Relaxed) {
+        return &[];
+    }
+    ui::warning!(
+        "Skipping git hooks for the merge in {}",
+        worktree_path.display()
+    );
+    &["--no-verify"]
+}
+
+// Environment overriding core.hooksPath through GIT_CONFIG_COUNT, appended after any
+// `existing_count` entries already present in the environment.
+pub fn hooks_bypass_env(existing_count: Option<&str>) -> Vec<(String, String)> {
+    let index = existing_count
+        .and_then(|count| count.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+    vec![
+        (
+            format!("GIT_CONFIG_KEY_{}", index),
+            "core.hooksPath".to_string(),
+        ),
+        (
+            format!("GIT_CONFIG_VALUE_{}", index),
+            "/dev/null".to_string(),
+        ),
+        ("GIT_CONFIG_COUNT".to_string(), (index + 1).to_string()),
+    ]
+}
+
+// Directories git refused to work in because of its `safe.directory` ownership check. Collected
+// by `run_git` so the caller can explain the problem once instead of once per command.
+static UNSAFE_DIRECTORIES: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
 
 #[derive(Debug, Clone)]
-pub struct GitRepo {
-    pub root: PathBuf,
-    pub name: String,
+pub struct CliGit {
+    root: PathBuf,
+    name: String,
 }
 
 #[derive(Debug, Clone)]
@@ -17,13 +164,684 @@ pub struct Worktree {
     pub path: PathBuf,
     pub branch: Option<String>,
     pub locked: bool,
+    // Git no longer finds the directory at `path`, usually because it was moved by hand.
+    pub prunable: bool,
+}
+
+// How the branch checked out in a worktree compares with its upstream (as last fetched).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpstreamDivergence {
+    pub upstream: String,
+    pub ahead: usize,
+    pub behind: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommitSummary {
+    pub sha: String,
+    pub subject: String,
+}
+
+// How to continue a cherry-pick stopped on conflicts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CherryPickStep {
+    Continue,
+    Skip,
+    Abort,
+}
+
+impl CherryPickStep {
+    fn flag(self) -> &'static str {
+        match self {
+            CherryPickStep::Continue => "--continue",
+            CherryPickStep::Skip => "--skip",
+            CherryPickStep::Abort => "--abort",
+        }
+    }
+}
+
+// Every stash AgentManager creates carries this prefix in its message, so the stash view can tell
+// them apart from the user's own.
+pub const STASH_MARKER: &str = "agent-manager:";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StashEntry {
+    // `stash@{N}`; shifts when an older entry is dropped.
+    pub reference: String,
+    pub created_at: u64,
+    // Branch the stash was taken on, when git recorded one.
+    pub branch: Option<String>,
+    // Message after the marker.
+    pub message: String,
+}
+
+// Parses `git stash list --format=%gd%x00%ct%x00%s`, keeping the entries whose message starts
+// with `STASH_MARKER`. Subjects look like `On <branch>: <message>` (`WIP on` without -m).
+pub fn parse_marked_stashes(output: &str) -> Vec<StashEntry> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, '\0');
+            let reference = fields.next()?;
+            let created_at = fields.next()?.trim().parse().unwrap_or_default();
+            let subject = fields.next()?;
+            let (branch, message) = match subject
+                .strip_prefix("On ")
+                .or_else(|| subject.strip_prefix("WIP on "))
+                .and_then(|rest| rest.split_once(": "))
+            {
+                Some((branch, message)) => (Some(branch), message),
+                None => (None, subject),
+            };
+            let message = message.strip_prefix(STASH_MARKER)?.trim();
+            Some(StashEntry {
+                reference: reference.to_string(),
+                created_at,
+                branch: branch
+                    .filter(|branch| *branch != "(no branch)")
+                    .map(str::to_string),
+                message: message.to_string(),
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BranchRef {
+    // Local branch name (remote name stripped for remote-tracking refs).
+    pub name: String,
+    // `origin/<name>` when the branch only exists as a remote-tracking ref.
+    pub remote_ref: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangedFile {
+    pub path: String,
+    pub untracked: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeError {
+    SameRef {
+        branch: String,
+    },
+    MissingTarget {
+        target: String,
+    },
+    SourceCheckedOut {
+        branch: String,
+    },
+    // The merge stopped on conflicts and is still in progress in `worktree`.
+    Conflicts {
+        worktree: PathBuf,
+        files: Vec<String>,
+    },
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MergeError::SameRef { branch } => {
+                write!(f, "Refusing to merge {} into itself", branch)
+            }
+            MergeError::MissingTarget { target } => {
+                write!(f, "Merge target {} does not exist", target)
+            }
+            MergeError::SourceCheckedOut { branch } => write!(
+                f,
+                "Branch {} is checked out in the primary worktree",
+                branch
+            ),
+            MergeError::Conflicts { worktree, files } => write!(
+                f,
+                "Merge stopped on conflicts in {} ({} file(s))",
+                worktree.display(),
+                files.len()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MergeError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HealthIssue {
+    MergeInProgress,
+    RebaseInProgress,
+    CherryPickInProgress,
+    StaleIndexLock { path: PathBuf, age: Duration },
+    DetachedHead,
+}
+
+impl HealthIssue {
+    pub fn suggestion(&self) -> &'static str {
+        match self {
+            HealthIssue::MergeInProgress => "git merge --continue or git merge --abort",
+            HealthIssue::RebaseInProgress => "git rebase --continue or git rebase --abort",
+            HealthIssue::CherryPickInProgress => {
+                "git cherry-pick --continue or git cherry-pick --abort"
+            }
+            HealthIssue::StaleIndexLock { .. } => {
+                "remove the lock file if no git process is running"
+            }
+            HealthIssue::DetachedHead => "git switch <branch>",
+        }
+    }
+}
+
+impl fmt::Display for HealthIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HealthIssue::MergeInProgress => f.write_str("A merge is in progress"),
+            HealthIssue::RebaseInProgress => f.write_str("A rebase is in progress"),
+            HealthIssue::CherryPickInProgress => f.write_str("A cherry-pick is in progress"),
+            HealthIssue::StaleIndexLock { path, age } => write!(
+                f,
+                "{} was left behind {} min ago",
+                path.display(),
+                age.as_secs() / 60
+            ),
+            HealthIssue::DetachedHead => f.write_str("The main checkout has a detached HEAD"),
+        }
+    }
+}
+
+const STALE_INDEX_LOCK_AGE: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Clone, Copy)]
+pub enum DiffRange<'a> {
+    AgainstTarget { target: &'a str, branch: &'a str },
+    // Straight from one branch tip to the other, e.g. two attempts at the same task.
+    Between { from: &'a str, to: &'a str },
+    Uncommitted,
+}
+
+// What review comparisons use as their left side.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DiffBase {
+    #[default]
+    MergeTarget,
+    CreationBase,
+}
+
+// Left side of a review comparison. A missing or malformed recorded SHA silently falls back to
+// the merge target.
+pub fn comparison_base(mode: DiffBase, target: &str, recorded_sha: Option<&str>) -> String {
+    match (mode, recorded_sha) {
+        (DiffBase::CreationBase, Some(sha))
+            if (7..=64).contains(&sha.len()) && sha.chars().all(|ch| ch.is_ascii_hexdigit()) =>
+        {
+            sha.to_string()
+        }
+        _ => target.to_string(),
+    }
+}
+
+pub fn diff_command(range: DiffRange<'_>, color: bool) -> Vec<String> {
+    let mut args = vec![
+        "-c".to_string(),
+        format!("color.diff={}", if color { "always" } else { "never" }),
+        "diff".to_string(),
+    ];
+    match range {
+        DiffRange::AgainstTarget { target, branch } => {
+            args.push(format!("{}...{}", target, branch))
+        }
+        DiffRange::Between { from, to } => args.push(format!("{}..{}", from, to)),
+        DiffRange::Uncommitted => args.push("HEAD".to_string()),
+    }
+    args
+}
+
+// Whether merging a branch into its target would stop on conflicts, and in which files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConflictPrediction {
+    Clean,
+    Conflicts(Vec<String>),
+}
+
+// `git merge-tree --write-tree` appeared in git 2.38.
+const WRITE_TREE_VERSION: (u32, u32) = (2, 38);
+
+// (major, minor) from `git version` output such as "git version 2.39.5 (Apple Git-154)".
+pub fn parse_git_version(output: &str) -> Option<(u32, u32)> {
+    let version = output.trim().strip_prefix("git version ")?;
+    let mut parts = version.split(|ch: char| !ch.is_ascii_digit());
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+// `git merge-tree --write-tree --name-only --no-messages`: the tree id, then one line per
+// conflicted file. Exit status 1 means conflicts, unless no tree was written (git also exits with
+// 1 for a revision it cannot resolve); anything but 0 or 1 is a failure.
+pub fn parse_merge_tree(stdout: &str, exit_code: Option<i32>) -> Option<ConflictPrediction> {
+    match exit_code? {
+        0 => Some(ConflictPrediction::Clean),
+        1 => {
+            let mut lines = stdout.lines();
+            lines
+                .next()
+                .filter(|tree| !tree.is_empty() && tree.chars().all(|ch| ch.is_ascii_hexdigit()))?;
+            let mut files = lines
+                .take_while(|line| !line.is_empty())
+                .map(str::to_string)
+                .collect::<Vec<_>>();
+            files.dedup();
+            Some(ConflictPrediction::Conflicts(files))
+        }
+        _ => None,
+    }
+}
+
+// Three-argument `git merge-tree <base> <ours> <theirs>`: sections such as "changed in both",
+// each listing the file's versions ("  our    <mode> <oid> <path>") and then a diff of the merge
+// result, where conflict markers show as added lines.
+pub fn parse_legacy_merge_tree(stdout: &str) -> ConflictPrediction {
+    let mut files: Vec<String> = Vec::new();
+    let mut path = None;
+    for line in stdout.lines() {
+        if line.starts_with(|ch: char| ch.is_ascii_alphabetic()) {
+            path = None;
+        } else if let Some(version) = ["  base ", "  our ", "  their "]
+            .iter()
+            .find_map(|prefix| line.strip_prefix(prefix))
+        {
+            path = version
+                .trim_start()
+                .splitn(3, ' ')
+                .nth(2)
+                .map(str::to_string);
+        } else if line.starts_with("+<<<<<<< ")
+            && let Some(path) = &path
+            && !files.contains(path)
+        {
+            files.push(path.clone());
+        }
+    }
+    if files.is_empty() {
+        ConflictPrediction::Clean
+    } else {
+        ConflictPrediction::Conflicts(files)
+    }
+}
+
+// Files two branches changed relative to the same target, split by who touched them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileOverlap {
+    pub only_first: Vec<String>,
+    pub both: Vec<String>,
+    pub only_second: Vec<String>,
+}
+
+impl FileOverlap {
+    pub fn compute(first: &[String], second: &[String]) -> Self {
+        let first = first.iter().collect::<BTreeSet<_>>();
+        let second = second.iter().collect::<BTreeSet<_>>();
+        Self {
+            only_first: first
+                .difference(&second)
+                .map(|file| file.to_string())
+                .collect(),
+            both: first
+                .intersection(&second)
+                .map(|file| file.to_string())
+                .collect(),
+            only_second: second
+                .difference(&first)
+                .map(|file| file.to_string())
+                .collect(),
+        }
+    }
+}
+
+pub trait GitBackend {
+    fn root(&self) -> &Path;
+    fn name(&self) -> &str;
+
+    fn common_git_dir(&self) -> Result<PathBuf>;
+
+    fn list_worktrees(&self) -> Result<Vec<Worktree>>;
+
+    fn create_worktree(
+        &self,
+        branch_name: &str,
+        target_dir: &Path,
+        base_branch: &str,
+        no_checkout: bool,
+    ) -> Result<()>;
+
+    fn add_worktree_for_branch(&self, branch: &str, target_dir: &Path) -> Result<()>;
+
+    fn prune_worktrees(&self) -> Result<()>;
+
+    // Writes a git bundle with the full history of `branch`.
+    fn bundle_branch(&self, branch: &str, bundle: &Path) -> Result<()>;
+
+    // Creates local `branch` from the same branch in `bundle`.
+    fn fetch_bundle(&self, bundle: &Path, branch: &str) -> Result<()>;
+
+    // Uncommitted changes of `worktree_path`, untracked files included, as a binary patch against
+    // HEAD, leaving out the `excluded` files. The worktree's own index is left untouched.
+    fn uncommitted_patch(&self, worktree_path: &Path, excluded: &[&str]) -> Result<Vec<u8>>;
+
+    fn apply_patch(&self, worktree_path: &Path, patch: &Path) -> Result<()>;
+
+    // Points git back at worktrees moved to `paths`; returns what git reports as fixed.
+    fn repair_worktrees(&self, paths: &[PathBuf]) -> Result<String>;
+
+    fn top_level_dirs(&self, revision: &str) -> Result<Vec<String>>;
+
+    // Total size in bytes of the blobs a checkout of `revision` (limited to `paths` if any) writes.
+    fn tree_size(&self, revision: &str, paths: &[String]) -> Result<u64>;
+
+    fn sparse_checkout_init(&self, worktree_path: &Path) -> Result<()>;
+
+    fn sparse_checkout_set(&self, worktree_path: &Path, paths: &[String]) -> Result<()>;
+
+    fn checkout_worktree(&self, worktree_path: &Path) -> Result<()>;
+
+    fn init_submodules(&self, worktree_path: &Path) -> Result<()>;
+
+    fn tracked_files(&self, worktree_path: &Path) -> Result<Vec<String>>;
+
+    fn is_tracked(&self, worktree_path: &Path, file: &str) -> Result<bool>;
+
+    fn remove_worktree(&self, target_dir: &Path, force: bool) -> Result<()>;
+
+    // Changes to paths matching the `ignore` pathspecs do not count.
+    fn is_worktree_dirty(&self, worktree_path: &Path, ignore: &[String]) -> Result<bool>;
+
+    // Files of `worktree_path` that differ from `base`, committed or not, plus untracked files.
+    fn changed_files(&self, worktree_path: &Path, base: &str) -> Result<Vec<ChangedFile>>;
+
+    // Diff of the working tree of `worktree_path` against `base`, without context lines.
+    fn working_diff(&self, worktree_path: &Path, base: &str) -> Result<String>;
+
+    fn delete_branch(&self, branch: &str, force: bool) -> Result<()>;
+
+    fn merge_branch(&self, source_branch: &str, target_branch: &str) -> Result<()>;
+
+    // Merges `source_branch` into the branch checked out in `worktree_path`, leaving the primary
+    // checkout untouched. Conflicts are left in progress and reported as `MergeError::Conflicts`.
+    fn merge_branch_in(&self, worktree_path: &Path, source_branch: &str) -> Result<()>;
+
+    // Merges `source_branch` into `target_branch` in the primary checkout without committing, so
+    // the result can be reviewed first. Conflicts are left in place for the user to resolve.
+    fn merge_no_commit(&self, source_branch: &str, target_branch: &str) -> Result<()>;
+
+    // Concludes a merge started with `merge_no_commit`.
+    fn commit_merge(&self, message: &str) -> Result<()>;
+
+    fn unmerged_paths(&self, worktree_path: &Path) -> Result<Vec<String>>;
+
+    fn staged_diffstat(&self) -> Result<String>;
+
+    fn branch_exists(&self, branch: &str) -> Result<bool>;
+
+    fn merge_base(&self, first: &str, second: &str) -> Result<String>;
+
+    // Full commit SHA of `rev`, or None when it does not name a commit.
+    fn rev_parse(&self, rev: &str) -> Result<Option<String>>;
+
+    fn create_branch(&self, branch: &str, start_point: &str) -> Result<()>;
+
+    fn remote_branch_exists(&self, remote: &str, branch: &str) -> Result<bool>;
+
+    fn create_tracking_branch(&self, branch: &str, upstream: &str) -> Result<()>;
+
+    // Local branches whose name matches the glob `pattern`, plus remote-tracking branches
+    // without a local counterpart when `include_remote` is set.
+    fn branches_matching(&self, pattern: &str, include_remote: bool) -> Result<Vec<BranchRef>>;
+
+    fn fetch(&self, remote: &str, timeout_secs: Option<u64>) -> Result<()>;
+
+    fn commits_behind(&self, branch: &str, target: &str) -> Result<usize>;
+
+    fn is_merged_into(&self, branch: &str, target: &str) -> Result<bool>;
+
+    fn has_branch_activity(&self, branch: &str) -> Result<bool>;
+
+    // Rebases the branch checked out in `worktree_path` onto `target`, aborting on conflicts.
+    fn rebase_onto(&self, worktree_path: &Path, target: &str) -> Result<()>;
+
+    // Like `rebase_onto`, but conflicts are left in progress and reported as
+    // `MergeError::Conflicts`.
+    fn rebase_in(&self, worktree_path: &Path, target: &str) -> Result<()>;
+
+    fn abort_rebase_in(&self, worktree_path: &Path) -> Result<()>;
+
+    fn merge_in_progress(&self, worktree_path: &Path) -> Result<bool>;
+
+    fn rebase_in_progress(&self, worktree_path: &Path) -> Result<bool>;
+
+    // Commits a merge whose conflicts were resolved, with git's prepared message.
+    fn conclude_merge_in(&self, worktree_path: &Path) -> Result<()>;
+
+    // Fetches the upstream of `branch` and fast-forwards the branch to it. Returns false when the
+    // branch has no upstream; a branch that diverged from it is an error.
+    fn fast_forward_from_upstream(&self, branch: &str, timeout_secs: Option<u64>) -> Result<bool>;
+
+    // None when the worktree's branch has no upstream configured.
+    fn upstream_divergence(&self, worktree_path: &Path) -> Result<Option<UpstreamDivergence>>;
+
+    // Subjects of the commits in `range` (e.g. `HEAD..@{u}`), newest first.
+    fn commit_subjects(&self, worktree_path: &Path, range: &str) -> Result<Vec<String>>;
+
+    // Author emails of the newest `limit` commits in `range`, newest first.
+    fn commit_authors(
+        &self,
+        worktree_path: &Path,
+        range: &str,
+        limit: usize,
+    ) -> Result<Vec<String>>;
+
+    fn merge_into_worktree(&self, worktree_path: &Path, source: &str) -> Result<()>;
+
+    // Commits reachable from `tip` but not from `base`, newest first.
+    fn commits_between(&self, base: &str, tip: &str) -> Result<Vec<CommitSummary>>;
+
+    // `git show --stat --patch` of a single commit, for previews.
+    fn show_commit(&self, commit: &str) -> Result<String>;
+
+    // Cherry-picks `commit` onto the branch checked out in `worktree_path`. Ok(false) when it
+    // stopped on conflicts; the cherry-pick is then left in progress for `cherry_pick_step`.
+    fn cherry_pick(&self, worktree_path: &Path, commit: &str) -> Result<bool>;
+
+    // Resumes or ends a stopped cherry-pick. Ok(false) when `--continue` still has conflicts.
+    fn cherry_pick_step(&self, worktree_path: &Path, step: CherryPickStep) -> Result<bool>;
+
+    // Stashes whose message starts with `STASH_MARKER`, newest first.
+    fn list_marked_stashes(&self) -> Result<Vec<StashEntry>>;
+
+    fn apply_stash(&self, worktree_path: &Path, reference: &str) -> Result<()>;
+
+    fn drop_stash(&self, reference: &str) -> Result<()>;
+
+    fn stash_diff(&self, reference: &str, color: bool) -> Result<Vec<u8>>;
+
+    fn config_value(&self, key: &str) -> Result<Option<String>>;
+
+    fn enable_worktree_config(&self) -> Result<()>;
+
+    fn set_worktree_config(&self, worktree_path: &Path, key: &str, value: &str) -> Result<()>;
+
+    fn current_branch_in(&self, worktree_path: &Path) -> Result<Option<String>>;
+
+    fn checkout_branch(&self, branch: &str) -> Result<()>;
+
+    fn switch_branch_in(&self, worktree_path: &Path, branch: &str) -> Result<()>;
+
+    // Stages `file` (relative to the repository root) and commits it on the checked-out branch.
+    fn commit_file(&self, file: &str, message: &str) -> Result<()>;
+
+    fn diff(&self, worktree_path: &Path, range: DiffRange<'_>, color: bool) -> Result<Vec<u8>>;
+
+    // What merging `branch` into `target` would run into, computed without touching any
+    // checkout. None when the installed git cannot tell.
+    fn predict_conflicts(&self, target: &str, branch: &str) -> Result<Option<ConflictPrediction>>;
+
+    // `git diff --stat from..to`.
+    fn diff_stat(&self, from: &str, to: &str) -> Result<String>;
+
+    // Files `branch` changed since it diverged from `target`.
+    fn branch_files(&self, target: &str, branch: &str) -> Result<Vec<String>>;
+
+    // Problems in the main checkout that make merges and checkouts fail in confusing ways.
+    fn health_check(&self) -> Result<Vec<HealthIssue>>;
+
+    fn abort_merge_in(&self, worktree_path: &Path) -> Result<()>;
+
+    fn abort_merge(&self) -> Result<()> {
+        self.abort_merge_in(self.root())
+    }
+
+    // Adds `path` to the global `safe.directory` list so git stops rejecting it.
+    fn mark_safe_directory(&self, path: &Path) -> Result<()>;
+
+    fn default_worktree_base(&self) -> Result<PathBuf> {
+        let parent = self
+            .root()
+            .parent()
+            .ok_or_else(|| anyhow!("Unable to resolve the repository parent directory"))?;
+        Ok(parent.join(format!("{}-worktree-agents", self.name())))
+    }
+
+    fn worktree_base(&self, cfg: &ConfigState) -> Result<PathBuf> {
+        let parent = self
+            .root()
+            .parent()
+            .ok_or_else(|| anyhow!("Unable to resolve the repository parent directory"))?;
+        let base = if let Some(pattern) = &cfg.config.worktree_base_override {
+            let mut rendered = pattern
+                .replace("{repo_name}", self.name())
+                .replace("{repo_root}", &self.root().to_string_lossy());
+            // A shared base would let two repositories pick the same directory for a branch.
+            if !pattern.contains("{repo_name}") && !pattern.contains("{repo_root}") {
+                static NAMESPACE_NOTICE: Once = Once::new();
+                NAMESPACE_NOTICE.call_once(|| {
+                    ui::warning!(
+                        "worktree_base_override has no {{repo_name}} or {{repo_root}}; using {}/{} to keep repositories apart.",
+                        pattern.trim_end_matches(['/', '\\']),
+                        self.name()
+                    );
+                });
+                rendered = format!("{}/{}", rendered.trim_end_matches(['/', '\\']), self.name());
+            }
+            let resolved = resolve_base_path(&rendered, parent)?;
+            if resolved.exists() {
+                resolved.canonicalize().with_context(|| {
+                    format!(
+                        "Unable to canonicalize worktree base {}",
+                        resolved.display()
+                    )
+                })?
+            } else {
+                resolved
+            }
+        } else {
+            self.default_worktree_base()?
+        };
+
+        if base == self.root() {
+            return Err(anyhow!(
+                "Worktree base {} is the repository root; adjust worktree_base_override",
+                base.display()
+            ));
+        }
+        for worktree in self.list_worktrees()? {
+            if base.starts_with(&worktree.path) {
+                return Err(anyhow!(
+                    "Worktree base {} is inside the existing worktree {}; adjust worktree_base_override",
+                    base.display(),
+                    worktree.path.display()
+                ));
+            }
+        }
+
+        // Probed once per session; warns when symlinks are unavailable there.
+        fs_caps::symlinks_supported(&base);
+        Ok(base)
+    }
+
+    // Resolves a base directory typed at a prompt the same way as worktree_base_override (relative
+    // to the repository parent, with ~ expanded) and checks that worktrees can be created there.
+    fn alternate_worktree_base(&self, typed: &str) -> Result<PathBuf> {
+        let parent = self
+            .root()
+            .parent()
+            .ok_or_else(|| anyhow!("Unable to resolve the repository parent directory"))?;
+        let base = resolve_base_path(typed, parent)?;
+        if base.exists() && !base.is_dir() {
+            return Err(anyhow!("{} is not a directory", base.display()));
+        }
+        let existing = base
+            .ancestors()
+            .find(|candidate| candidate.is_dir())
+            .ok_or_else(|| anyhow!("No existing parent directory for {}", base.display()))?;
+        if !directory_writable(existing) {
+            return Err(anyhow!("{} is not writable", existing.display()));
+        }
+        if base == self.root()
+            || self
+                .list_worktrees()?
+                .iter()
+                .any(|w| base.starts_with(&w.path))
+        {
+            return Err(anyhow!(
+                "{} is inside the repository or one of its worktrees",
+                base.display()
+            ));
+        }
+        Ok(base)
+    }
+
+    fn check_merge(
+        &self,
+        source_branch: &str,
+        target_branch: &str,
+        current: Option<&str>,
+    ) -> Result<(), MergeError> {
+        let source = short_branch_name(source_branch);
+        let target = short_branch_name(target_branch);
+        if source == target {
+            return Err(MergeError::SameRef {
+                branch: source.to_string(),
+            });
+        }
+        if !self.branch_exists(target).unwrap_or(false) {
+            return Err(MergeError::MissingTarget {
+                target: target.to_string(),
+            });
+        }
+        if current == Some(source) {
+            return Err(MergeError::SourceCheckedOut {
+                branch: source.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    fn current_branch(&self) -> Result<Option<String>> {
+        self.current_branch_in(self.root())
+    }
 }
 
-impl GitRepo {
+impl CliGit {
     pub fn discover() -> Result<Self> {
-        let output = Command::new("git")
+        let cwd = std::env::current_dir().ok();
+        Self::open_in(cwd.as_deref())
+    }
+
+    /// The repository containing `dir`.
+    pub fn open(dir: &Path) -> Result<Self> {
+        Self::open_in(Some(dir))
+    }
+
+    fn open_in(dir: Option<&Path>) -> Result<Self> {
+        let output = git_command(dir)
             .args(["rev-parse", "--show-toplevel"])
-            .output()
+            .traced_output()
             .context(
                 "Unable to resolve the current git repository (git rev-parse --show-toplevel)",
             )?;
@@ -37,7 +855,7 @@ impl GitRepo {
 
         let mut path = String::from_utf8(output.stdout)?;
         path.truncate(path.trim_end().len());
-        let root = PathBuf::from(path);
+        let root = unmap_path(Path::new(&path));
         let name = root
             .file_name()
             .ok_or_else(|| anyhow!("Repository name could not be determined"))?
@@ -46,23 +864,34 @@ impl GitRepo {
 
         Ok(Self { root, name })
     }
+}
 
-    pub fn worktree_base(&self, cfg: &ConfigState) -> Result<PathBuf> {
-        if let Some(pattern) = &cfg.config.worktree_base_override {
-            let rendered = pattern
-                .replace("{repo_name}", &self.name)
-                .replace("{repo_root}", &self.root.to_string_lossy());
-            Ok(PathBuf::from(rendered))
-        } else {
-            let parent = self
-                .root
-                .parent()
-                .ok_or_else(|| anyhow!("Unable to resolve the repository parent directory"))?;
-            Ok(parent.join(format!("{}-worktree-agents", self.name)))
+impl GitBackend for CliGit {
+    fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn common_git_dir(&self) -> Result<PathBuf> {
+        let output = run_git(&self.root, ["rev-parse", "--git-common-dir"])?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Unable to locate the git directory of {}",
+                self.root.display()
+            ));
         }
+        let dir = unmap_path(Path::new(String::from_utf8_lossy(&output.stdout).trim()));
+        Ok(if dir.is_relative() {
+            self.root.join(dir)
+        } else {
+            dir
+        })
     }
 
-    pub fn list_worktrees(&self) -> Result<Vec<Worktree>> {
+    fn list_worktrees(&self) -> Result<Vec<Worktree>> {
         let output = run_git(&self.root, ["worktree", "list", "--porcelain"])?;
         if !output.status.success() {
             return Err(anyhow!(
@@ -75,6 +904,7 @@ impl GitRepo {
         let mut current_path: Option<PathBuf> = None;
         let mut current_branch: Option<String> = None;
         let mut locked = false;
+        let mut prunable = false;
 
         for line in text.lines() {
             if line.is_empty() {
@@ -83,18 +913,22 @@ impl GitRepo {
                         path,
                         branch: current_branch.take(),
                         locked,
+                        prunable,
                     });
                     locked = false;
+                    prunable = false;
                 }
                 continue;
             }
 
             if let Some(rest) = line.strip_prefix("worktree ") {
-                current_path = Some(PathBuf::from(rest));
+                current_path = Some(unmap_path(Path::new(rest)));
             } else if let Some(rest) = line.strip_prefix("branch ") {
                 current_branch = Some(rest.trim().replacen("refs/heads/", "", 1));
             } else if line.starts_with("locked") {
                 locked = true;
+            } else if line.starts_with("prunable") {
+                prunable = true;
             }
         }
 
@@ -103,28 +937,32 @@ impl GitRepo {
                 path,
                 branch: current_branch,
                 locked,
+                prunable,
             });
         }
 
         Ok(worktrees)
     }
 
-    pub fn create_worktree(
+    fn create_worktree(
         &self,
         branch_name: &str,
         target_dir: &Path,
         base_branch: &str,
+        no_checkout: bool,
     ) -> Result<()> {
-        let status = Command::new("git")
-            .current_dir(&self.root)
-            .arg("worktree")
-            .arg("add")
+        let mut command = git_command(Some(&self.root));
+        command.args(["worktree", "add"]);
+        if no_checkout {
+            command.arg("--no-checkout");
+        }
+        command
             .arg("-b")
             .arg(branch_name)
-            .arg(target_dir)
-            .arg(base_branch)
-            .status()
-            .with_context(|| {
+            .arg(map_path(target_dir))
+            .arg(base_branch);
+        let status =
+            ui::run_with_progress("Creating worktree", &mut command).with_context(|| {
                 format!(
                     "Failed to run git worktree add for {} from {}",
                     target_dir.display(),
@@ -142,31 +980,293 @@ impl GitRepo {
         Ok(())
     }
 
-    pub fn remove_worktree(&self, target_dir: &Path, force: bool) -> Result<()> {
-        let mut command = Command::new("git");
-        command.current_dir(&self.root).args(["worktree", "remove"]);
-        if force {
-            command.arg("--force");
-        }
-        command.arg(target_dir);
-
-        let status = command.status().with_context(|| {
-            format!("Failed to run git worktree remove {}", target_dir.display())
-        })?;
+    fn add_worktree_for_branch(&self, branch: &str, target_dir: &Path) -> Result<()> {
+        let status = git_command(Some(&self.root))
+            .args(["worktree", "add"])
+            .arg(map_path(target_dir))
+            .arg(branch)
+            .traced_status()
+            .with_context(|| format!("Failed to run git worktree add for {}", branch))?;
         if !status.success() {
             return Err(anyhow!(
-                "git worktree remove failed for {}",
-                target_dir.display()
+                "git worktree add returned a non zero status for branch {}",
+                branch
+            ));
+        }
+        Ok(())
+    }
+
+    fn prune_worktrees(&self) -> Result<()> {
+        let output = run_git(&self.root, ["worktree", "prune"])?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git worktree prune failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(())
+    }
+
+    fn bundle_branch(&self, branch: &str, bundle: &Path) -> Result<()> {
+        let output = run_git(
+            &self.root,
+            [
+                OsStr::new("bundle"),
+                OsStr::new("create"),
+                map_path(bundle).as_os_str(),
+                OsStr::new(branch),
+            ],
+        )?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git bundle create failed for {}: {}",
+                branch,
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(())
+    }
+
+    fn fetch_bundle(&self, bundle: &Path, branch: &str) -> Result<()> {
+        let refspec = format!("refs/heads/{0}:refs/heads/{0}", branch);
+        let output = run_git(
+            &self.root,
+            [
+                OsStr::new("fetch"),
+                map_path(bundle).as_os_str(),
+                OsStr::new(&refspec),
+            ],
+        )?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git fetch from bundle {} failed: {}",
+                bundle.display(),
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(())
+    }
+
+    fn uncommitted_patch(&self, worktree_path: &Path, excluded: &[&str]) -> Result<Vec<u8>> {
+        let index = templates::git_dir_for_worktree(worktree_path)?.join("handoff-index");
+        let run = |args: &[String]| -> Result<Vec<u8>> {
+            let output = git_command(Some(worktree_path))
+                .env("GIT_INDEX_FILE", map_path(&index))
+                .args(args)
+                .traced_output()
+                .with_context(|| format!("Failed to run git {}", args.join(" ")))?;
+            if !output.status.success() {
+                return Err(anyhow!(
+                    "git {} failed in {}: {}",
+                    args.join(" "),
+                    worktree_path.display(),
+                    String::from_utf8_lossy(&output.stderr)
+                ));
+            }
+            Ok(output.stdout)
+        };
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        let mut add = args(&["add", "-A", "--", "."]);
+        add.extend(excluded.iter().map(|file| format!(":(exclude){}", file)));
+        let patch = run(&args(&["read-tree", "HEAD"]))
+            .and_then(|_| run(&add))
+            .and_then(|_| run(&args(&["diff", "--cached", "--binary", "HEAD"])));
+        let _ = fs::remove_file(&index);
+        patch
+    }
+
+    fn apply_patch(&self, worktree_path: &Path, patch: &Path) -> Result<()> {
+        let output = run_git(
+            worktree_path,
+            [
+                OsStr::new("apply"),
+                OsStr::new("--binary"),
+                map_path(patch).as_os_str(),
+            ],
+        )?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git apply failed in {}: {}",
+                worktree_path.display(),
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(())
+    }
+
+    fn repair_worktrees(&self, paths: &[PathBuf]) -> Result<String> {
+        let output = run_git(
+            &self.root,
+            ["worktree", "repair"]
+                .into_iter()
+                .map(PathBuf::from)
+                .chain(paths.iter().map(|path| map_path(path))),
+        )?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git worktree repair failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        // git reports each fix on stderr.
+        Ok(format!(
+            "{}{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        ))
+    }
+
+    fn top_level_dirs(&self, revision: &str) -> Result<Vec<String>> {
+        let output = run_git(&self.root, ["ls-tree", "-d", "--name-only", revision])?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git ls-tree failed for {}: {}",
+                revision,
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        let text = String::from_utf8(output.stdout)?;
+        Ok(text
+            .lines()
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect())
+    }
+
+    fn tree_size(&self, revision: &str, paths: &[String]) -> Result<u64> {
+        let mut args = vec![
+            "ls-tree".to_string(),
+            "-r".to_string(),
+            "-l".to_string(),
+            revision.to_string(),
+        ];
+        if !paths.is_empty() {
+            args.push("--".to_string());
+            args.extend(paths.iter().cloned());
+        }
+        let output = run_git(&self.root, &args)?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git ls-tree failed for {}: {}",
+                revision,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        // `<mode> <type> <object> <size>\t<path>`; submodule entries report `-` as size.
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| line.split('\t').next()?.split_whitespace().nth(3))
+            .filter_map(|size| size.parse::<u64>().ok())
+            .sum())
+    }
+
+    fn sparse_checkout_init(&self, worktree_path: &Path) -> Result<()> {
+        let output = run_git(worktree_path, ["sparse-checkout", "init", "--cone"])?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git sparse-checkout init failed in {}: {}",
+                worktree_path.display(),
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(())
+    }
+
+    fn sparse_checkout_set(&self, worktree_path: &Path, paths: &[String]) -> Result<()> {
+        let mut args = vec!["sparse-checkout".to_string(), "set".to_string()];
+        args.extend(paths.iter().cloned());
+        let output = run_git(worktree_path, &args)?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git sparse-checkout set failed in {}: {}",
+                worktree_path.display(),
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(())
+    }
+
+    fn checkout_worktree(&self, worktree_path: &Path) -> Result<()> {
+        let status = ui::run_with_progress(
+            "Checking out files",
+            git_command(Some(worktree_path)).arg("checkout"),
+        )
+        .with_context(|| format!("Failed to run git checkout in {}", worktree_path.display()))?;
+        if !status.success() {
+            return Err(anyhow!(
+                "git checkout failed in {}",
+                worktree_path.display()
+            ));
+        }
+        Ok(())
+    }
+
+    fn init_submodules(&self, worktree_path: &Path) -> Result<()> {
+        let status = ui::run_with_progress(
+            "Initializing submodules",
+            git_command(Some(worktree_path)).args(["submodule", "update", "--init", "--recursive"]),
+        )
+        .with_context(|| {
+            format!(
+                "Failed to run git submodule update in {}",
+                worktree_path.display()
+            )
+        })?;
+        if !status.success() {
+            return Err(anyhow!(
+                "git submodule update failed in {}",
+                worktree_path.display()
+            ));
+        }
+        Ok(())
+    }
+
+    fn tracked_files(&self, worktree_path: &Path) -> Result<Vec<String>> {
+        let output = run_git(worktree_path, ["ls-files", "-z"])?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git ls-files failed in {}: {}",
+                worktree_path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .split('\0')
+            .filter(|file| !file.is_empty())
+            .map(|file| file.to_string())
+            .collect())
+    }
+
+    fn is_tracked(&self, worktree_path: &Path, file: &str) -> Result<bool> {
+        let output = run_git(worktree_path, ["ls-files", "--error-unmatch", "--", file])?;
+        Ok(output.status.success())
+    }
+
+    fn remove_worktree(&self, target_dir: &Path, force: bool) -> Result<()> {
+        let mut command = git_command(Some(&self.root));
+        command.args(["worktree", "remove"]);
+        if force {
+            command.arg("--force");
+        }
+        command.arg(map_path(target_dir));
+
+        let status = command.traced_status().with_context(|| {
+            format!("Failed to run git worktree remove {}", target_dir.display())
+        })?;
+        if !status.success() {
+            return Err(anyhow!(
+                "git worktree remove failed for {}",
+                target_dir.display()
             ));
         }
         Ok(())
     }
 
-    pub fn is_worktree_dirty(&self, worktree_path: &Path) -> Result<bool> {
-        let output = Command::new("git")
-            .current_dir(worktree_path)
+    fn is_worktree_dirty(&self, worktree_path: &Path, ignore: &[String]) -> Result<bool> {
+        let output = git_command(Some(worktree_path))
             .args(["status", "--porcelain"])
-            .output()
+            .args(status_pathspecs(ignore))
+            .traced_output()
             .with_context(|| {
                 format!(
                     "Failed to check worktree status in {}",
@@ -185,12 +1285,11 @@ impl GitRepo {
         Ok(!status.trim().is_empty())
     }
 
-    pub fn delete_branch(&self, branch: &str, force: bool) -> Result<()> {
+    fn delete_branch(&self, branch: &str, force: bool) -> Result<()> {
         let flag = if force { "-D" } else { "-d" };
-        let status = Command::new("git")
-            .current_dir(&self.root)
+        let status = git_command(Some(&self.root))
             .args(["branch", flag, branch])
-            .status()
+            .traced_status()
             .context("Failed to run git branch -d")?;
         if !status.success() {
             return Err(anyhow!("Unable to delete branch {}", branch));
@@ -198,19 +1297,34 @@ impl GitRepo {
         Ok(())
     }
 
-    pub fn merge_branch(&self, source_branch: &str, target_branch: &str) -> Result<()> {
+    fn merge_branch(&self, source_branch: &str, target_branch: &str) -> Result<()> {
         let current = self.current_branch()?;
+        self.check_merge(source_branch, target_branch, current.as_deref())?;
         if current.as_deref() != Some(target_branch) {
             self.checkout_branch(target_branch)?;
         }
 
-        let status = Command::new("git")
-            .current_dir(&self.root)
+        let status = git_command(Some(&self.root))
             .args(["merge", "--no-ff", source_branch])
-            .status()
+            .traced_status()
             .with_context(|| format!("Failed to merge {} into {}", source_branch, target_branch))?;
 
         if !status.success() {
+            // Conflicts stay in progress on the target; anything else is undone.
+            let files = self.unmerged_paths(&self.root)?;
+            if !files.is_empty() {
+                return Err(MergeError::Conflicts {
+                    worktree: self.root.clone(),
+                    files,
+                }
+                .into());
+            }
+            let _ = run_git(&self.root, ["merge", "--abort"]);
+            if current.as_deref() != Some(target_branch)
+                && let Some(branch) = &current
+            {
+                self.checkout_branch(branch)?;
+            }
             return Err(anyhow!(
                 "git merge failed while merging {} into {}",
                 source_branch,
@@ -218,51 +1332,2832 @@ impl GitRepo {
             ));
         }
 
-        if current.as_deref() != Some(target_branch) {
-            if let Some(branch) = current {
-                self.checkout_branch(&branch)?;
-            }
+        if current.as_deref() != Some(target_branch)
+            && let Some(branch) = current
+        {
+            self.checkout_branch(&branch)?;
         }
 
         Ok(())
     }
 
-    pub fn current_branch(&self) -> Result<Option<String>> {
-        let output = run_git(&self.root, ["rev-parse", "--abbrev-ref", "HEAD"])?;
+    fn branch_exists(&self, branch: &str) -> Result<bool> {
+        let reference = format!("refs/heads/{}", short_branch_name(branch));
+        let output = run_git(&self.root, ["rev-parse", "--verify", "--quiet", &reference])?;
+        Ok(output.status.success())
+    }
+
+    fn merge_base(&self, first: &str, second: &str) -> Result<String> {
+        let output = run_git(&self.root, ["merge-base", first, second])?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git merge-base {} {} failed: {}",
+                first,
+                second,
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(String::from_utf8(output.stdout)?.trim().to_string())
+    }
+
+    fn rev_parse(&self, rev: &str) -> Result<Option<String>> {
+        let spec = format!("{}^{{commit}}", rev);
+        let output = run_git(&self.root, ["rev-parse", "--verify", "--quiet", &spec])?;
         if !output.status.success() {
             return Ok(None);
         }
-        let name = String::from_utf8(output.stdout)?;
-        let trimmed = name.trim();
-        if trimmed == "HEAD" {
-            Ok(None)
-        } else {
-            Ok(Some(trimmed.to_string()))
+        Ok(Some(String::from_utf8(output.stdout)?.trim().to_string()))
+    }
+
+    fn create_branch(&self, branch: &str, start_point: &str) -> Result<()> {
+        let output = run_git(&self.root, ["branch", branch, start_point])?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Unable to create branch {} from {}: {}",
+                branch,
+                start_point,
+                String::from_utf8_lossy(&output.stderr)
+            ));
         }
+        Ok(())
     }
 
-    pub fn checkout_branch(&self, branch: &str) -> Result<()> {
-        let status = Command::new("git")
-            .current_dir(&self.root)
-            .args(["checkout", branch])
-            .status()
-            .with_context(|| format!("Failed to run git checkout {}", branch))?;
-        if !status.success() {
-            return Err(anyhow!("Unable to checkout branch {}", branch));
+    fn remote_branch_exists(&self, remote: &str, branch: &str) -> Result<bool> {
+        let reference = format!("refs/remotes/{}/{}", remote, short_branch_name(branch));
+        let output = run_git(&self.root, ["rev-parse", "--verify", "--quiet", &reference])?;
+        Ok(output.status.success())
+    }
+
+    fn branches_matching(&self, pattern: &str, include_remote: bool) -> Result<Vec<BranchRef>> {
+        let mut args = vec!["for-each-ref", "--format=%(refname)", "refs/heads"];
+        if include_remote {
+            args.push("refs/remotes");
+        }
+        let output = run_git(&self.root, args)?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git for-each-ref failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(matching_branches(
+            &String::from_utf8(output.stdout)?,
+            pattern,
+        ))
+    }
+
+    fn fetch(&self, remote: &str, timeout_secs: Option<u64>) -> Result<()> {
+        let mut command = git_command(Some(&self.root));
+        command.args(["fetch", "--progress", remote]);
+        run_network_git(command, &format!("git fetch {}", remote), timeout_secs)
+    }
+
+    fn create_tracking_branch(&self, branch: &str, upstream: &str) -> Result<()> {
+        let output = run_git(&self.root, ["branch", "--track", branch, upstream])?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Unable to create branch {} tracking {}: {}",
+                branch,
+                upstream,
+                String::from_utf8_lossy(&output.stderr)
+            ));
         }
         Ok(())
     }
-}
 
-fn run_git<S>(root: &Path, args: impl IntoIterator<Item = S>) -> Result<std::process::Output>
-where
-    S: AsRef<OsStr>,
-{
-    let output = Command::new("git")
-        .current_dir(root)
-        .args(args)
-        .output()
-        .with_context(|| format!("Failed to execute git in {}", root.display()))?;
+    fn is_merged_into(&self, branch: &str, target: &str) -> Result<bool> {
+        let output = run_git(&self.root, ["merge-base", "--is-ancestor", branch, target])?;
+        match output.status.code() {
+            Some(0) => Ok(true),
+            Some(1) => Ok(false),
+            _ => Err(anyhow!(
+                "git merge-base --is-ancestor {} {} failed: {}",
+                branch,
+                target,
+                String::from_utf8_lossy(&output.stderr)
+            )),
+        }
+    }
 
-    Ok(output)
+    // A branch whose reflog only holds its creation entry never received commits.
+    fn has_branch_activity(&self, branch: &str) -> Result<bool> {
+        let reference = format!("refs/heads/{}", short_branch_name(branch));
+        let output = run_git(&self.root, ["reflog", "show", "--format=%H", &reference])?;
+        if !output.status.success() {
+            return Ok(true);
+        }
+        Ok(String::from_utf8(output.stdout)?.lines().count() > 1)
+    }
+
+    fn commits_behind(&self, branch: &str, target: &str) -> Result<usize> {
+        let range = format!("{}..{}", branch, target);
+        let output = run_git(&self.root, ["rev-list", "--count", &range])?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git rev-list --count {} failed: {}",
+                range,
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        let count = String::from_utf8(output.stdout)?;
+        count
+            .trim()
+            .parse()
+            .with_context(|| format!("Unexpected git rev-list output: {}", count.trim()))
+    }
+
+    fn rebase_onto(&self, worktree_path: &Path, target: &str) -> Result<()> {
+        let Err(err) = self.rebase_in(worktree_path, target) else {
+            return Ok(());
+        };
+        if !matches!(err.downcast_ref(), Some(MergeError::Conflicts { .. })) {
+            return Err(err);
+        }
+        let _ = run_git(worktree_path, ["rebase", "--abort"]);
+        Err(anyhow!(
+            "git rebase onto {} failed in {}; the rebase was aborted",
+            target,
+            worktree_path.display()
+        ))
+    }
+
+    fn rebase_in(&self, worktree_path: &Path, target: &str) -> Result<()> {
+        let status = git_command(Some(worktree_path))
+            .args(["rebase", target])
+            .traced_status()
+            .with_context(|| format!("Failed to run git rebase {}", target))?;
+        if status.success() {
+            return Ok(());
+        }
+        let files = self.unmerged_paths(worktree_path)?;
+        if files.is_empty() {
+            let _ = run_git(worktree_path, ["rebase", "--abort"]);
+            return Err(anyhow!(
+                "git rebase onto {} failed in {}; the rebase was aborted",
+                target,
+                worktree_path.display()
+            ));
+        }
+        Err(MergeError::Conflicts {
+            worktree: worktree_path.to_path_buf(),
+            files,
+        }
+        .into())
+    }
+
+    fn abort_rebase_in(&self, worktree_path: &Path) -> Result<()> {
+        let output = run_git(worktree_path, ["rebase", "--abort"])?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git rebase --abort failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(())
+    }
+
+    fn merge_in_progress(&self, worktree_path: &Path) -> Result<bool> {
+        Ok(templates::git_dir_for_worktree(worktree_path)?
+            .join("MERGE_HEAD")
+            .exists())
+    }
+
+    fn rebase_in_progress(&self, worktree_path: &Path) -> Result<bool> {
+        let git_dir = templates::git_dir_for_worktree(worktree_path)?;
+        Ok(git_dir.join("rebase-merge").is_dir() || git_dir.join("rebase-apply").is_dir())
+    }
+
+    fn conclude_merge_in(&self, worktree_path: &Path) -> Result<()> {
+        let output = run_git(worktree_path, ["commit", "--no-edit"])?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git commit of the merge failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(())
+    }
+
+    fn fast_forward_from_upstream(&self, branch: &str, timeout_secs: Option<u64>) -> Result<bool> {
+        let branch = short_branch_name(branch);
+        let Some(remote) = self.config_value(&format!("branch.{}.remote", branch))? else {
+            return Ok(false);
+        };
+        if remote != "." {
+            self.fetch(&remote, timeout_secs)?;
+        }
+        let (Some(local), Some(upstream)) = (
+            self.rev_parse(branch)?,
+            self.rev_parse(&format!("{}@{{upstream}}", branch))?,
+        ) else {
+            return Ok(false);
+        };
+        if local == upstream || self.is_merged_into(&upstream, &local)? {
+            return Ok(true);
+        }
+        if !self.is_merged_into(&local, &upstream)? {
+            return Err(anyhow!(
+                "{} has diverged from its upstream; reconcile them before merging into it",
+                branch
+            ));
+        }
+
+        // A checked out branch moves with its worktree; any other only needs its ref updated.
+        let checkout = self
+            .list_worktrees()?
+            .into_iter()
+            .find(|worktree| worktree.branch.as_deref() == Some(branch));
+        let output = match checkout {
+            Some(worktree) => run_git(&worktree.path, ["merge", "--ff-only", &upstream])?,
+            None => run_git(
+                &self.root,
+                [
+                    "update-ref",
+                    &format!("refs/heads/{}", branch),
+                    &upstream,
+                    &local,
+                ],
+            )?,
+        };
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Unable to fast-forward {}: {}",
+                branch,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(true)
+    }
+
+    fn upstream_divergence(&self, worktree_path: &Path) -> Result<Option<UpstreamDivergence>> {
+        let output = run_git(
+            worktree_path,
+            ["rev-parse", "--abbrev-ref", "--symbolic-full-name", "@{u}"],
+        )?;
+        if !output.status.success() {
+            return Ok(None);
+        }
+        let upstream = String::from_utf8(output.stdout)?.trim().to_string();
+
+        let output = run_git(
+            worktree_path,
+            ["rev-list", "--left-right", "--count", "@{u}...HEAD"],
+        )?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git rev-list --left-right --count @{{u}}...HEAD failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        let counts = String::from_utf8(output.stdout)?;
+        let mut fields = counts.split_whitespace().map(str::parse::<usize>);
+        match (fields.next(), fields.next()) {
+            (Some(Ok(behind)), Some(Ok(ahead))) => Ok(Some(UpstreamDivergence {
+                upstream,
+                ahead,
+                behind,
+            })),
+            _ => Err(anyhow!("Unexpected git rev-list output: {}", counts.trim())),
+        }
+    }
+
+    fn commit_subjects(&self, worktree_path: &Path, range: &str) -> Result<Vec<String>> {
+        let output = run_git(worktree_path, ["log", "--format=%s", range])?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git log {} failed: {}",
+                range,
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::to_string)
+            .collect())
+    }
+
+    fn commit_authors(
+        &self,
+        worktree_path: &Path,
+        range: &str,
+        limit: usize,
+    ) -> Result<Vec<String>> {
+        let limit = format!("-{}", limit);
+        let output = run_git(
+            worktree_path,
+            ["log", limit.as_str(), "--format=%ae", range],
+        )?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git log {} failed: {}",
+                range,
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::to_string)
+            .collect())
+    }
+
+    fn commits_between(&self, base: &str, tip: &str) -> Result<Vec<CommitSummary>> {
+        let range = format!("{}..{}", base, tip);
+        let output = run_git(&self.root, ["log", "--format=%H%x00%s", &range])?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git log {} failed: {}",
+                range,
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| {
+                let (sha, subject) = line.split_once('\0')?;
+                Some(CommitSummary {
+                    sha: sha.to_string(),
+                    subject: subject.to_string(),
+                })
+            })
+            .collect())
+    }
+
+    fn show_commit(&self, commit: &str) -> Result<String> {
+        let output = run_git(
+            &self.root,
+            ["show", "--stat", "--patch", "--no-color", commit],
+        )?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git show {} failed: {}",
+                commit,
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    fn cherry_pick(&self, worktree_path: &Path, commit: &str) -> Result<bool> {
+        let status = git_command(Some(worktree_path))
+            .args(["cherry-pick", commit])
+            .traced_status()
+            .with_context(|| format!("Failed to run git cherry-pick {}", commit))?;
+        if status.success() {
+            return Ok(true);
+        }
+        if cherry_pick_in_progress(worktree_path) {
+            return Ok(false);
+        }
+        Err(anyhow!(
+            "git cherry-pick {} failed in {}",
+            commit,
+            worktree_path.display()
+        ))
+    }
+
+    fn cherry_pick_step(&self, worktree_path: &Path, step: CherryPickStep) -> Result<bool> {
+        // `--continue` would otherwise open an editor for the message.
+        let status = git_command(Some(worktree_path))
+            .args(["-c", "core.editor=true", "cherry-pick", step.flag()])
+            .traced_status()
+            .with_context(|| format!("Failed to run git cherry-pick {}", step.flag()))?;
+        if status.success() {
+            return Ok(true);
+        }
+        if step == CherryPickStep::Continue && cherry_pick_in_progress(worktree_path) {
+            return Ok(false);
+        }
+        Err(anyhow!(
+            "git cherry-pick {} failed in {}",
+            step.flag(),
+            worktree_path.display()
+        ))
+    }
+
+    fn list_marked_stashes(&self) -> Result<Vec<StashEntry>> {
+        let output = run_git(&self.root, ["stash", "list", "--format=%gd%x00%ct%x00%s"])?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git stash list failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(parse_marked_stashes(&String::from_utf8_lossy(
+            &output.stdout,
+        )))
+    }
+
+    fn apply_stash(&self, worktree_path: &Path, reference: &str) -> Result<()> {
+        let output = run_git(worktree_path, ["stash", "apply", reference])?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git stash apply {} failed: {}",
+                reference,
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(())
+    }
+
+    fn drop_stash(&self, reference: &str) -> Result<()> {
+        let output = run_git(&self.root, ["stash", "drop", reference])?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git stash drop {} failed: {}",
+                reference,
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(())
+    }
+
+    fn stash_diff(&self, reference: &str, color: bool) -> Result<Vec<u8>> {
+        let color = if color {
+            "--color=always"
+        } else {
+            "--no-color"
+        };
+        let output = run_git(
+            &self.root,
+            [
+                "stash",
+                "show",
+                "--patch",
+                "--include-untracked",
+                color,
+                reference,
+            ],
+        )?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git stash show {} failed: {}",
+                reference,
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(output.stdout)
+    }
+
+    fn changed_files(&self, worktree_path: &Path, base: &str) -> Result<Vec<ChangedFile>> {
+        let mut files = Vec::new();
+        for (args, untracked) in [
+            (vec!["diff", "--name-only", "-z", base], false),
+            (
+                vec!["ls-files", "--others", "--exclude-standard", "-z"],
+                true,
+            ),
+        ] {
+            let output = run_git(worktree_path, &args)?;
+            if !output.status.success() {
+                return Err(anyhow!(
+                    "git {} failed: {}",
+                    args.join(" "),
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
+            }
+            files.extend(
+                output
+                    .stdout
+                    .split(|byte| *byte == 0)
+                    .filter(|name| !name.is_empty())
+                    .map(|name| ChangedFile {
+                        path: String::from_utf8_lossy(name).into_owned(),
+                        untracked,
+                    }),
+            );
+        }
+        Ok(files)
+    }
+
+    fn working_diff(&self, worktree_path: &Path, base: &str) -> Result<String> {
+        let output = run_git(worktree_path, ["diff", "--no-color", "-U0", base])?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git diff {} failed: {}",
+                base,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    fn merge_no_commit(&self, source_branch: &str, target_branch: &str) -> Result<()> {
+        let current = self.current_branch()?;
+        self.check_merge(source_branch, target_branch, current.as_deref())?;
+        if current.as_deref() != Some(target_branch) {
+            self.checkout_branch(target_branch)?;
+        }
+
+        let status = git_command(Some(&self.root))
+            .args(["merge", "--no-ff", "--no-commit", source_branch])
+            .traced_status()
+            .with_context(|| format!("Failed to merge {} into {}", source_branch, target_branch))?;
+        // A conflicted merge also exits non-zero but is still reviewable.
+        if !status.success() && self.unmerged_paths(&self.root)?.is_empty() {
+            return Err(anyhow!(
+                "git merge --no-commit failed while merging {} into {}",
+                source_branch,
+                target_branch
+            ));
+        }
+        Ok(())
+    }
+
+    fn commit_merge(&self, message: &str) -> Result<()> {
+        let output = run_git(&self.root, ["commit", "-m", message])?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git commit failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(())
+    }
+
+    fn unmerged_paths(&self, worktree_path: &Path) -> Result<Vec<String>> {
+        let output = run_git(
+            worktree_path,
+            ["diff", "--name-only", "--diff-filter=U", "-z"],
+        )?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git diff --diff-filter=U failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(output
+            .stdout
+            .split(|byte| *byte == 0)
+            .filter(|name| !name.is_empty())
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .collect())
+    }
+
+    fn staged_diffstat(&self) -> Result<String> {
+        let output = run_git(&self.root, ["diff", "--cached", "--stat"])?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git diff --cached --stat failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    fn merge_branch_in(&self, worktree_path: &Path, source_branch: &str) -> Result<()> {
+        let current = self.current_branch_in(worktree_path)?;
+        if current.as_deref() == Some(short_branch_name(source_branch)) {
+            return Err(MergeError::SameRef {
+                branch: source_branch.to_string(),
+            }
+            .into());
+        }
+
+        let status = git_command(Some(worktree_path))
+            .args(["merge", "--no-ff", "--no-edit"])
+            .args(worktree_hook_args(worktree_path))
+            .arg(source_branch)
+            .traced_status()
+            .with_context(|| format!("Failed to run git merge {}", source_branch))?;
+        if status.success() {
+            return Ok(());
+        }
+
+        let files = self.unmerged_paths(worktree_path)?;
+        if files.is_empty() {
+            let _ = run_git(worktree_path, ["merge", "--abort"]);
+            return Err(anyhow!(
+                "git merge of {} failed in {}",
+                source_branch,
+                worktree_path.display()
+            ));
+        }
+        Err(MergeError::Conflicts {
+            worktree: worktree_path.to_path_buf(),
+            files,
+        }
+        .into())
+    }
+
+    fn merge_into_worktree(&self, worktree_path: &Path, source: &str) -> Result<()> {
+        let status = git_command(Some(worktree_path))
+            .args(["merge", "--no-edit"])
+            .args(worktree_hook_args(worktree_path))
+            .arg(source)
+            .traced_status()
+            .with_context(|| format!("Failed to run git merge {}", source))?;
+        if !status.success() {
+            let _ = run_git(worktree_path, ["merge", "--abort"]);
+            return Err(anyhow!(
+                "git merge of {} failed in {}; the merge was aborted",
+                source,
+                worktree_path.display()
+            ));
+        }
+        Ok(())
+    }
+
+    fn config_value(&self, key: &str) -> Result<Option<String>> {
+        let output = run_git(&self.root, ["config", "--get", key])?;
+        if !output.status.success() {
+            return Ok(None);
+        }
+        let value = String::from_utf8(output.stdout)?.trim().to_string();
+        Ok(if value.is_empty() { None } else { Some(value) })
+    }
+
+    fn enable_worktree_config(&self) -> Result<()> {
+        let output = run_git(&self.root, ["config", "extensions.worktreeConfig", "true"])?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Unable to enable extensions.worktreeConfig: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(())
+    }
+
+    fn set_worktree_config(&self, worktree_path: &Path, key: &str, value: &str) -> Result<()> {
+        let output = run_git(worktree_path, ["config", "--worktree", key, value])?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git config --worktree {} failed in {}: {}",
+                key,
+                worktree_path.display(),
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(())
+    }
+
+    fn health_check(&self) -> Result<Vec<HealthIssue>> {
+        let output = run_git(&self.root, ["rev-parse", "--absolute-git-dir"])?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Unable to locate the git directory of {}",
+                self.root.display()
+            ));
+        }
+        let git_dir = unmap_path(Path::new(String::from_utf8_lossy(&output.stdout).trim()));
+
+        let mut issues = Vec::new();
+        if git_dir.join("MERGE_HEAD").exists() {
+            issues.push(HealthIssue::MergeInProgress);
+        }
+        if git_dir.join("rebase-merge").is_dir() || git_dir.join("rebase-apply").is_dir() {
+            issues.push(HealthIssue::RebaseInProgress);
+        }
+        if git_dir.join("CHERRY_PICK_HEAD").exists() {
+            issues.push(HealthIssue::CherryPickInProgress);
+        }
+        let lock = git_dir.join("index.lock");
+        if let Some(age) = fs::metadata(&lock)
+            .and_then(|meta| meta.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            && age > STALE_INDEX_LOCK_AGE
+        {
+            issues.push(HealthIssue::StaleIndexLock { path: lock, age });
+        }
+        // A rebase detaches HEAD on its own; only report it separately otherwise.
+        if !issues.contains(&HealthIssue::RebaseInProgress) && self.current_branch()?.is_none() {
+            issues.push(HealthIssue::DetachedHead);
+        }
+        Ok(issues)
+    }
+
+    fn abort_merge_in(&self, worktree_path: &Path) -> Result<()> {
+        let output = run_git(worktree_path, ["merge", "--abort"])?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git merge --abort failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(())
+    }
+
+    fn current_branch_in(&self, worktree_path: &Path) -> Result<Option<String>> {
+        let output = run_git(worktree_path, ["rev-parse", "--abbrev-ref", "HEAD"])?;
+        if !output.status.success() {
+            return Ok(None);
+        }
+        let name = String::from_utf8(output.stdout)?;
+        let trimmed = name.trim();
+        if trimmed == "HEAD" {
+            Ok(None)
+        } else {
+            Ok(Some(trimmed.to_string()))
+        }
+    }
+
+    fn commit_file(&self, file: &str, message: &str) -> Result<()> {
+        let output = run_git(&self.root, ["add", "--", file])?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git add {} failed: {}",
+                file,
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        let output = run_git(&self.root, ["commit", "-m", message, "--", file])?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git commit of {} failed: {}",
+                file,
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(())
+    }
+
+    fn checkout_branch(&self, branch: &str) -> Result<()> {
+        let status = git_command(Some(&self.root))
+            .args(["checkout", branch])
+            .traced_status()
+            .with_context(|| format!("Failed to run git checkout {}", branch))?;
+        if !status.success() {
+            return Err(anyhow!("Unable to checkout branch {}", branch));
+        }
+        Ok(())
+    }
+
+    fn switch_branch_in(&self, worktree_path: &Path, branch: &str) -> Result<()> {
+        let output = run_git(worktree_path, ["switch", branch])?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Unable to switch {} to {}: {}",
+                worktree_path.display(),
+                branch,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(())
+    }
+
+    fn mark_safe_directory(&self, path: &Path) -> Result<()> {
+        let status = git_command(None)
+            .args(["config", "--global", "--add", "safe.directory"])
+            .arg(map_path(path))
+            .traced_status()
+            .context("Failed to run git config --global --add safe.directory")?;
+        if !status.success() {
+            return Err(anyhow!(
+                "Unable to mark {} as a safe directory",
+                path.display()
+            ));
+        }
+        Ok(())
+    }
+
+    fn diff(&self, worktree_path: &Path, range: DiffRange<'_>, color: bool) -> Result<Vec<u8>> {
+        let args = diff_command(range, color);
+        let output = run_git(worktree_path, &args)?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(output.stdout)
+    }
+
+    fn predict_conflicts(&self, target: &str, branch: &str) -> Result<Option<ConflictPrediction>> {
+        static VERSION: OnceLock<Option<(u32, u32)>> = OnceLock::new();
+        let version = *VERSION.get_or_init(|| {
+            run_git(&self.root, ["version"])
+                .ok()
+                .and_then(|output| parse_git_version(&String::from_utf8_lossy(&output.stdout)))
+        });
+        if version.is_some_and(|version| version >= WRITE_TREE_VERSION) {
+            let output = run_git(
+                &self.root,
+                [
+                    "merge-tree",
+                    "--write-tree",
+                    "--name-only",
+                    "--no-messages",
+                    target,
+                    branch,
+                ],
+            )?;
+            return Ok(parse_merge_tree(
+                &String::from_utf8_lossy(&output.stdout),
+                output.status.code(),
+            ));
+        }
+        let Ok(base) = self.merge_base(target, branch) else {
+            return Ok(None);
+        };
+        let output = run_git(&self.root, ["merge-tree", base.as_str(), target, branch])?;
+        if !output.status.success() {
+            return Ok(None);
+        }
+        Ok(Some(parse_legacy_merge_tree(&String::from_utf8_lossy(
+            &output.stdout,
+        ))))
+    }
+
+    fn diff_stat(&self, from: &str, to: &str) -> Result<String> {
+        let range = format!("{}..{}", from, to);
+        let output = run_git(&self.root, ["diff", "--stat", "--no-color", &range])?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git diff --stat {} failed: {}",
+                range,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    fn branch_files(&self, target: &str, branch: &str) -> Result<Vec<String>> {
+        let range = format!("{}...{}", target, branch);
+        let output = run_git(&self.root, ["diff", "--name-only", "-z", &range])?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git diff --name-only {} failed: {}",
+                range,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(output
+            .stdout
+            .split(|byte| *byte == 0)
+            .filter(|name| !name.is_empty())
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .collect())
+    }
+}
+
+// Distinct authors in `authors` other than `own_email` (compared case-insensitively), in the
+// order first seen. Without an own email nobody can be told apart, so nobody is reported.
+pub fn foreign_authors(authors: &[String], own_email: Option<&str>) -> Vec<String> {
+    let Some(own_email) = own_email.map(str::trim).filter(|email| !email.is_empty()) else {
+        return Vec::new();
+    };
+    let mut foreign: Vec<String> = Vec::new();
+    for author in authors.iter().map(|author| author.trim()) {
+        if !author.is_empty()
+            && !author.eq_ignore_ascii_case(own_email)
+            && !foreign.iter().any(|seen| seen.eq_ignore_ascii_case(author))
+        {
+            foreign.push(author.to_string());
+        }
+    }
+    foreign
+}
+
+// `-- . :(exclude)<pattern>...` for `git status`, or nothing without patterns. Each pattern is
+// taken as a path relative to the worktree root: any magic of its own is matched literally, and
+// empty patterns, which would exclude everything, are dropped.
+pub fn status_pathspecs(ignore: &[String]) -> Vec<String> {
+    let excludes = ignore
+        .iter()
+        .map(|pattern| pattern.trim().trim_start_matches('/'))
+        .filter(|pattern| !pattern.is_empty())
+        .map(|pattern| format!(":(exclude){}", pattern))
+        .collect::<Vec<_>>();
+    if excludes.is_empty() {
+        return excludes;
+    }
+    ["--".to_string(), ".".to_string()]
+        .into_iter()
+        .chain(excludes)
+        .collect()
+}
+
+// `git diff --no-index` of two files; empty when they are identical.
+pub fn diff_files(old: &Path, new: &Path, color: bool) -> Result<Vec<u8>> {
+    let color = if color {
+        "--color=always"
+    } else {
+        "--no-color"
+    };
+    let output = git_command(None)
+        .args(["diff", "--no-index", color, "--"])
+        .arg(old)
+        .arg(new)
+        .traced_output()
+        .context("Failed to run git diff --no-index")?;
+    // Exit status 1 only means the files differ.
+    match output.status.code() {
+        Some(0 | 1) => Ok(output.stdout),
+        _ => Err(anyhow!(
+            "git diff --no-index failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )),
+    }
+}
+
+fn cherry_pick_in_progress(worktree_path: &Path) -> bool {
+    let Ok(output) = run_git(
+        worktree_path,
+        ["rev-parse", "--git-path", "CHERRY_PICK_HEAD"],
+    ) else {
+        return false;
+    };
+    let reported = String::from_utf8_lossy(&output.stdout);
+    let path = Path::new(reported.trim());
+    output.status.success()
+        && if path.is_absolute() {
+            unmap_path(path).exists()
+        } else {
+            worktree_path.join(path).exists()
+        }
+}
+
+pub fn has_submodules(worktree_path: &Path) -> bool {
+    worktree_path.join(".gitmodules").is_file()
+}
+
+// Submodule paths declared in `.gitmodules` whose directory is missing or has no content yet.
+pub fn empty_submodule_dirs(worktree_path: &Path) -> Vec<PathBuf> {
+    let Ok(content) = fs::read_to_string(worktree_path.join(".gitmodules")) else {
+        return Vec::new();
+    };
+    content
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            (key.trim() == "path").then(|| worktree_path.join(value.trim()))
+        })
+        .filter(|dir| {
+            fs::read_dir(dir)
+                .map(|mut entries| entries.next().is_none())
+                .unwrap_or(true)
+        })
+        .collect()
+}
+
+// Where the worktree git still records at `recorded` now lives, among the children of `search_dirs`.
+pub fn find_moved_worktree(
+    common_dir: &Path,
+    recorded: &Path,
+    search_dirs: &[PathBuf],
+) -> Option<PathBuf> {
+    let recorded_git = recorded.join(".git");
+    let admin_dir = fs::read_dir(common_dir.join("worktrees"))
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .find(|admin| {
+            fs::read_to_string(admin.join("gitdir"))
+                .is_ok_and(|gitdir| unmap_path(Path::new(gitdir.trim())) == recorded_git)
+        })?
+        .canonicalize()
+        .ok()?;
+
+    search_dirs
+        .iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flat_map(|entries| entries.flatten().map(|entry| entry.path()))
+        .find(|candidate| {
+            let Ok(spec) = fs::read_to_string(candidate.join(".git")) else {
+                return false;
+            };
+            let Some(gitdir) = spec.trim().strip_prefix("gitdir:") else {
+                return false;
+            };
+            candidate
+                .join(unmap_path(Path::new(gitdir.trim())))
+                .canonicalize()
+                .is_ok_and(|gitdir| gitdir == admin_dir)
+        })
+}
+
+// Common git dir of the repository that owns the worktree at `path`, following its gitdir
+// pointer. `None` when the directory is not a git checkout.
+pub fn worktree_common_dir(path: &Path) -> Option<PathBuf> {
+    let git_dir = templates::git_dir_for_worktree(path).ok()?;
+    let common = match fs::read_to_string(git_dir.join("commondir")) {
+        Ok(spec) => {
+            let spec = PathBuf::from(spec.trim());
+            if spec.is_relative() {
+                git_dir.join(spec)
+            } else {
+                spec
+            }
+        }
+        Err(_) => git_dir,
+    };
+    common.canonicalize().ok()
+}
+
+fn resolve_base_path(rendered: &str, relative_to: &Path) -> Result<PathBuf> {
+    let expanded = if rendered == "~" || rendered.starts_with("~/") {
+        let home = directories::BaseDirs::new()
+            .map(|dirs| dirs.home_dir().to_path_buf())
+            .ok_or_else(|| anyhow!("Unable to resolve the home directory to expand ~"))?;
+        match rendered.strip_prefix("~/") {
+            Some(rest) => home.join(rest),
+            None => home,
+        }
+    } else if rendered.starts_with('~') {
+        return Err(anyhow!(
+            "Worktree base {} uses ~user expansion, which is not supported; use an absolute path instead",
+            rendered
+        ));
+    } else {
+        PathBuf::from(rendered)
+    };
+
+    let joined = if expanded.is_relative() {
+        relative_to.join(expanded)
+    } else {
+        expanded
+    };
+    Ok(normalize_path(&joined))
+}
+
+// Creates and removes a throwaway file in `dir`.
+fn directory_writable(dir: &Path) -> bool {
+    let probe = dir.join(format!(".agent-write-probe-{}", std::process::id()));
+    let written = fs::write(&probe, b"").is_ok();
+    let _ = fs::remove_file(&probe);
+    written
+}
+
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    normalized.push(component);
+                }
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+// Parses `for-each-ref` refnames; remote-tracking refs are only kept when no local branch has
+// the same name.
+fn matching_branches(refnames: &str, pattern: &str) -> Vec<BranchRef> {
+    let mut local = Vec::new();
+    let mut remote = Vec::new();
+    for refname in refnames.lines().map(str::trim) {
+        if let Some(name) = refname.strip_prefix("refs/heads/") {
+            if glob_match(pattern, name) {
+                local.push(BranchRef {
+                    name: name.to_string(),
+                    remote_ref: None,
+                });
+            }
+        } else if let Some(remote_ref) = refname.strip_prefix("refs/remotes/")
+            && let Some((_, name)) = remote_ref.split_once('/')
+            && name != "HEAD"
+            && glob_match(pattern, name)
+        {
+            remote.push(BranchRef {
+                name: name.to_string(),
+                remote_ref: Some(remote_ref.to_string()),
+            });
+        }
+    }
+    for branch in remote {
+        if !local.iter().any(|existing| existing.name == branch.name) {
+            local.push(branch);
+        }
+    }
+    local
+}
+
+// First `git check-ref-format --branch` rule that `name` breaks, if any.
+pub fn branch_name_violation(name: &str) -> Option<&'static str> {
+    if name.is_empty() {
+        return Some("the name is empty");
+    }
+    if name == "@" {
+        return Some("the name cannot be the single character @");
+    }
+    if name.starts_with('-') {
+        return Some("the name cannot start with a dash");
+    }
+    if name.starts_with('/') || name.ends_with('/') {
+        return Some("the name cannot start or end with a slash");
+    }
+    if name.contains("//") {
+        return Some("the name cannot contain consecutive slashes");
+    }
+    if name.ends_with('.') {
+        return Some("the name cannot end with a dot");
+    }
+    if name.contains("..") {
+        return Some("the name cannot contain ..");
+    }
+    if name.contains("@{") {
+        return Some("the name cannot contain @{");
+    }
+    if name.chars().any(|ch| ch.is_ascii_control()) {
+        return Some("the name cannot contain control characters");
+    }
+    if let Some(ch) = name.chars().find(|ch| " ~^:?*[\\".contains(*ch)) {
+        return Some(match ch {
+            ' ' => "the name cannot contain spaces",
+            _ => "the name cannot contain any of ~ ^ : ? * [ \\",
+        });
+    }
+    for component in name.split('/') {
+        if component.starts_with('.') {
+            return Some("no path component can start with a dot");
+        }
+        if component.ends_with(".lock") {
+            return Some("no path component can end with .lock");
+        }
+    }
+    None
+}
+
+// A close, valid alternative to `name`: forbidden characters become dashes and separators
+// are collapsed.
+pub fn suggest_branch_name(name: &str) -> String {
+    let mut candidate = name
+        .trim()
+        .chars()
+        .map(|ch| {
+            if ch.is_ascii_control() || " ~^:?*[\\".contains(ch) {
+                '-'
+            } else {
+                ch
+            }
+        })
+        .collect::<String>()
+        .replace("@{", "@");
+    while candidate.contains("..") || candidate.contains("//") || candidate.contains("--") {
+        candidate = candidate
+            .replace("..", ".")
+            .replace("//", "/")
+            .replace("--", "-");
+    }
+    let candidate = candidate
+        .split('/')
+        .map(|component| {
+            component
+                .trim_start_matches('.')
+                .trim_end_matches(".lock")
+                .to_string()
+        })
+        .filter(|component| !component.is_empty())
+        .collect::<Vec<_>>()
+        .join("/");
+    let candidate = candidate
+        .trim_start_matches(['-', '.'])
+        .trim_end_matches('.');
+    if branch_name_violation(candidate).is_none() {
+        candidate.to_string()
+    } else {
+        "agent/branch".to_string()
+    }
+}
+
+// Asks git itself, for names the checks above cannot judge with confidence (non-ASCII).
+pub fn git_accepts_branch_name(name: &str) -> bool {
+    git_command(None)
+        .args(["check-ref-format", "--branch", name])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .traced_status()
+        .map(|status| status.success())
+        .unwrap_or(true)
+}
+
+fn short_branch_name(branch: &str) -> &str {
+    branch.strip_prefix("refs/heads/").unwrap_or(branch)
+}
+
+pub fn clone_directory_name(url: &str) -> String {
+    let trimmed = url.trim().trim_end_matches('/');
+    let last = trimmed.rsplit(['/', ':']).next().unwrap_or_default();
+    let name = last.strip_suffix(".git").unwrap_or(last);
+    if name.is_empty() {
+        "repository".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+pub fn clone_repository(url: &str, destination: &Path, timeout_secs: Option<u64>) -> Result<()> {
+    let mut command = git_command(None);
+    command
+        .arg("clone")
+        .arg("--progress")
+        .arg(url)
+        .arg(map_path(destination));
+    run_network_git(command, &format!("git clone of {}", url), timeout_secs)
+}
+
+pub fn shallow_clone(url: &str, destination: &Path, timeout_secs: Option<u64>) -> Result<()> {
+    let mut command = git_command(None);
+    command
+        .args(["clone", "--depth", "1", "--progress"])
+        .arg(url)
+        .arg(map_path(destination));
+    run_network_git(command, &format!("git clone of {}", url), timeout_secs)
+}
+
+pub fn pull_fast_forward(dir: &Path, timeout_secs: Option<u64>) -> Result<()> {
+    let mut command = git_command(Some(dir));
+    command.args(["pull", "--ff-only"]);
+    run_network_git(
+        command,
+        &format!("git pull in {}", dir.display()),
+        timeout_secs,
+    )
+}
+
+// `git fetch --prune` of the default remote for a background refresh: nothing is printed and git
+// never asks for credentials, so an offline or unauthenticated remote just fails.
+pub fn quiet_fetch(root: &Path, timeout_secs: Option<u64>) -> Result<()> {
+    let mut command = git_command(Some(root));
+    command
+        .args(["fetch", "--prune", "--quiet"])
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(Stdio::null());
+    if let Some(secs) = timeout_secs {
+        command
+            .env("GIT_HTTP_LOW_SPEED_LIMIT", "1000")
+            .env("GIT_HTTP_LOW_SPEED_TIME", secs.to_string());
+    }
+    let output = command.traced_output().context("Failed to run git fetch")?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    Err(anyhow!(
+        "git fetch failed ({}): {}",
+        network_failure_hint(&stderr),
+        stderr
+            .lines()
+            .find(|line| line.starts_with("fatal:"))
+            .or_else(|| stderr.lines().rfind(|line| !line.trim().is_empty()))
+            .unwrap_or_default()
+            .trim()
+    ))
+}
+
+// Network operations keep the terminal attached so git's credential prompts (SSH passphrase,
+// HTTPS username) and progress reach the user; stderr is copied to classify failures.
+fn run_network_git(mut command: Command, what: &str, timeout_secs: Option<u64>) -> Result<()> {
+    if let Some(secs) = timeout_secs {
+        // Abort HTTPS transfers that stay below 1 KiB/s for the configured time.
+        command
+            .env("GIT_HTTP_LOW_SPEED_LIMIT", "1000")
+            .env("GIT_HTTP_LOW_SPEED_TIME", secs.to_string());
+    }
+    let mut child = command
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::piped())
+        .traced_spawn()
+        .with_context(|| format!("Failed to run {}", what))?;
+
+    // Forward git's output as it arrives while keeping a copy for the error message.
+    let mut captured = Vec::new();
+    if let Some(mut stderr) = child.stderr.take() {
+        let mut buf = [0u8; 4096];
+        let mut terminal = io::stderr();
+        loop {
+            let read = stderr.read(&mut buf)?;
+            if read == 0 {
+                break;
+            }
+            terminal.write_all(&buf[..read])?;
+            terminal.flush()?;
+            captured.extend_from_slice(&buf[..read]);
+        }
+    }
+
+    let status = child
+        .wait()
+        .with_context(|| format!("Failed to wait for {}", what))?;
+    if status.success() {
+        return Ok(());
+    }
+    let output = String::from_utf8_lossy(&captured);
+    let last_lines = output
+        .lines()
+        .rev()
+        .filter(|line| !line.trim().is_empty())
+        .take(3)
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+        .collect::<Vec<_>>()
+        .join("\n");
+    Err(anyhow!(
+        "{} failed ({}): {}",
+        what,
+        network_failure_hint(&output),
+        last_lines
+    ))
+}
+
+fn network_failure_hint(stderr: &str) -> &'static str {
+    const AUTH_MARKERS: [&str; 6] = [
+        "permission denied",
+        "authentication failed",
+        "could not read username",
+        "could not read password",
+        "invalid username or password",
+        "403",
+    ];
+    const NETWORK_MARKERS: [&str; 7] = [
+        "could not resolve host",
+        "connection timed out",
+        "connection refused",
+        "network is unreachable",
+        "operation too slow",
+        "unable to access",
+        "early eof",
+    ];
+    let stderr = stderr.to_lowercase();
+    if AUTH_MARKERS.iter().any(|marker| stderr.contains(marker)) {
+        "authentication was rejected; check your SSH agent or credentials"
+    } else if NETWORK_MARKERS.iter().any(|marker| stderr.contains(marker)) {
+        "the remote could not be reached; check your network"
+    } else {
+        "run `git remote -v` to check the remote URL"
+    }
+}
+
+fn run_git<S>(root: &Path, args: impl IntoIterator<Item = S>) -> Result<std::process::Output>
+where
+    S: AsRef<OsStr>,
+{
+    let output = git_command(Some(root))
+        .args(args)
+        .traced_output()
+        .with_context(|| format!("Failed to execute git in {}", root.display()))?;
+
+    if !output.status.success()
+        && let Some(path) = dubious_ownership_path(&String::from_utf8_lossy(&output.stderr))
+    {
+        let path = path.unwrap_or_else(|| root.to_path_buf());
+        let message = format!(
+            "git refuses to work in {} (dubious ownership)",
+            path.display()
+        );
+        UNSAFE_DIRECTORIES
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(path);
+        return Err(anyhow!(message));
+    }
+
+    Ok(output)
+}
+
+// Some(_) when git rejected the repository because of `safe.directory`; the inner value is the
+// directory git named, if it could be parsed.
+fn dubious_ownership_path(stderr: &str) -> Option<Option<PathBuf>> {
+    let line = stderr
+        .lines()
+        .find(|line| line.contains("detected dubious ownership"))?;
+    let path = line
+        .split_once(" at '")
+        .and_then(|(_, rest)| rest.rsplit_once('\''))
+        .map(|(path, _)| PathBuf::from(path));
+    Some(path)
+}
+
+// Drains the directories rejected since the last call.
+pub fn take_unsafe_directories() -> Vec<PathBuf> {
+    std::mem::take(
+        &mut *UNSAFE_DIRECTORIES
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()),
+    )
+    .into_iter()
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{TempDir, commit_file, git, init_repo};
+
+    fn repo_with_branch(tmp: &TempDir, branch: &str) -> CliGit {
+        let root = tmp.path().join("repo");
+        init_repo(&root);
+        git(&root, &["branch", branch]);
+        CliGit::open(&root).unwrap()
+    }
+
+    #[test]
+    fn check_merge_accepts_a_branch_into_an_existing_target() {
+        let tmp = TempDir::new("check-merge");
+        let repo = repo_with_branch(&tmp, "agent/login");
+        assert_eq!(
+            repo.check_merge("agent/login", "main", Some("main")),
+            Ok(())
+        );
+        assert_eq!(
+            repo.check_merge("agent/login", "refs/heads/main", None),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn check_merge_rejects_merging_a_branch_into_itself() {
+        let tmp = TempDir::new("check-merge-same");
+        let repo = repo_with_branch(&tmp, "agent/login");
+        assert_eq!(
+            repo.check_merge("agent/login", "refs/heads/agent/login", None),
+            Err(MergeError::SameRef {
+                branch: "agent/login".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn check_merge_reports_a_missing_target() {
+        let tmp = TempDir::new("check-merge-missing");
+        let repo = repo_with_branch(&tmp, "agent/login");
+        assert_eq!(
+            repo.check_merge("agent/login", "release", Some("main")),
+            Err(MergeError::MissingTarget {
+                target: "release".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn check_merge_rejects_a_source_checked_out_in_the_primary_worktree() {
+        let tmp = TempDir::new("check-merge-checked-out");
+        let repo = repo_with_branch(&tmp, "agent/login");
+        assert_eq!(
+            repo.check_merge("agent/login", "main", Some("agent/login")),
+            Err(MergeError::SourceCheckedOut {
+                branch: "agent/login".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn comparison_base_uses_the_recorded_sha_in_creation_base_mode() {
+        let sha = "0123456789abcdef0123456789abcdef01234567";
+        assert_eq!(
+            comparison_base(DiffBase::CreationBase, "main", Some(sha)),
+            sha
+        );
+        assert_eq!(
+            comparison_base(DiffBase::CreationBase, "main", Some("abc1234")),
+            "abc1234"
+        );
+    }
+
+    #[test]
+    fn comparison_base_falls_back_to_the_target() {
+        assert_eq!(
+            comparison_base(DiffBase::CreationBase, "main", None),
+            "main"
+        );
+        assert_eq!(
+            comparison_base(DiffBase::MergeTarget, "main", Some("abc1234")),
+            "main"
+        );
+        for malformed in ["abc12", "not-a-sha", "HEAD~1; rm -rf /"] {
+            assert_eq!(
+                comparison_base(DiffBase::CreationBase, "main", Some(malformed)),
+                "main"
+            );
+        }
+    }
+
+    #[test]
+    fn branch_name_violation_table() {
+        let cases = [
+            ("agent/login", None),
+            ("feature-été", None),
+            ("功能/登录", None),
+            ("v1.2", None),
+            ("a@b", None),
+            ("", Some("the name is empty")),
+            ("@", Some("the name cannot be the single character @")),
+            ("-feature", Some("the name cannot start with a dash")),
+            (
+                "/feature",
+                Some("the name cannot start or end with a slash"),
+            ),
+            (
+                "feature/",
+                Some("the name cannot start or end with a slash"),
+            ),
+            (
+                "agent//login",
+                Some("the name cannot contain consecutive slashes"),
+            ),
+            ("feature.", Some("the name cannot end with a dot")),
+            ("a..b", Some("the name cannot contain ..")),
+            ("main@{1}", Some("the name cannot contain @{")),
+            (
+                "tab\there",
+                Some("the name cannot contain control characters"),
+            ),
+            (
+                "bell\u{7}",
+                Some("the name cannot contain control characters"),
+            ),
+            (
+                "del\u{7f}",
+                Some("the name cannot contain control characters"),
+            ),
+            ("my feature", Some("the name cannot contain spaces")),
+            ("a~1", Some("the name cannot contain any of ~ ^ : ? * [ \\")),
+            ("a^", Some("the name cannot contain any of ~ ^ : ? * [ \\")),
+            ("a:b", Some("the name cannot contain any of ~ ^ : ? * [ \\")),
+            (
+                "what?",
+                Some("the name cannot contain any of ~ ^ : ? * [ \\"),
+            ),
+            (
+                "wild*",
+                Some("the name cannot contain any of ~ ^ : ? * [ \\"),
+            ),
+            ("[x", Some("the name cannot contain any of ~ ^ : ? * [ \\")),
+            (
+                "back\\slash",
+                Some("the name cannot contain any of ~ ^ : ? * [ \\"),
+            ),
+            (
+                "agent/.hidden",
+                Some("no path component can start with a dot"),
+            ),
+            (".hidden", Some("no path component can start with a dot")),
+            ("agent.lock/x", Some("no path component can end with .lock")),
+            ("agent/x.lock", Some("no path component can end with .lock")),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(branch_name_violation(name), expected, "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn branch_name_violation_agrees_with_git() {
+        for name in [
+            "agent/login",
+            "feature-été",
+            "-feature",
+            "agent//login",
+            "a..b",
+            "main@{1}",
+            "tab\there",
+            "my feature",
+            "a:b",
+            "agent/.hidden",
+            "agent/x.lock",
+        ] {
+            assert_eq!(
+                branch_name_violation(name).is_none(),
+                git_accepts_branch_name(name),
+                "{:?}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn suggest_branch_name_repairs_invalid_names() {
+        let cases = [
+            ("my feature", "my-feature"),
+            ("  fix: login  ", "fix-login"),
+            ("a..b", "a.b"),
+            ("agent//login", "agent/login"),
+            ("/agent/login/", "agent/login"),
+            ("main@{1}", "main@1}"),
+            ("tab\there", "tab-here"),
+            ("agent/.hidden", "agent/hidden"),
+            ("agent/x.lock", "agent/x"),
+            ("-feature.", "feature"),
+            ("été ~ 2", "été-2"),
+            ("@", "agent/branch"),
+            ("???", "agent/branch"),
+        ];
+        for (name, expected) in cases {
+            let suggestion = suggest_branch_name(name);
+            assert_eq!(suggestion, expected, "{:?}", name);
+            assert_eq!(branch_name_violation(&suggestion), None, "{:?}", suggestion);
+        }
+    }
+
+    #[test]
+    fn status_pathspecs_excludes_each_pattern_from_the_root() {
+        let ignore = ["scratch/", "/notes.md", " *.log ", "", "  "].map(String::from);
+        assert_eq!(
+            status_pathspecs(&ignore),
+            [
+                "--",
+                ".",
+                ":(exclude)scratch/",
+                ":(exclude)notes.md",
+                ":(exclude)*.log"
+            ]
+        );
+    }
+
+    #[test]
+    fn status_pathspecs_is_empty_without_patterns() {
+        assert!(status_pathspecs(&[]).is_empty());
+        assert!(status_pathspecs(&["".to_string(), "/".to_string()]).is_empty());
+    }
+
+    #[test]
+    fn scratch_only_changes_leave_a_worktree_clean() {
+        let tmp = TempDir::new("dirty-ignore");
+        let repo = repo_with_branch(&tmp, "agent/login");
+        let worktree = tmp.path().join("login");
+        git(
+            repo.root(),
+            &[
+                "worktree",
+                "add",
+                "-q",
+                worktree.to_str().unwrap(),
+                "agent/login",
+            ],
+        );
+        let ignore = ["scratch/", "notes.md", "*.log"].map(String::from);
+        assert!(!repo.is_worktree_dirty(&worktree, &ignore).unwrap());
+
+        fs::create_dir_all(worktree.join("scratch/plan")).unwrap();
+        fs::write(worktree.join("scratch/plan/todo.txt"), "todo\n").unwrap();
+        fs::write(worktree.join("notes.md"), "notes\n").unwrap();
+        fs::write(worktree.join("agent.log"), "log\n").unwrap();
+        assert!(!repo.is_worktree_dirty(&worktree, &ignore).unwrap());
+        assert!(repo.is_worktree_dirty(&worktree, &[]).unwrap());
+
+        fs::write(worktree.join("README.md"), "changed\n").unwrap();
+        assert!(repo.is_worktree_dirty(&worktree, &ignore).unwrap());
+    }
+
+    fn log_output(output: &str) -> Vec<String> {
+        output.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn foreign_authors_lists_each_other_author_once() {
+        let authors = log_output(
+            "bob@example.com\nMe@Example.com\ncarol@example.com\n  BOB@example.com \n\nme@example.com\n",
+        );
+        assert_eq!(
+            foreign_authors(&authors, Some("me@example.com")),
+            ["bob@example.com", "carol@example.com"]
+        );
+    }
+
+    #[test]
+    fn foreign_authors_is_empty_for_own_commits_only() {
+        let authors = log_output("me@example.com\nme@example.com\n");
+        assert!(foreign_authors(&authors, Some(" me@example.com ")).is_empty());
+        assert!(foreign_authors(&[], Some("me@example.com")).is_empty());
+    }
+
+    #[test]
+    fn foreign_authors_reports_nobody_without_an_own_email() {
+        let authors = log_output("bob@example.com\n");
+        assert!(foreign_authors(&authors, None).is_empty());
+        assert!(foreign_authors(&authors, Some("  ")).is_empty());
+    }
+
+    #[test]
+    fn commit_authors_reads_the_newest_authors_of_a_range() {
+        let tmp = TempDir::new("commit-authors");
+        let repo = repo_with_branch(&tmp, "agent/login");
+        let root = repo.root().to_path_buf();
+        git(&root, &["checkout", "-q", "agent/login"]);
+        for (email, file) in [
+            ("bob@example.com", "a.txt"),
+            ("test@example.com", "b.txt"),
+            ("carol@example.com", "c.txt"),
+        ] {
+            fs::write(root.join(file), "content\n").unwrap();
+            git(&root, &["add", file]);
+            git(
+                &root,
+                &[
+                    "-c",
+                    &format!("user.email={}", email),
+                    "commit",
+                    "-q",
+                    "-m",
+                    file,
+                ],
+            );
+        }
+
+        assert_eq!(
+            repo.commit_authors(&root, "main..HEAD", 10).unwrap(),
+            ["carol@example.com", "test@example.com", "bob@example.com"]
+        );
+        assert_eq!(
+            repo.commit_authors(&root, "main..HEAD", 2).unwrap(),
+            ["carol@example.com", "test@example.com"]
+        );
+        assert!(
+            repo.commit_authors(&root, "HEAD..HEAD", 10)
+                .unwrap()
+                .is_empty()
+        );
+        assert!(repo.commit_authors(&root, "@{u}..HEAD", 10).is_err());
+        assert_eq!(
+            foreign_authors(
+                &repo.commit_authors(&root, "main..HEAD", 10).unwrap(),
+                Some("test@example.com")
+            ),
+            ["carol@example.com", "bob@example.com"]
+        );
+    }
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    #[test]
+    fn file_overlap_splits_two_file_lists() {
+        let overlap = FileOverlap::compute(
+            &strings(&["src/b.rs", "src/a.rs", "README.md", "src/a.rs"]),
+            &strings(&["src/c.rs", "README.md", "src/b.rs"]),
+        );
+        assert_eq!(overlap.only_first, ["src/a.rs"]);
+        assert_eq!(overlap.both, ["README.md", "src/b.rs"]);
+        assert_eq!(overlap.only_second, ["src/c.rs"]);
+    }
+
+    #[test]
+    fn file_overlap_of_two_branches_ignores_the_target_moving_on() {
+        let tmp = TempDir::new("file-overlap");
+        let root = tmp.path().join("repo");
+        init_repo(&root);
+        for (branch, files) in [
+            ("agent/first", ["first.txt", "shared.txt"]),
+            ("agent/second", ["shared.txt", "second.txt"]),
+        ] {
+            git(&root, &["checkout", "-q", "-b", branch, "main"]);
+            for file in files {
+                commit_file(&root, file, branch, file);
+            }
+        }
+        git(&root, &["checkout", "-q", "main"]);
+        commit_file(&root, "main.txt", "main\n", "main moves on");
+        let repo = CliGit::open(&root).unwrap();
+
+        let overlap = FileOverlap::compute(
+            &repo.branch_files("main", "agent/first").unwrap(),
+            &repo.branch_files("main", "agent/second").unwrap(),
+        );
+
+        assert_eq!(overlap.only_first, ["first.txt"]);
+        assert_eq!(overlap.both, ["shared.txt"]);
+        assert_eq!(overlap.only_second, ["second.txt"]);
+        let stat = repo.diff_stat("agent/first", "agent/second").unwrap();
+        for file in ["first.txt", "shared.txt", "second.txt"] {
+            assert!(stat.contains(file), "{}", stat);
+        }
+        assert!(!stat.contains("main.txt"), "{}", stat);
+    }
+
+    #[test]
+    fn parse_git_version_reads_major_and_minor() {
+        assert_eq!(parse_git_version("git version 2.39.5\n"), Some((2, 39)));
+        assert_eq!(
+            parse_git_version("git version 2.39.5 (Apple Git-154)"),
+            Some((2, 39))
+        );
+        assert_eq!(
+            parse_git_version("git version 2.45.1.windows.1"),
+            Some((2, 45))
+        );
+        assert_eq!(parse_git_version("git version 3"), None);
+        assert_eq!(parse_git_version("git version unknown"), None);
+        assert_eq!(parse_git_version("hub version 2.14.2"), None);
+    }
+
+    const WRITE_TREE_CONFLICTS: &str = "\
+8944fd31200c224348b1a31e60d9a55b9e743eab
+conflict.txt
+src dir/other.rs
+src dir/other.rs
+
+Auto-merging conflict.txt
+CONFLICT (content): Merge conflict in conflict.txt
+";
+
+    #[test]
+    fn parse_merge_tree_lists_conflicted_files() {
+        assert_eq!(
+            parse_merge_tree(WRITE_TREE_CONFLICTS, Some(1)),
+            Some(ConflictPrediction::Conflicts(strings(&[
+                "conflict.txt",
+                "src dir/other.rs"
+            ])))
+        );
+    }
+
+    #[test]
+    fn parse_merge_tree_maps_the_exit_status() {
+        assert_eq!(
+            parse_merge_tree("e157c65367ff8754644eb7cd8a2f0fd47a5e86f4\n", Some(0)),
+            Some(ConflictPrediction::Clean)
+        );
+        assert_eq!(parse_merge_tree("", Some(128)), None);
+        assert_eq!(parse_merge_tree("", Some(1)), None);
+        assert_eq!(parse_merge_tree("", None), None);
+    }
+
+    const LEGACY_CONFLICTS: &str = "\
+added in remote
+  their  100644 3e757656cf36eca53338e520d134963a44f793f8 added.txt
+@@ -0,0 +1 @@
++new
+changed in both
+  base   100644 de980441c3ab03a8c07dda1ad27b8a11f39deb1e conflict.txt
+  our    100644 af703352c64a2d88d4f62818fa68e6ae91241dfd conflict.txt
+  their  100644 a068d3f17a4fdfb930c07e052711e74bce280614 conflict.txt
+@@ -1,3 +1,7 @@
+ a
++<<<<<<< .our
+ MAIN
++=======
++TOPIC
++>>>>>>> .their
+ c
+changed in both
+  base   100644 975fbec8256d3e8a3797e7a3611380f27c49f4ac src dir/other.rs
+  our    100644 28ce6a8b26aa170e1de65536fe8abe1832bd3242 src dir/other.rs
+  their  100644 718f4d2ff533cf8ead8d3556cf43912bd245fbc4 src dir/other.rs
+@@ -1 +1,5 @@
++<<<<<<< .our
+ m
++=======
++t
++>>>>>>> .their
+";
+
+    #[test]
+    fn parse_legacy_merge_tree_finds_conflict_markers() {
+        assert_eq!(
+            parse_legacy_merge_tree(LEGACY_CONFLICTS),
+            ConflictPrediction::Conflicts(strings(&["conflict.txt", "src dir/other.rs"]))
+        );
+    }
+
+    #[test]
+    fn parse_legacy_merge_tree_ignores_sections_that_merge_cleanly() {
+        let clean = "\
+changed in both
+  base   100644 de980441c3ab03a8c07dda1ad27b8a11f39deb1e notes.txt
+  our    100644 af703352c64a2d88d4f62818fa68e6ae91241dfd notes.txt
+  their  100644 a068d3f17a4fdfb930c07e052711e74bce280614 notes.txt
+@@ -1,3 +1,3 @@
+-a
++A
+ b
+-c
++C
+added in local
+  our    100644 3e757656cf36eca53338e520d134963a44f793f8 added.txt
+@@ -0,0 +1 @@
+++<<<<<<< not a marker
+";
+        assert_eq!(parse_legacy_merge_tree(clean), ConflictPrediction::Clean);
+        assert_eq!(parse_legacy_merge_tree(""), ConflictPrediction::Clean);
+    }
+
+    // `main` and `agent/clean` change different files; `agent/conflict` rewrites the line `main`
+    // changed.
+    fn diverged_repo(tmp: &TempDir) -> CliGit {
+        let root = tmp.path().join("repo");
+        init_repo(&root);
+        commit_file(&root, "shared.txt", "a\nb\nc\n", "shared");
+        git(&root, &["checkout", "-q", "-b", "agent/conflict"]);
+        commit_file(&root, "shared.txt", "a\nAGENT\nc\n", "agent");
+        git(&root, &["checkout", "-q", "-b", "agent/clean", "main"]);
+        commit_file(&root, "other.txt", "other\n", "other");
+        git(&root, &["checkout", "-q", "main"]);
+        commit_file(&root, "shared.txt", "a\nMAIN\nc\n", "main");
+        CliGit::open(&root).unwrap()
+    }
+
+    #[test]
+    fn predict_conflicts_on_a_temp_repo() {
+        let tmp = TempDir::new("predict-conflicts");
+        let repo = diverged_repo(&tmp);
+        assert_eq!(
+            repo.predict_conflicts("main", "agent/clean").unwrap(),
+            Some(ConflictPrediction::Clean)
+        );
+        assert_eq!(
+            repo.predict_conflicts("main", "agent/conflict").unwrap(),
+            Some(ConflictPrediction::Conflicts(strings(&["shared.txt"])))
+        );
+        assert_eq!(
+            repo.predict_conflicts("main", "agent/missing").unwrap(),
+            None
+        );
+        // Nothing was merged along the way.
+        assert_eq!(git(repo.root(), &["status", "--porcelain"]), "");
+    }
+
+    #[test]
+    fn parse_legacy_merge_tree_reads_real_output() {
+        let tmp = TempDir::new("legacy-merge-tree");
+        let repo = diverged_repo(&tmp);
+        let root = repo.root();
+        for (branch, expected) in [
+            ("agent/clean", ConflictPrediction::Clean),
+            (
+                "agent/conflict",
+                ConflictPrediction::Conflicts(strings(&["shared.txt"])),
+            ),
+        ] {
+            let base = git(root, &["merge-base", "main", branch]);
+            let output = git(root, &["merge-tree", base.trim(), "main", branch]);
+            assert_eq!(parse_legacy_merge_tree(&output), expected, "{}", branch);
+        }
+    }
+
+    #[test]
+    fn current_branch_in_reads_the_worktree_head() {
+        let tmp = TempDir::new("current-branch");
+        let repo = repo_with_branch(&tmp, "agent/login");
+        git(repo.root(), &["branch", "agent/signup"]);
+        let worktree = tmp.path().join("login");
+        git(
+            repo.root(),
+            &[
+                "worktree",
+                "add",
+                "-q",
+                worktree.to_str().unwrap(),
+                "agent/login",
+            ],
+        );
+        let listed = repo.list_worktrees().unwrap();
+
+        assert_eq!(repo.current_branch().unwrap().as_deref(), Some("main"));
+        assert_eq!(
+            repo.current_branch_in(&worktree).unwrap().as_deref(),
+            Some("agent/login")
+        );
+
+        // The listing goes stale once the worktree switches; the path-scoped lookup does not.
+        git(&worktree, &["checkout", "-q", "agent/signup"]);
+        assert!(listed.iter().any(|entry| {
+            entry.path == worktree && entry.branch.as_deref() == Some("agent/login")
+        }));
+        assert_eq!(
+            repo.current_branch_in(&worktree).unwrap().as_deref(),
+            Some("agent/signup")
+        );
+
+        git(&worktree, &["checkout", "-q", "--detach"]);
+        assert_eq!(repo.current_branch_in(&worktree).unwrap(), None);
+        assert_eq!(repo.current_branch().unwrap().as_deref(), Some("main"));
+    }
+
+    // Runs a git command expected to stop on conflicts.
+    fn git_conflicts(dir: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .unwrap();
+        assert!(!status.success(), "git {:?} should have stopped", args);
+    }
+
+    #[test]
+    fn health_check_finds_nothing_in_a_clean_checkout() {
+        let tmp = TempDir::new("health-clean");
+        let repo = diverged_repo(&tmp);
+        assert_eq!(repo.health_check().unwrap(), []);
+    }
+
+    #[test]
+    fn health_check_detects_an_interrupted_merge() {
+        let tmp = TempDir::new("health-merge");
+        let repo = diverged_repo(&tmp);
+        git_conflicts(repo.root(), &["merge", "agent/conflict"]);
+        assert_eq!(repo.health_check().unwrap(), [HealthIssue::MergeInProgress]);
+    }
+
+    #[test]
+    fn health_check_detects_an_interrupted_rebase_without_reporting_its_detached_head() {
+        let tmp = TempDir::new("health-rebase");
+        let repo = diverged_repo(&tmp);
+        git_conflicts(repo.root(), &["rebase", "agent/conflict"]);
+        assert_eq!(
+            repo.health_check().unwrap(),
+            [HealthIssue::RebaseInProgress]
+        );
+    }
+
+    #[test]
+    fn health_check_detects_an_interrupted_cherry_pick() {
+        let tmp = TempDir::new("health-cherry-pick");
+        let repo = diverged_repo(&tmp);
+        git_conflicts(repo.root(), &["cherry-pick", "agent/conflict"]);
+        assert_eq!(
+            repo.health_check().unwrap(),
+            [HealthIssue::CherryPickInProgress]
+        );
+    }
+
+    #[test]
+    fn health_check_detects_a_detached_head() {
+        let tmp = TempDir::new("health-detached");
+        let repo = diverged_repo(&tmp);
+        git(repo.root(), &["checkout", "-q", "--detach"]);
+        assert_eq!(repo.health_check().unwrap(), [HealthIssue::DetachedHead]);
+    }
+
+    #[test]
+    fn health_check_reports_an_index_lock_once_it_is_stale() {
+        let tmp = TempDir::new("health-lock");
+        let repo = diverged_repo(&tmp);
+        let lock = repo.root().join(".git/index.lock");
+        let file = fs::File::create(&lock).unwrap();
+        assert_eq!(repo.health_check().unwrap(), []);
+
+        let modified = std::time::SystemTime::now() - STALE_INDEX_LOCK_AGE * 2;
+        file.set_modified(modified).unwrap();
+        let issues = repo.health_check().unwrap();
+        assert!(
+            matches!(
+                issues.as_slice(),
+                [HealthIssue::StaleIndexLock { path, age }]
+                    if *path == lock && *age > STALE_INDEX_LOCK_AGE
+            ),
+            "{:?}",
+            issues
+        );
+    }
+
+    #[test]
+    fn repositories_sharing_a_worktree_base_are_kept_apart() {
+        let tmp = TempDir::new("shared-base");
+        let mut cfg = ConfigState::load_from(&tmp.path().join("config")).unwrap();
+        let shared = tmp.path().join("agents");
+        let repos = ["alpha", "beta"].map(|name| {
+            init_repo(&tmp.path().join(name));
+            CliGit::open(&tmp.path().join(name)).unwrap()
+        });
+
+        cfg.config.worktree_base_override = Some(shared.to_string_lossy().to_string());
+        let bases = repos
+            .each_ref()
+            .map(|repo| repo.worktree_base(&cfg).unwrap());
+        assert_eq!(bases, [shared.join("alpha"), shared.join("beta")]);
+
+        cfg.config.worktree_base_override =
+            Some(format!("{}/{{repo_name}}-agents", shared.display()));
+        let bases = repos
+            .each_ref()
+            .map(|repo| repo.worktree_base(&cfg).unwrap());
+        assert_eq!(
+            bases,
+            [shared.join("alpha-agents"), shared.join("beta-agents")]
+        );
+
+        let worktree = shared.join("fix-login");
+        repos[0]
+            .create_worktree("fix-login", &worktree, "main", false)
+            .unwrap();
+        assert_eq!(
+            worktree_common_dir(&worktree),
+            repos[0].common_git_dir().unwrap().canonicalize().ok()
+        );
+        assert_ne!(
+            worktree_common_dir(&worktree),
+            repos[1].common_git_dir().unwrap().canonicalize().ok()
+        );
+        assert_eq!(worktree_common_dir(&tmp.path().join("config")), None);
+    }
+
+    #[test]
+    fn resolve_base_path_expands_home_and_resolves_relative_paths() {
+        let home = directories::BaseDirs::new()
+            .unwrap()
+            .home_dir()
+            .to_path_buf();
+        let parent = Path::new("/work/projects");
+        let cases = [
+            ("~", home.clone()),
+            ("~/agents/api", home.join("agents/api")),
+            ("/srv/agents/api", PathBuf::from("/srv/agents/api")),
+            ("agents/api", parent.join("agents/api")),
+            ("./agents/../worktrees/api", parent.join("worktrees/api")),
+            ("../agents", PathBuf::from("/work/agents")),
+            (
+                "/work/projects/api/../api-agents",
+                parent.join("api-agents"),
+            ),
+        ];
+        for (rendered, expected) in cases {
+            assert_eq!(
+                resolve_base_path(rendered, parent).unwrap(),
+                expected,
+                "{}",
+                rendered
+            );
+        }
+
+        let err = resolve_base_path("~bob/agents", parent).unwrap_err();
+        assert!(err.to_string().contains("~user"), "{}", err);
+    }
+
+    #[test]
+    fn worktree_base_override_resolves_placeholders_and_is_validated() {
+        let tmp = TempDir::new("base-override");
+        let mut cfg = ConfigState::load_from(&tmp.path().join("config")).unwrap();
+        let root = tmp.path().join("api");
+        init_repo(&root);
+        let repo = CliGit::open(&root).unwrap();
+        let parent = repo.root().parent().unwrap().to_path_buf();
+        let mut base = |pattern: &str| {
+            cfg.config.worktree_base_override = Some(pattern.to_string());
+            repo.worktree_base(&cfg)
+        };
+
+        assert_eq!(
+            base("{repo_root}/../{repo_name}-agents").unwrap(),
+            parent.join("api-agents")
+        );
+        assert_eq!(
+            base("agents/{repo_name}").unwrap(),
+            parent.join("agents/api")
+        );
+        // Without a placeholder the repository name is appended.
+        assert_eq!(base("agents").unwrap(), parent.join("agents/api"));
+
+        let err = base("{repo_root}").unwrap_err();
+        assert!(
+            err.to_string().contains("is the repository root"),
+            "{}",
+            err
+        );
+        let err = base("{repo_root}/agents").unwrap_err();
+        assert!(
+            err.to_string().contains("is inside the existing worktree"),
+            "{}",
+            err
+        );
+        assert!(base("~other/{repo_name}").is_err());
+    }
+
+    // `diverged_repo` with each agent branch checked out in its own worktree.
+    fn diverged_worktrees(tmp: &TempDir) -> (CliGit, PathBuf, PathBuf) {
+        let repo = diverged_repo(tmp);
+        let [clean, conflict] = ["clean", "conflict"].map(|name| {
+            let path = tmp.path().join(name);
+            let branch = format!("agent/{}", name);
+            git(
+                repo.root(),
+                &["worktree", "add", "-q", path.to_str().unwrap(), &branch],
+            );
+            path
+        });
+        (repo, clean, conflict)
+    }
+
+    #[test]
+    fn merge_branch_in_merges_inside_the_worktree_only() {
+        let tmp = TempDir::new("merge-in");
+        let (repo, clean, _) = diverged_worktrees(&tmp);
+        let main_head = git(repo.root(), &["rev-parse", "main"]);
+
+        repo.merge_branch_in(&clean, "agent/conflict").unwrap();
+
+        assert_eq!(
+            fs::read_to_string(clean.join("shared.txt")).unwrap(),
+            "a\nAGENT\nc\n"
+        );
+        assert_eq!(
+            git(&clean, &["rev-parse", "HEAD^2"]),
+            git(repo.root(), &["rev-parse", "agent/conflict"])
+        );
+        assert_eq!(git(repo.root(), &["rev-parse", "HEAD"]), main_head);
+        assert_eq!(git(repo.root(), &["status", "--porcelain"]), "");
+        assert!(!repo.merge_in_progress(repo.root()).unwrap());
+    }
+
+    #[test]
+    fn merge_branch_in_leaves_conflicts_in_the_worktree() {
+        let tmp = TempDir::new("merge-in-conflict");
+        let (repo, _, conflict) = diverged_worktrees(&tmp);
+
+        let err = repo.merge_branch_in(&conflict, "main").unwrap_err();
+
+        assert_eq!(
+            err.downcast_ref::<MergeError>(),
+            Some(&MergeError::Conflicts {
+                worktree: conflict.clone(),
+                files: strings(&["shared.txt"]),
+            })
+        );
+        assert!(repo.merge_in_progress(&conflict).unwrap());
+        assert!(!repo.merge_in_progress(repo.root()).unwrap());
+        assert_eq!(git(repo.root(), &["status", "--porcelain"]), "");
+
+        repo.abort_merge_in(&conflict).unwrap();
+        assert!(!repo.merge_in_progress(&conflict).unwrap());
+    }
+
+    #[test]
+    fn merge_branch_in_refuses_the_checked_out_branch() {
+        let tmp = TempDir::new("merge-in-same");
+        let (repo, clean, _) = diverged_worktrees(&tmp);
+        let err = repo
+            .merge_branch_in(&clean, "refs/heads/agent/clean")
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<MergeError>(),
+            Some(MergeError::SameRef { .. })
+        ));
+    }
+
+    #[test]
+    fn repair_worktrees_follows_a_moved_worktree() {
+        let tmp = TempDir::new("repair");
+        let repo = repo_with_branch(&tmp, "agent/login");
+        let original = tmp.path().join("login");
+        git(
+            repo.root(),
+            &[
+                "worktree",
+                "add",
+                "-q",
+                original.to_str().unwrap(),
+                "agent/login",
+            ],
+        );
+        let moved = tmp.path().join("moved-login");
+        fs::rename(&original, &moved).unwrap();
+
+        let stale = repo.list_worktrees().unwrap();
+        let entry = stale.iter().find(|entry| entry.path == original).unwrap();
+        assert!(entry.prunable);
+        assert_eq!(entry.branch.as_deref(), Some("agent/login"));
+
+        let report = repo.repair_worktrees(std::slice::from_ref(&moved)).unwrap();
+        assert!(report.contains("gitdir"), "{}", report);
+
+        let repaired = repo.list_worktrees().unwrap();
+        assert!(repaired.iter().all(|entry| entry.path != original));
+        let entry = repaired.iter().find(|entry| entry.path == moved).unwrap();
+        assert!(!entry.prunable);
+        assert_eq!(entry.branch.as_deref(), Some("agent/login"));
+        assert_eq!(
+            repo.current_branch_in(&moved).unwrap().as_deref(),
+            Some("agent/login")
+        );
+        assert!(!repo.is_worktree_dirty(&moved, &[]).unwrap());
+    }
+
+    // A clone of `origin` with the test identity, so it can commit.
+    fn clone_of(origin: &Path, dir: &Path) {
+        git(
+            origin.parent().unwrap(),
+            &[
+                "clone",
+                "-q",
+                origin.to_str().unwrap(),
+                dir.to_str().unwrap(),
+            ],
+        );
+        git(dir, &["config", "user.name", "Test"]);
+        git(dir, &["config", "user.email", "test@example.com"]);
+        git(dir, &["config", "commit.gpgsign", "false"]);
+    }
+
+    fn divergence(ahead: usize, behind: usize) -> Option<UpstreamDivergence> {
+        Some(UpstreamDivergence {
+            upstream: "origin/agent/login".to_string(),
+            ahead,
+            behind,
+        })
+    }
+
+    #[test]
+    fn upstream_divergence_follows_a_second_clone() {
+        let tmp = TempDir::new("upstream");
+        let seed = tmp.path().join("seed");
+        init_repo(&seed);
+        let origin = tmp.path().join("origin.git");
+        git(
+            tmp.path(),
+            &["clone", "-q", "--bare", "seed", origin.to_str().unwrap()],
+        );
+        let colleague = tmp.path().join("colleague");
+        clone_of(&origin, &colleague);
+        git(&colleague, &["checkout", "-q", "-b", "agent/login"]);
+        commit_file(&colleague, "login.txt", "v1\n", "Start the login");
+        git(&colleague, &["push", "-q", "-u", "origin", "agent/login"]);
+
+        let local = tmp.path().join("local");
+        clone_of(&origin, &local);
+        let worktree = tmp.path().join("login");
+        git(
+            &local,
+            &[
+                "worktree",
+                "add",
+                "-q",
+                "--track",
+                "-b",
+                "agent/login",
+                worktree.to_str().unwrap(),
+                "origin/agent/login",
+            ],
+        );
+        let repo = CliGit::open(&local).unwrap();
+        assert_eq!(
+            repo.upstream_divergence(&worktree).unwrap(),
+            divergence(0, 0)
+        );
+
+        // The colleague pushes: the worktree is behind and fast-forwards.
+        commit_file(&colleague, "login.txt", "v2\n", "Validate the login");
+        git(&colleague, &["push", "-q"]);
+        git(&local, &["fetch", "-q"]);
+        assert_eq!(
+            repo.upstream_divergence(&worktree).unwrap(),
+            divergence(0, 1)
+        );
+        assert_eq!(
+            repo.commit_subjects(&worktree, "HEAD..@{u}").unwrap(),
+            ["Validate the login"]
+        );
+        pull_fast_forward(&worktree, None).unwrap();
+        assert_eq!(
+            repo.upstream_divergence(&worktree).unwrap(),
+            divergence(0, 0)
+        );
+        assert_eq!(
+            fs::read_to_string(worktree.join("login.txt")).unwrap(),
+            "v2\n"
+        );
+
+        // Both sides commit: a fast-forward is refused and a rebase reconciles them.
+        commit_file(&colleague, "login.txt", "v3\n", "Remember the user");
+        git(&colleague, &["push", "-q"]);
+        commit_file(&worktree, "notes.txt", "local\n", "Local work");
+        git(&local, &["fetch", "-q"]);
+        assert_eq!(
+            repo.upstream_divergence(&worktree).unwrap(),
+            divergence(1, 1)
+        );
+        assert!(pull_fast_forward(&worktree, None).is_err());
+        repo.rebase_onto(&worktree, "@{u}").unwrap();
+        assert_eq!(
+            repo.upstream_divergence(&worktree).unwrap(),
+            divergence(1, 0)
+        );
+        assert_eq!(
+            fs::read_to_string(worktree.join("login.txt")).unwrap(),
+            "v3\n"
+        );
+
+        git(&worktree, &["checkout", "-q", "-b", "agent/untracked"]);
+        assert_eq!(repo.upstream_divergence(&worktree).unwrap(), None);
+    }
+
+    #[test]
+    fn commits_between_lists_the_branch_commits_newest_first() {
+        let tmp = TempDir::new("commits-between");
+        let repo = diverged_repo(&tmp);
+        commit_file(repo.root(), "extra.txt", "extra\n", "Extra on main");
+        git(repo.root(), &["checkout", "-q", "agent/clean"]);
+        commit_file(repo.root(), "more.txt", "more\n", "More work");
+        git(repo.root(), &["checkout", "-q", "main"]);
+
+        let commits = repo.commits_between("main", "agent/clean").unwrap();
+        assert_eq!(
+            commits
+                .iter()
+                .map(|commit| commit.subject.as_str())
+                .collect::<Vec<_>>(),
+            ["More work", "other"]
+        );
+        assert_eq!(
+            commits[0].sha,
+            git(repo.root(), &["rev-parse", "agent/clean"]).trim()
+        );
+        assert!(
+            repo.commits_between("agent/clean", "agent/clean")
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn cherry_pick_applies_a_clean_commit() {
+        let tmp = TempDir::new("cherry-pick");
+        let repo = diverged_repo(&tmp);
+        let commit = &repo.commits_between("main", "agent/clean").unwrap()[0];
+
+        assert!(repo.cherry_pick(repo.root(), &commit.sha).unwrap());
+
+        assert_eq!(
+            fs::read_to_string(repo.root().join("other.txt")).unwrap(),
+            "other\n"
+        );
+        assert_eq!(git(repo.root(), &["log", "-1", "--format=%s"]), "other\n");
+        assert_eq!(repo.health_check().unwrap(), []);
+    }
+
+    #[test]
+    fn cherry_pick_stops_on_conflicts_until_resolved_or_aborted() {
+        let tmp = TempDir::new("cherry-pick-conflict");
+        let repo = diverged_repo(&tmp);
+        let commit = &repo.commits_between("main", "agent/conflict").unwrap()[0];
+        let head = git(repo.root(), &["rev-parse", "HEAD"]);
+
+        assert!(!repo.cherry_pick(repo.root(), &commit.sha).unwrap());
+        assert_eq!(
+            repo.health_check().unwrap(),
+            [HealthIssue::CherryPickInProgress]
+        );
+        assert!(
+            repo.cherry_pick_step(repo.root(), CherryPickStep::Abort)
+                .unwrap()
+        );
+        assert_eq!(git(repo.root(), &["rev-parse", "HEAD"]), head);
+        assert_eq!(git(repo.root(), &["status", "--porcelain"]), "");
+
+        assert!(!repo.cherry_pick(repo.root(), &commit.sha).unwrap());
+        assert!(
+            !repo
+                .cherry_pick_step(repo.root(), CherryPickStep::Continue)
+                .unwrap()
+        );
+        fs::write(repo.root().join("shared.txt"), "a\nBOTH\nc\n").unwrap();
+        git(repo.root(), &["add", "shared.txt"]);
+        assert!(
+            repo.cherry_pick_step(repo.root(), CherryPickStep::Continue)
+                .unwrap()
+        );
+        assert_eq!(git(repo.root(), &["log", "-1", "--format=%s"]), "agent\n");
+        assert_eq!(repo.health_check().unwrap(), []);
+    }
+
+    fn branch_ref(name: &str, remote_ref: Option<&str>) -> BranchRef {
+        BranchRef {
+            name: name.to_string(),
+            remote_ref: remote_ref.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn matching_branches_prefers_local_refs_and_skips_remote_heads() {
+        let refnames = "\
+refs/heads/agent/login
+refs/heads/main
+refs/remotes/origin/HEAD
+refs/remotes/origin/agent/login
+refs/remotes/origin/agent/payments
+refs/remotes/upstream/agent/payments
+refs/remotes/origin/main
+refs/tags/agent/v1
+";
+
+        assert_eq!(
+            matching_branches(refnames, "agent/*"),
+            [
+                branch_ref("agent/login", None),
+                branch_ref("agent/payments", Some("origin/agent/payments")),
+            ]
+        );
+        assert_eq!(
+            matching_branches(refnames, "*"),
+            [
+                branch_ref("agent/login", None),
+                branch_ref("main", None),
+                branch_ref("agent/payments", Some("origin/agent/payments")),
+            ]
+        );
+        assert!(matching_branches(refnames, "feature/*").is_empty());
+    }
+
+    #[test]
+    fn branches_matching_lists_fetched_branches_of_a_colleague() {
+        let tmp = TempDir::new("branches-matching");
+        let origin = tmp.path().join("origin");
+        init_repo(&origin);
+        git(&origin, &["branch", "agent/shared"]);
+        git(&origin, &["branch", "agent/theirs"]);
+        let mine = tmp.path().join("mine");
+        clone_of(&origin, &mine);
+        git(&mine, &["branch", "agent/mine"]);
+        git(&mine, &["branch", "agent/shared", "origin/agent/shared"]);
+        let repo = CliGit::open(&mine).unwrap();
+
+        assert_eq!(
+            repo.branches_matching("agent/*", false).unwrap(),
+            [
+                branch_ref("agent/mine", None),
+                branch_ref("agent/shared", None)
+            ]
+        );
+        assert_eq!(
+            repo.branches_matching("agent/*", true).unwrap(),
+            [
+                branch_ref("agent/mine", None),
+                branch_ref("agent/shared", None),
+                branch_ref("agent/theirs", Some("origin/agent/theirs")),
+            ]
+        );
+    }
+
+    #[test]
+    fn dubious_ownership_is_recognised_in_git_stderr() {
+        let rejected = "fatal: detected dubious ownership in repository at '/srv/shared repo'\n\
+            To add an exception for this directory, call:\n\
+            \n\
+            \tgit config --global --add safe.directory '/srv/shared repo'\n";
+        let cases = [
+            (rejected, Some(Some(PathBuf::from("/srv/shared repo")))),
+            (
+                "fatal: detected dubious ownership in repository\n",
+                Some(None),
+            ),
+            ("fatal: not a git repository (or any parent)\n", None),
+            ("", None),
+        ];
+        for (stderr, expected) in cases {
+            assert_eq!(dubious_ownership_path(stderr), expected, "{:?}", stderr);
+        }
+    }
+
+    #[test]
+    fn run_git_records_nothing_for_ordinary_failures() {
+        let tmp = TempDir::new("git-ordinary-failure");
+        let root = tmp.path().join("repo");
+        init_repo(&root);
+        take_unsafe_directories();
+
+        let output = run_git(&root, ["rev-parse", "--verify", "no-such-ref"]).unwrap();
+
+        assert!(!output.status.success());
+        assert!(take_unsafe_directories().is_empty());
+    }
+
+    #[test]
+    fn merge_no_commit_stages_a_clean_merge_until_it_is_committed() {
+        let tmp = TempDir::new("merge-no-commit");
+        let repo = diverged_repo(&tmp);
+        let head = git(repo.root(), &["rev-parse", "HEAD"]);
+
+        repo.merge_no_commit("agent/clean", "main").unwrap();
+
+        assert_eq!(git(repo.root(), &["rev-parse", "HEAD"]), head);
+        assert!(repo.staged_diffstat().unwrap().contains("other.txt"));
+        assert_eq!(
+            repo.unmerged_paths(repo.root()).unwrap(),
+            Vec::<String>::new()
+        );
+
+        repo.commit_merge("Merge agent/clean").unwrap();
+
+        assert_eq!(
+            git(repo.root(), &["log", "-1", "--format=%s"]).trim(),
+            "Merge agent/clean"
+        );
+        let parents = git(repo.root(), &["log", "-1", "--format=%P"]);
+        assert_eq!(parents.split_whitespace().count(), 2);
+        assert_eq!(repo.staged_diffstat().unwrap(), "");
+    }
+
+    #[test]
+    fn merge_no_commit_leaves_conflicts_for_review_on_the_target_branch() {
+        let tmp = TempDir::new("merge-no-commit-conflict");
+        let repo = diverged_repo(&tmp);
+        git(repo.root(), &["checkout", "-q", "agent/clean"]);
+
+        repo.merge_no_commit("agent/conflict", "main").unwrap();
+
+        assert_eq!(repo.current_branch().unwrap().as_deref(), Some("main"));
+        assert_eq!(
+            repo.unmerged_paths(repo.root()).unwrap(),
+            strings(&["shared.txt"])
+        );
+        assert!(repo.commit_merge("Merge agent/conflict").is_err());
+        assert_eq!(repo.health_check().unwrap(), [HealthIssue::MergeInProgress]);
+    }
+
+    fn wrapped(prefix: &[&str], path_map: &[(&str, &str)]) -> Invocation {
+        Invocation::new(
+            &strings(prefix),
+            &path_map
+                .iter()
+                .map(|(local, wrapped)| (local.to_string(), wrapped.to_string()))
+                .collect(),
+        )
+    }
+
+    fn argv(command: &Command) -> Vec<String> {
+        std::iter::once(command.get_program())
+            .chain(command.get_args())
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn plain_invocation_runs_git_in_the_directory() {
+        let command = Invocation::default().command(Some(Path::new("/work/repo")));
+        assert_eq!(argv(&command), ["git"]);
+        assert_eq!(command.get_current_dir(), Some(Path::new("/work/repo")));
+    }
+
+    #[test]
+    fn prefixed_invocation_wraps_git_and_passes_the_mapped_directory() {
+        let invocation = wrapped(
+            &["devcontainer", "exec", "--"],
+            &[("/home/me/src", "/workspaces")],
+        );
+
+        let command = invocation.command(Some(Path::new("/home/me/src/repo")));
+        assert_eq!(
+            argv(&command),
+            [
+                "devcontainer",
+                "exec",
+                "--",
+                "git",
+                "-C",
+                "/workspaces/repo"
+            ]
+        );
+        assert_eq!(command.get_current_dir(), None);
+
+        let command = invocation.command(None);
+        assert_eq!(argv(&command), ["devcontainer", "exec", "--", "git"]);
+    }
+
+    #[test]
+    fn path_map_translates_both_ways_and_prefers_the_longest_prefix() {
+        let invocation = wrapped(
+            &[],
+            &[
+                ("/home/me/src", "/workspaces"),
+                ("/home/me/src/big", "/mnt/big"),
+            ],
+        );
+        let cases = [
+            ("/home/me/src/repo/a.txt", "/workspaces/repo/a.txt"),
+            ("/home/me/src/big/repo", "/mnt/big/repo"),
+            ("/home/me/src", "/workspaces"),
+            // Only whole components match.
+            ("/home/me/srcs/repo", "/home/me/srcs/repo"),
+            ("/tmp/elsewhere", "/tmp/elsewhere"),
+        ];
+        for (local, mapped) in cases {
+            assert_eq!(
+                invocation.map_path(Path::new(local)),
+                Path::new(mapped),
+                "{:?}",
+                local
+            );
+            assert_eq!(
+                invocation.unmap_path(Path::new(mapped)),
+                Path::new(local),
+                "{:?}",
+                mapped
+            );
+        }
+    }
+
+    #[test]
+    fn hooks_bypass_env_appends_after_existing_config_entries() {
+        let cases = [
+            (None, "0", "1"),
+            (Some("2"), "2", "3"),
+            (Some(" 1\n"), "1", "2"),
+            (Some("garbage"), "0", "1"),
+        ];
+        for (existing, index, count) in cases {
+            assert_eq!(
+                hooks_bypass_env(existing),
+                [
+                    (
+                        format!("GIT_CONFIG_KEY_{}", index),
+                        "core.hooksPath".to_string()
+                    ),
+                    (
+                        format!("GIT_CONFIG_VALUE_{}", index),
+                        "/dev/null".to_string()
+                    ),
+                    ("GIT_CONFIG_COUNT".to_string(), count.to_string()),
+                ],
+                "{:?}",
+                existing
+            );
+        }
+    }
+
+    // Relies on a shell hook and on /dev/null as the empty hooks path.
+    #[cfg(unix)]
+    #[test]
+    fn hooks_bypass_env_stops_git_from_running_hooks() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = TempDir::new("hooks-bypass");
+        let root = tmp.path().join("repo");
+        init_repo(&root);
+        let hook = root.join(".git/hooks/pre-commit");
+        fs::write(&hook, "#!/bin/sh\nexit 1\n").unwrap();
+        fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+        let commit = |env: Vec<(String, String)>| {
+            Command::new("git")
+                .current_dir(&root)
+                .args(["commit", "-q", "--allow-empty", "-m", "empty"])
+                .envs(env)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .unwrap()
+        };
+
+        assert!(!commit(Vec::new()).success());
+        assert!(commit(hooks_bypass_env(None)).success());
+    }
+
+    fn stash(reference: &str, created_at: u64, branch: Option<&str>, message: &str) -> StashEntry {
+        StashEntry {
+            reference: reference.to_string(),
+            created_at,
+            branch: branch.map(str::to_string),
+            message: message.to_string(),
+        }
+    }
+
+    #[test]
+    fn parse_marked_stashes_keeps_only_marked_entries() {
+        let output = [
+            "stash@{0}\x001700000300\x00On agent/login: agent-manager: before merge",
+            "stash@{1}\x001700000200\x00On main: my own stash",
+            "stash@{2}\x001700000100\x00WIP on agent/search: 1a2b3c4 agent-manager: wip",
+            "stash@{3}\x00not-a-time\x00On (no branch): agent-manager: detached: with colon",
+            "stash@{4}\x001700000000\x00agent-manager: no subject prefix",
+            "stash@{5}\x00truncated",
+            "",
+        ]
+        .join("\n");
+
+        assert_eq!(
+            parse_marked_stashes(&output),
+            [
+                stash(
+                    "stash@{0}",
+                    1_700_000_300,
+                    Some("agent/login"),
+                    "before merge"
+                ),
+                stash("stash@{3}", 0, None, "detached: with colon"),
+                stash("stash@{4}", 1_700_000_000, None, "no subject prefix"),
+            ]
+        );
+    }
+
+    #[test]
+    fn list_marked_stashes_reads_a_real_stash_list() {
+        let tmp = TempDir::new("marked-stashes");
+        let repo = repo_with_branch(&tmp, "agent/login");
+        let root = repo.root().to_path_buf();
+        for (content, message) in [
+            ("one", format!("{} first", STASH_MARKER)),
+            ("two", "unrelated".to_string()),
+            ("three", format!("{} second", STASH_MARKER)),
+        ] {
+            fs::write(root.join("README.md"), content).unwrap();
+            git(&root, &["stash", "push", "-q", "-m", &message]);
+        }
+
+        let stashes = repo.list_marked_stashes().unwrap();
+
+        let summary = stashes
+            .iter()
+            .map(|entry| {
+                (
+                    entry.reference.as_str(),
+                    entry.branch.as_deref(),
+                    entry.message.as_str(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                ("stash@{0}", Some("main"), "second"),
+                ("stash@{2}", Some("main"), "first"),
+            ]
+        );
+        assert!(stashes.iter().all(|entry| entry.created_at > 0));
+    }
 }
@@ -0,0 +1,159 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::archive;
+
+// Bumped whenever the archive layout changes; archives from another version are refused.
+const HANDOFF_VERSION: u32 = 1;
+
+const MANIFEST_ENTRY: &str = "manifest.toml";
+const BUNDLE_ENTRY: &str = "branch.bundle";
+const TEMPLATE_ENTRY: &str = "agent-template";
+const METADATA_ENTRY: &str = "metadata.toml";
+const NOTES_ENTRY: &str = "notes.md";
+const PATCH_ENTRY: &str = "changes.patch";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    pub tool_version: String,
+    pub branch: String,
+    pub created_at: u64,
+}
+
+impl Manifest {
+    pub fn new(branch: &str) -> Self {
+        Self {
+            version: HANDOFF_VERSION,
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            branch: branch.to_string(),
+            created_at: archive::now_secs(),
+        }
+    }
+}
+
+// Everything needed to continue a worktree on another machine: the branch as a git bundle, the
+// files kept out of git, and the uncommitted changes as a binary patch.
+#[derive(Debug, Clone)]
+pub struct Handoff {
+    pub manifest: Manifest,
+    pub bundle: Vec<u8>,
+    pub template: Option<String>,
+    pub metadata: Option<String>,
+    pub notes: Option<String>,
+    pub patch: Vec<u8>,
+}
+
+impl Handoff {
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let file =
+            File::create(path).with_context(|| format!("Unable to create {}", path.display()))?;
+        let mut archive = tar::Builder::new(file);
+        let manifest = toml::to_string_pretty(&self.manifest)?;
+        let entries = [
+            (MANIFEST_ENTRY, Some(manifest.as_bytes())),
+            (BUNDLE_ENTRY, Some(self.bundle.as_slice())),
+            (TEMPLATE_ENTRY, self.template.as_deref().map(str::as_bytes)),
+            (METADATA_ENTRY, self.metadata.as_deref().map(str::as_bytes)),
+            (NOTES_ENTRY, self.notes.as_deref().map(str::as_bytes)),
+            (PATCH_ENTRY, Some(self.patch.as_slice())),
+        ];
+        for (name, content) in entries {
+            let Some(content) = content else {
+                continue;
+            };
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_mtime(self.manifest.created_at);
+            archive
+                .append_data(&mut header, name, content)
+                .with_context(|| format!("Unable to write {} to {}", name, path.display()))?;
+        }
+        archive
+            .into_inner()
+            .and_then(|file| file.sync_all())
+            .with_context(|| format!("Unable to write {}", path.display()))
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        let file =
+            File::open(path).with_context(|| format!("Unable to open {}", path.display()))?;
+        let mut archive = tar::Archive::new(file);
+        let mut manifest = None;
+        let mut bundle = None;
+        let mut template = None;
+        let mut metadata = None;
+        let mut notes = None;
+        let mut patch = Vec::new();
+        for entry in archive
+            .entries()
+            .with_context(|| format!("{} is not a handoff archive", path.display()))?
+        {
+            let mut entry = entry?;
+            let name = entry.path()?.to_string_lossy().into_owned();
+            let mut content = Vec::new();
+            entry
+                .read_to_end(&mut content)
+                .with_context(|| format!("Unable to read {} from {}", name, path.display()))?;
+            match name.as_str() {
+                MANIFEST_ENTRY => manifest = Some(String::from_utf8(content)?),
+                BUNDLE_ENTRY => bundle = Some(content),
+                TEMPLATE_ENTRY => template = Some(String::from_utf8(content)?),
+                METADATA_ENTRY => metadata = Some(String::from_utf8(content)?),
+                NOTES_ENTRY => notes = Some(String::from_utf8(content)?),
+                PATCH_ENTRY => patch = content,
+                _ => {}
+            }
+        }
+
+        let manifest =
+            manifest.ok_or_else(|| anyhow!("{} has no handoff manifest", path.display()))?;
+        // Only the version is read first: other fields may differ between formats.
+        let manifest: toml::Table = toml::from_str(&manifest)
+            .with_context(|| format!("The manifest of {} is invalid", path.display()))?;
+        let version = manifest.get("version").and_then(toml::Value::as_integer);
+        if version != Some(i64::from(HANDOFF_VERSION)) {
+            bail!(
+                "{} uses handoff format {} (written by AgentManager {}); this AgentManager {} reads format {}",
+                path.display(),
+                version.map_or_else(|| "unknown".to_string(), |version| version.to_string()),
+                manifest
+                    .get("tool_version")
+                    .and_then(toml::Value::as_str)
+                    .unwrap_or("unknown"),
+                env!("CARGO_PKG_VERSION"),
+                HANDOFF_VERSION
+            );
+        }
+        let manifest: Manifest = manifest
+            .try_into()
+            .with_context(|| format!("The manifest of {} is invalid", path.display()))?;
+        let bundle = bundle.ok_or_else(|| anyhow!("{} has no branch bundle", path.display()))?;
+        Ok(Self {
+            manifest,
+            bundle,
+            template,
+            metadata,
+            notes,
+            patch,
+        })
+    }
+}
+
+// Scratch file for the bundle or patch while an archive is written or imported.
+pub fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("agent-handoff-{}-{}", std::process::id(), name))
+}
+
+pub fn read_optional(path: &Path) -> Result<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).with_context(|| format!("Unable to read {}", path.display())),
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+
+use crate::config::ConfigState;
+use crate::git::GitBackend;
+use crate::templates;
+use crate::ui;
+
+const GENERATED_MARKER: &str = "<!-- Generated by AgentManager; edit its sources instead. -->";
+
+// Writes the standing-instructions file (e.g. AGENTS.md) into the worktree by concatenating
+// the configured sources. Does nothing when `instructions_filename` is unset.
+pub fn generate(
+    repo: &dyn GitBackend,
+    cfg: &ConfigState,
+    worktree: &Path,
+    variables: &HashMap<String, String>,
+) -> Result<()> {
+    let Some(file_name) = cfg.config.instructions_filename.as_deref() else {
+        return Ok(());
+    };
+    let target = worktree.join(file_name);
+
+    // A tracked file of the same name belongs to the repository unless we generated it.
+    if repo.is_tracked(worktree, file_name)? && !is_generated(&target) {
+        ui::warning!(
+            "{} is tracked in the repository; not overwriting it with generated instructions.",
+            file_name
+        );
+        return Ok(());
+    }
+
+    let pattern = templates::placeholder_pattern()?;
+    let mut content = format!("{}\n", GENERATED_MARKER);
+    for source in resolved_sources(cfg, repo.root()) {
+        let snippet = match fs::read_to_string(&source) {
+            Ok(snippet) => snippet,
+            Err(err) => {
+                ui::warning!("Skipping instructions source {}: {}", source.display(), err);
+                continue;
+            }
+        };
+        // Unknown placeholders are left as-is; instructions never prompt.
+        let rendered = pattern.replace_all(&snippet, |caps: &regex::Captures| {
+            let key = caps.get(1).map(|m| m.as_str().trim()).unwrap_or_default();
+            templates::placeholder_value(key, variables)
+                .ok()
+                .flatten()
+                .unwrap_or_else(|| caps[0].to_string())
+        });
+        content.push('\n');
+        content.push_str(rendered.trim_end());
+        content.push('\n');
+    }
+    content.push_str(&format!(
+        "\nThe task for this worktree is described in `{}`.\n",
+        templates::TEMPLATE_FILENAME
+    ));
+
+    fs::write(&target, content)
+        .with_context(|| format!("Unable to write instructions file {}", target.display()))?;
+    if !cfg.config.commit_instructions {
+        templates::ensure_excluded(worktree, file_name)?;
+    }
+    ui::info!("Instructions written to {}", target.display());
+    Ok(())
+}
+
+// True when the generated file is missing or older than any of its sources.
+pub fn is_stale(cfg: &ConfigState, repo_root: &Path, worktree: &Path) -> bool {
+    let Some(file_name) = cfg.config.instructions_filename.as_deref() else {
+        return false;
+    };
+    let Some(generated) = modified(&worktree.join(file_name)) else {
+        return true;
+    };
+    resolved_sources(cfg, repo_root)
+        .iter()
+        .filter_map(|source| modified(source))
+        .any(|source| source > generated)
+}
+
+fn resolved_sources(cfg: &ConfigState, repo_root: &Path) -> Vec<PathBuf> {
+    let home = directories::BaseDirs::new().map(|dirs| dirs.home_dir().to_path_buf());
+    cfg.config
+        .instructions_sources
+        .iter()
+        .map(|source| {
+            if let (Ok(rest), Some(home)) = (source.strip_prefix("~"), home.as_ref()) {
+                home.join(rest)
+            } else if source.is_relative() {
+                repo_root.join(source)
+            } else {
+                source.clone()
+            }
+        })
+        .collect()
+}
+
+fn is_generated(path: &Path) -> bool {
+    fs::read_to_string(path)
+        .map(|content| content.lines().next() == Some(GENERATED_MARKER))
+        .unwrap_or(false)
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
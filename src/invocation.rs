@@ -0,0 +1,577 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+
+use anyhow::{Context, Result, anyhow};
+
+use crate::config::{AgentInput, Config, ConfigState};
+use crate::disk;
+use crate::git;
+use crate::metadata::WorktreeMetadata;
+use crate::placeholders;
+use crate::run_script::CONTENT_SENTINEL;
+use crate::templates;
+use crate::ui::{self, TracedCommand};
+
+/// Which agent to launch and with which arguments (before placeholder expansion).
+pub struct AgentSpec<'a> {
+    pub display_name: &'a str,
+    pub command: &'a str,
+    pub args: Vec<&'a String>,
+}
+
+// A fully resolved agent launch, built before anything runs so it can be shown, saved as a run
+// script, and executed from the same data. The template content appears as CONTENT_SENTINEL in
+// `args` and `env` and is only substituted when the command is built.
+#[derive(Debug, Clone)]
+pub struct AgentInvocation {
+    pub command: String,
+    pub args: Vec<String>,
+    pub env: Vec<(String, String)>,
+    pub cwd: PathBuf,
+    pub template_on_stdin: bool,
+    pub template_content: String,
+    // Resource limits wrapped around the command, described for the launch summary.
+    pub limits: Vec<String>,
+}
+
+impl AgentInvocation {
+    /// Resolves the configured agent command for `worktree_dir`: placeholders are expanded, the
+    /// environment is assembled and the configured resource limits are applied.
+    pub fn resolve(
+        cfg: &ConfigState,
+        agent: &AgentSpec<'_>,
+        worktree_dir: &Path,
+        branch: &str,
+        template: &Path,
+    ) -> Result<Self> {
+        let template_str = template.to_string_lossy().to_string();
+        let worktree_str = worktree_dir.to_string_lossy().to_string();
+        let template_content = templates::read_template(cfg, template)?;
+
+        let input_mode = cfg.config.agent_input;
+        if input_mode == AgentInput::Stdin && cfg.config.agent_interactive {
+            return Err(anyhow!(
+                "agent_input = \"stdin\" cannot be used with agent_interactive = true: \
+                 an interactive agent needs the terminal on stdin, use \"arg\" instead"
+            ));
+        }
+
+        let metadata = WorktreeMetadata::load(worktree_dir).unwrap_or_default();
+        let mut values = HashMap::from([
+            ("template", template_str.clone()),
+            ("worktree", worktree_str.clone()),
+            ("branch", branch.to_string()),
+            ("feature", metadata.feature.clone().unwrap_or_default()),
+            ("task_type", metadata.task_type.clone().unwrap_or_default()),
+        ]);
+        if input_mode != AgentInput::Stdin {
+            values.insert("template_content", template_content.clone());
+        }
+
+        // Arguments keep a sentinel for the content; whether one is dropped for expanding to
+        // nothing is still decided on the real values.
+        let mut sentinel_values = values.clone();
+        if input_mode != AgentInput::Stdin {
+            sentinel_values.insert("template_content", CONTENT_SENTINEL.to_string());
+        }
+        let mut args = Vec::new();
+        let mut uses_template_placeholder = false;
+        let mut warned_unexpanded = false;
+
+        for arg in &agent.args {
+            let mentions_content = placeholders::mentions(arg, "template_content");
+            if mentions_content || placeholders::mentions(arg, "template") {
+                uses_template_placeholder = true;
+            }
+            if input_mode == AgentInput::Stdin && mentions_content && !warned_unexpanded {
+                ui::warning!(
+                    "{{template_content}} is left unexpanded because the template is sent on stdin."
+                );
+                warned_unexpanded = true;
+            }
+
+            match placeholders::expand_arg(arg, &values) {
+                Some(_) => args.extend(placeholders::expand_arg(arg, &sentinel_values)),
+                None => ui::debug!("Dropping agent argument {:?}: it expanded to nothing", arg),
+            }
+        }
+
+        if input_mode == AgentInput::Arg && !uses_template_placeholder {
+            args.push(CONTENT_SENTINEL.to_string());
+        }
+
+        let mut env = vec![
+            ("AGENT_TEMPLATE_PATH".to_string(), template_str),
+            ("AGENT_WORKTREE_PATH".to_string(), worktree_str),
+            ("AGENT_BRANCH_NAME".to_string(), branch.to_string()),
+            (
+                "AGENT_TEMPLATE_CONTENT".to_string(),
+                CONTENT_SENTINEL.to_string(),
+            ),
+        ];
+        let identity = &cfg.config.git_identity;
+        for (variable, value) in [
+            ("GIT_AUTHOR_NAME", &identity.author_name),
+            ("GIT_AUTHOR_EMAIL", &identity.author_email),
+        ] {
+            if let Some(value) = value {
+                ui::info!("{}={}", variable, value);
+                env.push((variable.to_string(), value.clone()));
+            }
+        }
+        if cfg.config.bypass_hooks_in_worktrees {
+            ui::warning!("Git hooks are bypassed for the agent's commits in this worktree.");
+            env.extend(git::hooks_bypass_env(
+                std::env::var("GIT_CONFIG_COUNT").ok().as_deref(),
+            ));
+        }
+
+        let mut invocation = Self {
+            command: agent.command.to_string(),
+            args,
+            env,
+            cwd: worktree_dir.to_path_buf(),
+            template_on_stdin: input_mode == AgentInput::Stdin,
+            template_content,
+            limits: Vec::new(),
+        };
+        invocation.apply_limits(&cfg.config);
+        Ok(invocation)
+    }
+
+    /// Runs the agent to completion with the caller's stdout and stderr. With
+    /// `template_on_stdin` the content is written to the agent's stdin instead of the terminal;
+    /// an agent that exits before reading all of it is not an error.
+    pub fn run(&self) -> Result<ExitStatus> {
+        let mut command = self.to_command();
+        if !self.template_on_stdin {
+            return command
+                .stdin(Stdio::inherit())
+                .traced_status()
+                .with_context(|| format!("Failed to launch agent {}", self.command));
+        }
+        let mut child = command
+            .stdin(Stdio::piped())
+            .traced_spawn()
+            .with_context(|| format!("Failed to launch agent {}", self.command))?;
+        let write_result = child
+            .stdin
+            .take()
+            .map(|mut stdin| stdin.write_all(self.template_content.as_bytes()))
+            .unwrap_or(Ok(()));
+        let status = child.wait().context("Failed to wait for the agent")?;
+        if let Err(err) = write_result
+            && err.kind() != std::io::ErrorKind::BrokenPipe
+        {
+            return Err(anyhow!("Unable to send the template on stdin: {}", err));
+        }
+        Ok(status)
+    }
+
+    /// The command with the template content substituted back in. Stdin is left to the caller.
+    pub fn to_command(&self) -> Command {
+        let mut command = Command::new(&self.command);
+        command
+            .args(self.args.iter().map(|arg| self.expand(arg)))
+            .envs(
+                self.env
+                    .iter()
+                    .map(|(name, value)| (name, self.expand(value))),
+            )
+            .current_dir(&self.cwd)
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit());
+        command
+    }
+
+    // The command line with the template content abbreviated, for the launch summary.
+    pub fn display_command(&self) -> String {
+        let abbreviated = content_label(self.template_content.len());
+        std::iter::once(ui::shell_quote(&self.command))
+            .chain(self.args.iter().map(|arg| {
+                if arg == CONTENT_SENTINEL {
+                    abbreviated.clone()
+                } else {
+                    ui::shell_quote(&arg.replace(CONTENT_SENTINEL, &abbreviated))
+                }
+            }))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    pub fn env_names(&self) -> Vec<&str> {
+        self.env.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    // Wraps the command in `sh -c 'ulimit -v'`, `cpulimit` and `nice` as configured. A limit that
+    // cannot be applied here is skipped with a warning; the agent still runs.
+    pub fn apply_limits(&mut self, config: &Config) {
+        self.apply_limits_with(config, ui::program_on_path);
+    }
+
+    // `on_path` decides which wrapper programs are installed.
+    fn apply_limits_with(&mut self, config: &Config, on_path: impl Fn(&str) -> bool) {
+        let mut nice = config.agent_nice;
+        if let Some(megabytes) = config.agent_max_memory_mb {
+            if cfg!(unix) {
+                let kilobytes = megabytes.saturating_mul(1024);
+                self.wrap(
+                    "sh",
+                    [
+                        "-c".to_string(),
+                        format!(
+                            "ulimit -v {} 2>/dev/null || echo 'AgentManager: unable to apply the memory limit' >&2; exec \"$@\"",
+                            kilobytes
+                        ),
+                        "agent".to_string(),
+                    ],
+                );
+                self.limits.push(format!("memory {} MiB", megabytes));
+            } else {
+                ui::warning!("agent_max_memory_mb is not supported on this platform; ignoring it.");
+            }
+        }
+        if let Some(percent) = config.agent_cpu_limit_percent {
+            if on_path("cpulimit") {
+                self.wrap(
+                    "cpulimit",
+                    ["-l".to_string(), percent.to_string(), "--".to_string()],
+                );
+                self.limits.push(format!("CPU {}% (cpulimit)", percent));
+            } else {
+                nice = nice.or(Some(FALLBACK_NICE));
+                ui::warning!(
+                    "cpulimit is not installed; the agent only runs with a lower priority (nice {}).",
+                    nice.unwrap_or(FALLBACK_NICE)
+                );
+            }
+        }
+        if let Some(nice) = nice {
+            if on_path("nice") {
+                self.wrap("nice", ["-n".to_string(), nice.to_string()]);
+                self.limits.push(format!("nice {}", nice));
+            } else {
+                ui::warning!("nice is not available; agent_nice is ignored.");
+            }
+        }
+    }
+
+    fn wrap(&mut self, program: &str, program_args: impl IntoIterator<Item = String>) {
+        let command = std::mem::replace(&mut self.command, program.to_string());
+        let mut args = program_args.into_iter().collect::<Vec<_>>();
+        args.push(command);
+        args.append(&mut self.args);
+        self.args = args;
+    }
+
+    fn expand(&self, value: &str) -> String {
+        value.replace(CONTENT_SENTINEL, &self.template_content)
+    }
+}
+
+// Priority used instead of a CPU cap when `cpulimit` is missing.
+const FALLBACK_NICE: i32 = 10;
+
+pub fn content_label(bytes: usize) -> String {
+    format!("«template, {}»", disk::format_bytes(bytes as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    // A configuration in `tmp/config` and a worktree holding a rendered template.
+    fn fixture(tmp: &TempDir, template: &str) -> (ConfigState, PathBuf, PathBuf) {
+        let cfg = ConfigState::load_from(&tmp.path().join("config")).unwrap();
+        let worktree = tmp.path().join("worktree");
+        std::fs::create_dir_all(worktree.join(".git")).unwrap();
+        let template_path = worktree.join(templates::TEMPLATE_FILENAME);
+        std::fs::write(&template_path, template).unwrap();
+        (cfg, worktree, template_path)
+    }
+
+    fn invocation(command: &str, args: &[&str]) -> AgentInvocation {
+        AgentInvocation {
+            command: command.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            env: Vec::new(),
+            cwd: std::env::temp_dir(),
+            template_on_stdin: false,
+            template_content: String::new(),
+            limits: Vec::new(),
+        }
+    }
+
+    fn memory_limited(megabytes: u64, command: &str, args: &[&str]) -> AgentInvocation {
+        let mut invocation = invocation(command, args);
+        let config = Config {
+            agent_max_memory_mb: Some(megabytes),
+            ..Config::default()
+        };
+        invocation.apply_limits_with(&config, |_| false);
+        invocation
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn memory_limit_stops_an_agent_that_exceeds_it() {
+        let hungry = memory_limited(
+            64,
+            "sh",
+            &[
+                "-c",
+                "x=$(head -c 200000000 /dev/zero | tr '\\0' a); echo survived",
+            ],
+        );
+        assert_eq!(hungry.limits, ["memory 64 MiB"]);
+        let output = hungry.to_command().stdout(Stdio::piped()).output().unwrap();
+        assert!(!output.status.success());
+        assert!(!String::from_utf8_lossy(&output.stdout).contains("survived"));
+
+        let small = memory_limited(64, "sh", &["-c", "echo fine"]);
+        let output = small.to_command().stdout(Stdio::piped()).output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "fine\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn memory_limit_wrapper_passes_arguments_through_verbatim() {
+        let args = [
+            "a b",
+            "it's",
+            "$HOME",
+            "*",
+            "",
+            "--flag=\"q\"",
+            "line\nbreak",
+        ];
+        let limited = memory_limited(512, "printf", &[&["%s|"][..], &args[..]].concat());
+        assert_eq!(limited.command, "sh");
+
+        let output = limited
+            .to_command()
+            .stdout(Stdio::piped())
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let expected: String = args.iter().map(|arg| format!("{}|", arg)).collect();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+    }
+
+    #[test]
+    fn cpu_limit_falls_back_to_nice_without_cpulimit() {
+        let config = Config {
+            agent_cpu_limit_percent: Some(50),
+            ..Config::default()
+        };
+
+        let mut fallback = invocation("agent", &["--run"]);
+        fallback.apply_limits_with(&config, |program| program == "nice");
+        assert_eq!(fallback.command, "nice");
+        assert_eq!(fallback.args, ["-n", "10", "agent", "--run"]);
+        assert_eq!(fallback.limits, ["nice 10"]);
+
+        // A configured niceness wins over the fallback one.
+        let mut configured = invocation("agent", &[]);
+        let config_with_nice = Config {
+            agent_nice: Some(5),
+            agent_cpu_limit_percent: Some(50),
+            ..Config::default()
+        };
+        configured.apply_limits_with(&config_with_nice, |program| program == "nice");
+        assert_eq!(configured.args, ["-n", "5", "agent"]);
+
+        let mut capped = invocation("agent", &["--run"]);
+        capped.apply_limits_with(&config, |_| true);
+        assert_eq!(capped.command, "cpulimit");
+        assert_eq!(capped.args, ["-l", "50", "--", "agent", "--run"]);
+        assert_eq!(capped.limits, ["CPU 50% (cpulimit)"]);
+
+        // Without either program the agent runs unwrapped.
+        let mut bare = invocation("agent", &["--run"]);
+        bare.apply_limits_with(&config, |_| false);
+        assert_eq!(bare.command, "agent");
+        assert!(bare.limits.is_empty());
+    }
+
+    #[test]
+    fn stdin_input_is_rejected_for_an_interactive_agent() {
+        let tmp = TempDir::new("invocation-interactive");
+        let (mut cfg, worktree, template) = fixture(&tmp, "task\n");
+        cfg.config.agent_input = AgentInput::Stdin;
+        cfg.config.agent_interactive = true;
+        let agent = AgentSpec {
+            display_name: "agent",
+            command: "agent",
+            args: Vec::new(),
+        };
+
+        let err = AgentInvocation::resolve(&cfg, &agent, &worktree, "agent/login", &template)
+            .unwrap_err();
+        assert!(err.to_string().contains("agent_interactive"), "{}", err);
+
+        cfg.config.agent_input = AgentInput::Arg;
+        assert!(
+            AgentInvocation::resolve(&cfg, &agent, &worktree, "agent/login", &template).is_ok()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stdin_input_reaches_the_agent_byte_for_byte() {
+        let tmp = TempDir::new("invocation-stdin");
+        let content = "# Task\n\nQuote ' and \" and $HOME stay as is.\n\u{e9}t\u{e9}\n";
+        let (mut cfg, worktree, template) = fixture(&tmp, content);
+        cfg.config.agent_input = AgentInput::Stdin;
+        let received = tmp.path().join("received");
+        // A `cat` that saves its stdin instead of printing it.
+        let args = [
+            "-c".to_string(),
+            "cat > \"$1\"".to_string(),
+            "agent".to_string(),
+            received.to_string_lossy().into_owned(),
+        ];
+        let agent = AgentSpec {
+            display_name: "cat",
+            command: "sh",
+            args: args.iter().collect(),
+        };
+
+        let invocation =
+            AgentInvocation::resolve(&cfg, &agent, &worktree, "agent/login", &template).unwrap();
+        assert!(invocation.template_on_stdin);
+        assert_eq!(invocation.args, args);
+        assert!(invocation.run().unwrap().success());
+
+        assert_eq!(std::fs::read(&received).unwrap(), content.as_bytes());
+    }
+
+    fn spec(args: &[String]) -> AgentSpec<'_> {
+        AgentSpec {
+            display_name: "Agent",
+            command: "agent",
+            args: args.iter().collect(),
+        }
+    }
+
+    #[test]
+    fn resolve_expands_placeholders_and_keeps_the_content_as_a_sentinel() {
+        let tmp = TempDir::new("invocation-resolve");
+        let (mut cfg, worktree, template) = fixture(&tmp, "Fix the login");
+        cfg.config.git_identity.author_name = Some("Agent Smith".to_string());
+        let args = [
+            "--branch".to_string(),
+            "{branch}".to_string(),
+            // No feature is recorded, so this argument is dropped.
+            "{feature}".to_string(),
+            "--prompt={template_content}".to_string(),
+        ];
+
+        let invocation =
+            AgentInvocation::resolve(&cfg, &spec(&args), &worktree, "agent/login", &template)
+                .unwrap();
+
+        assert_eq!(invocation.command, "agent");
+        assert_eq!(
+            invocation.args,
+            [
+                "--branch".to_string(),
+                "agent/login".to_string(),
+                format!("--prompt={}", CONTENT_SENTINEL),
+            ]
+        );
+        assert_eq!(invocation.cwd, worktree);
+        assert!(!invocation.template_on_stdin);
+        assert_eq!(invocation.template_content, "Fix the login");
+        assert_eq!(
+            invocation.env_names(),
+            [
+                "AGENT_TEMPLATE_PATH",
+                "AGENT_WORKTREE_PATH",
+                "AGENT_BRANCH_NAME",
+                "AGENT_TEMPLATE_CONTENT",
+                "GIT_AUTHOR_NAME",
+            ]
+        );
+    }
+
+    #[test]
+    fn resolve_appends_the_content_when_no_argument_mentions_the_template() {
+        let tmp = TempDir::new("invocation-append");
+        let (cfg, worktree, template) = fixture(&tmp, "Fix the login");
+        let args = ["--yes".to_string()];
+
+        let invocation =
+            AgentInvocation::resolve(&cfg, &spec(&args), &worktree, "agent/login", &template)
+                .unwrap();
+
+        assert_eq!(
+            invocation.args,
+            ["--yes".to_string(), CONTENT_SENTINEL.to_string()]
+        );
+    }
+
+    #[test]
+    fn to_command_substitutes_the_content_into_args_and_env() {
+        let mut invocation = invocation(
+            "agent",
+            &[
+                "--prompt",
+                CONTENT_SENTINEL,
+                &format!("--x={}", CONTENT_SENTINEL),
+            ],
+        );
+        invocation.template_content = "it's $HOME".to_string();
+        invocation.env = vec![
+            (
+                "AGENT_TEMPLATE_CONTENT".to_string(),
+                CONTENT_SENTINEL.to_string(),
+            ),
+            ("AGENT_BRANCH_NAME".to_string(), "agent/login".to_string()),
+        ];
+
+        let command = invocation.to_command();
+
+        let args = command
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(args, ["--prompt", "it's $HOME", "--x=it's $HOME"]);
+        let env = command
+            .get_envs()
+            .map(|(name, value)| {
+                (
+                    name.to_string_lossy().into_owned(),
+                    value.map(|value| value.to_string_lossy().into_owned()),
+                )
+            })
+            .collect::<HashMap<_, _>>();
+        assert_eq!(env["AGENT_TEMPLATE_CONTENT"].as_deref(), Some("it's $HOME"));
+        assert_eq!(env["AGENT_BRANCH_NAME"].as_deref(), Some("agent/login"));
+    }
+
+    #[test]
+    fn display_command_abbreviates_the_content_and_quotes_the_rest() {
+        let mut invocation = invocation(
+            "my agent",
+            &[
+                "--branch",
+                "agent/login",
+                CONTENT_SENTINEL,
+                &format!("--prompt={}", CONTENT_SENTINEL),
+            ],
+        );
+        invocation.template_content = "x".repeat(2048);
+
+        assert_eq!(content_label(12), "\u{ab}template, 12 B\u{bb}");
+        assert_eq!(
+            invocation.display_command(),
+            "'my agent' --branch agent/login \u{ab}template, 2.0 KiB\u{bb} \
+             '--prompt=\u{ab}template, 2.0 KiB\u{bb}'"
+        );
+    }
+}
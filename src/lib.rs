@@ -0,0 +1,113 @@
+//! The worktree and agent lifecycle behind the `AgentManager` binary, usable without a terminal.
+//!
+//! The binary adds the menus, pickers and prompts on top. The functions at the crate root are the
+//! stable entry points and return [`Error`]; the modules expose the full building blocks with
+//! `anyhow` errors. Build with `default-features = false` to leave out the terminal dependencies
+//! of the `cli` feature.
+
+pub mod archive;
+pub mod config;
+pub mod disk;
+pub mod flow;
+pub mod fs_caps;
+pub mod git;
+pub mod handoff;
+pub mod invocation;
+pub mod lock;
+pub mod metadata;
+pub mod persistence;
+pub mod placeholders;
+pub mod presets;
+pub mod run_script;
+pub mod shared_templates;
+pub mod templates;
+pub mod ui;
+
+#[cfg(test)]
+mod test_support;
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+
+use config::ConfigState;
+use git::{CliGit, GitBackend};
+use invocation::{AgentInvocation, AgentSpec};
+use templates::{VariablePresets, VariableResolver};
+
+/// Errors returned by the crate-root functions. The source carries git's or the OS's message.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{} is not inside a git repository", dir.display())]
+    NotARepository {
+        dir: PathBuf,
+        #[source]
+        source: anyhow::Error,
+    },
+    #[error("unable to load the configuration")]
+    Config(#[source] anyhow::Error),
+    #[error("git operation failed")]
+    Git(#[source] anyhow::Error),
+    #[error("unable to render the template")]
+    Template(#[source] anyhow::Error),
+    #[error("unable to run the agent")]
+    Agent(#[source] anyhow::Error),
+}
+
+/// Loads the user configuration, creating it with defaults on first use.
+pub fn load_config() -> Result<ConfigState, Error> {
+    ConfigState::load().map_err(Error::Config)
+}
+
+/// Opens the repository containing `dir`.
+pub fn open_repository(dir: &Path) -> Result<CliGit, Error> {
+    CliGit::open(dir).map_err(|source| Error::NotARepository {
+        dir: dir.to_path_buf(),
+        source,
+    })
+}
+
+/// Creates `worktree_dir` on `branch`, cutting the branch from `base_branch` when it does not
+/// exist yet.
+pub fn create_worktree(
+    repo: &dyn GitBackend,
+    branch: &str,
+    worktree_dir: &Path,
+    base_branch: &str,
+) -> Result<(), Error> {
+    repo.create_worktree(branch, worktree_dir, base_branch, false)
+        .map_err(Error::Git)
+}
+
+/// Renders a template with `variables` as the automatic values; anything else is asked from
+/// `resolver`, which can simply be another map.
+pub fn render_template(
+    content: &str,
+    variables: &HashMap<String, String>,
+    presets: &VariablePresets,
+    resolver: &mut dyn VariableResolver,
+) -> Result<String, Error> {
+    templates::render(content, variables, presets, resolver).map_err(Error::Template)
+}
+
+/// Writes the rendered template into the worktree and resolves the configured agent for it.
+pub fn prepare_agent(
+    cfg: &ConfigState,
+    worktree_dir: &Path,
+    branch: &str,
+    rendered: &str,
+) -> Result<AgentInvocation, Error> {
+    let template =
+        templates::write_rendered_template(worktree_dir, rendered).map_err(Error::Template)?;
+    let agent = AgentSpec {
+        display_name: &cfg.config.agent_display_name,
+        command: &cfg.config.agent_command,
+        args: cfg.config.agent_args.iter().collect(),
+    };
+    AgentInvocation::resolve(cfg, &agent, worktree_dir, branch, &template).map_err(Error::Agent)
+}
+
+/// Runs a resolved agent to completion.
+pub fn run_agent(invocation: &AgentInvocation) -> Result<ExitStatus, Error> {
+    invocation.run().map_err(Error::Agent)
+}
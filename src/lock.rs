@@ -0,0 +1,518 @@
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, anyhow};
+use serde::de::DeserializeOwned;
+
+use crate::ui;
+
+const LOCK_TIMEOUT: Duration = Duration::from_secs(2);
+const INSTANCES_DIR: &str = "agent-manager-instances";
+
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// Advisory lock shared between AgentManager processes and threads: an OS lock on `<file>.lock`,
+// which also holds the owner's PID for diagnostics. The OS drops the lock when its owner dies,
+// so there is no stale lock to take over.
+pub struct FileLock {
+    path: PathBuf,
+    _file: File,
+}
+
+impl FileLock {
+    pub fn acquire(target: &Path) -> Result<Self> {
+        let path = lock_path(target);
+        let started = Instant::now();
+        loop {
+            let mut file = match open_lock_file(&path) {
+                Ok(file) => file,
+                // Windows refuses to reopen a file whose deletion by the previous owner is pending.
+                Err(err) if cfg!(windows) && err.kind() == io::ErrorKind::PermissionDenied => {
+                    wait_or_fail(started, &path, target)?;
+                    continue;
+                }
+                Err(err) => {
+                    return Err(err)
+                        .with_context(|| format!("Unable to create lock file {}", path.display()));
+                }
+            };
+            match file.try_lock() {
+                Ok(()) => {
+                    // The previous owner unlinks the file on release; a lock on the orphaned
+                    // file would not exclude whoever creates the next one.
+                    if !is_current_file(&file, &path) {
+                        continue;
+                    }
+                    file.set_len(0)
+                        .and_then(|()| write!(file, "{}", std::process::id()))
+                        .with_context(|| format!("Unable to write lock file {}", path.display()))?;
+                    return Ok(Self { path, _file: file });
+                }
+                Err(TryLockError::WouldBlock) => wait_or_fail(started, &path, target)?,
+                Err(TryLockError::Error(err)) => {
+                    return Err(err).with_context(|| format!("Unable to lock {}", path.display()));
+                }
+            }
+        }
+    }
+}
+
+impl Drop for FileLock {
+    // Unlinked while still locked; the lock itself goes when the file is closed right after.
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn open_lock_file(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+}
+
+fn wait_or_fail(started: Instant, path: &Path, target: &Path) -> Result<()> {
+    if started.elapsed() > LOCK_TIMEOUT {
+        return Err(anyhow!(
+            "Another AgentManager instance (pid {}) holds the lock on {}; try again once it finishes",
+            read_pid(path).unwrap_or_default(),
+            target.display()
+        ));
+    }
+    thread::sleep(Duration::from_millis(25));
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_current_file(file: &File, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (file.metadata(), fs::metadata(path)) {
+        (Ok(held), Ok(current)) => held.dev() == current.dev() && held.ino() == current.ino(),
+        _ => false,
+    }
+}
+
+// Windows cannot open a file pending deletion, so an opened lock file is always the current one.
+#[cfg(not(unix))]
+fn is_current_file(_file: &File, _path: &Path) -> bool {
+    true
+}
+
+// Readers never observe a half-written file: content goes to a sibling temp file that is renamed over.
+pub fn write_atomic(path: &Path, contents: impl AsRef<[u8]>) -> Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("Invalid state file path {}", path.display()))?
+        .to_string_lossy();
+    // The counter keeps concurrent writers in one process off each other's temp file.
+    let temp = path.with_file_name(format!(
+        ".{}.{}.{}.tmp",
+        file_name,
+        std::process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::write(&temp, contents).with_context(|| format!("Unable to write {}", temp.display()))?;
+    fs::rename(&temp, path).with_context(|| {
+        let _ = fs::remove_file(&temp);
+        format!("Unable to replace {}", path.display())
+    })
+}
+
+// Reads a TOML state file, defaulting when it does not exist. A file torn by a crash or an older
+// non-atomic writer falls back to its longest valid prefix, i.e. the records before the tear.
+pub fn read_state<T: DeserializeOwned + Default>(path: &Path, what: &str) -> Result<T> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(T::default()),
+        Err(err) => return Err(err.into()),
+    };
+    let err = match toml::from_str(&content) {
+        Ok(state) => return Ok(state),
+        Err(err) => err,
+    };
+    match last_valid_prefix(&content) {
+        Some(state) => {
+            ui::warning!(
+                "{} {} is damaged; keeping its records up to the damage",
+                what,
+                path.display()
+            );
+            Ok(state)
+        }
+        None => Err(err).with_context(|| format!("{} {} is invalid", what, path.display())),
+    }
+}
+
+fn last_valid_prefix<T: DeserializeOwned>(content: &str) -> Option<T> {
+    content
+        .match_indices('\n')
+        .rev()
+        .map(|(end, _)| &content[..=end])
+        .filter(|prefix| !prefix.trim().is_empty())
+        .find_map(|prefix| toml::from_str(prefix).ok())
+}
+
+// Marks this process as active for a repository until dropped.
+pub struct InstanceMarker {
+    path: PathBuf,
+}
+
+impl InstanceMarker {
+    pub fn register(git_dir: &Path) -> Result<Self> {
+        let dir = git_dir.join(INSTANCES_DIR);
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Unable to create instance directory {}", dir.display()))?;
+        let path = dir.join(std::process::id().to_string());
+        fs::write(&path, "")
+            .with_context(|| format!("Unable to write instance marker {}", path.display()))?;
+        Ok(Self { path })
+    }
+
+    // Counts other live instances, removing markers left behind by crashed processes.
+    pub fn others(&self) -> usize {
+        let Some(dir) = self.path.parent() else {
+            return 0;
+        };
+        let Ok(entries) = fs::read_dir(dir) else {
+            return 0;
+        };
+        entries
+            .flatten()
+            .filter(|entry| entry.path() != self.path)
+            .filter(|entry| {
+                let alive = entry
+                    .file_name()
+                    .to_str()
+                    .and_then(|name| name.parse().ok())
+                    .is_some_and(process_alive);
+                if !alive {
+                    let _ = fs::remove_file(entry.path());
+                }
+                alive
+            })
+            .count()
+    }
+}
+
+impl Drop for InstanceMarker {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+// Lock files and `write_atomic` temp files in `dir` whose owning process is gone.
+pub fn stale_files(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                return false;
+            };
+            if name.ends_with(".lock") {
+                return is_stale_lock(path);
+            }
+            temp_file_pid(name).is_some_and(|pid| !process_alive(pid))
+        })
+        .collect()
+}
+
+// Removes a file reported by `stale_files`, unless its lock was taken in the meantime. A lock
+// file is unlinked while held, so anyone who opened it before is sent back to recreate it.
+// Returns whether the file was removed.
+pub fn remove_stale(path: &Path) -> io::Result<bool> {
+    if !path.to_string_lossy().ends_with(".lock") {
+        return fs::remove_file(path).map(|()| true);
+    }
+    let file = match OpenOptions::new().read(true).write(true).open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err),
+    };
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => return Ok(false),
+        Err(TryLockError::Error(err)) => return Err(err),
+    }
+    if !is_current_file(&file, path) || !is_stale_lock_content(path) {
+        return Ok(false);
+    }
+    fs::remove_file(path).map(|()| true)
+}
+
+// Besides AgentManager's own locks, this covers git's `index.lock`, which carries no PID.
+fn is_stale_lock(path: &Path) -> bool {
+    let unlocked = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .is_ok_and(|file| file.try_lock().is_ok());
+    unlocked && is_stale_lock_content(path)
+}
+
+fn is_stale_lock_content(path: &Path) -> bool {
+    match read_pid(path) {
+        Some(pid) => !process_alive(pid),
+        None => is_older_than(path, LOCK_TIMEOUT),
+    }
+}
+
+// `write_atomic` temp files are named `.<file>.<pid>.<counter>.tmp`.
+fn temp_file_pid(name: &str) -> Option<u32> {
+    let rest = name.strip_prefix('.')?.strip_suffix(".tmp")?;
+    let (rest, counter) = rest.rsplit_once('.')?;
+    counter.parse::<usize>().ok()?;
+    rest.rsplit_once('.')?.1.parse().ok()
+}
+
+fn lock_path(target: &Path) -> PathBuf {
+    let mut name = target.file_name().unwrap_or_default().to_os_string();
+    name.push(".lock");
+    target.with_file_name(name)
+}
+
+fn read_pid(path: &Path) -> Option<u32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+fn is_older_than(path: &Path, age: Duration) -> bool {
+    fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|elapsed| elapsed > age)
+}
+
+pub fn process_alive(pid: u32) -> bool {
+    pid == std::process::id() || pid_alive(pid)
+}
+
+#[cfg(windows)]
+fn pid_alive(pid: u32) -> bool {
+    let filter = format!("PID eq {}", pid);
+    Command::new("tasklist")
+        .args(["/FI", filter.as_str(), "/FO", "CSV", "/NH"])
+        .stderr(Stdio::null())
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).contains(&format!("\"{}\"", pid)))
+        .unwrap_or(false)
+}
+
+#[cfg(not(windows))]
+fn pid_alive(pid: u32) -> bool {
+    let proc_root = Path::new("/proc");
+    if proc_root.join("self").exists() {
+        return proc_root.join(pid.to_string()).exists();
+    }
+    Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+    use serde::{Deserialize, Serialize};
+    use std::sync::Arc;
+
+    #[derive(Debug, Default, Serialize, Deserialize)]
+    struct Journal {
+        #[serde(default)]
+        records: Vec<Record>,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Record {
+        writer: usize,
+        seq: usize,
+    }
+
+    // No PID can be this high on any supported platform.
+    const DEAD_PID: u32 = 4_000_000_000;
+
+    fn append(path: &Path, writer: usize, seq: usize) {
+        let _lock = FileLock::acquire(path).unwrap();
+        let mut journal: Journal = read_state(path, "Journal").unwrap();
+        journal.records.push(Record { writer, seq });
+        write_atomic(path, toml::to_string_pretty(&journal).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn concurrent_appends_through_the_lock_keep_every_record() {
+        let tmp = TempDir::new("lock-append");
+        let path = Arc::new(tmp.path().join("journal.toml"));
+        let writers: Vec<_> = (0..2)
+            .map(|writer| {
+                let path = Arc::clone(&path);
+                thread::spawn(move || {
+                    for seq in 0..25 {
+                        append(&path, writer, seq);
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let journal: Journal = read_state(&path, "Journal").unwrap();
+        assert_eq!(journal.records.len(), 50);
+        for writer in 0..2 {
+            let mut seqs: Vec<_> = journal
+                .records
+                .iter()
+                .filter(|record| record.writer == writer)
+                .map(|record| record.seq)
+                .collect();
+            seqs.sort_unstable();
+            assert_eq!(seqs, (0..25).collect::<Vec<_>>());
+        }
+        // Neither the lock file nor any temp file is left behind.
+        let names: Vec<_> = fs::read_dir(tmp.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, ["journal.toml"]);
+    }
+
+    #[test]
+    fn a_lock_file_left_by_a_dead_process_is_taken_over() {
+        let tmp = TempDir::new("lock-dead-owner");
+        let target = tmp.path().join("queue.toml");
+        fs::write(lock_path(&target), DEAD_PID.to_string()).unwrap();
+
+        let started = Instant::now();
+        let lock = FileLock::acquire(&target).unwrap();
+        assert!(started.elapsed() < LOCK_TIMEOUT);
+        assert_eq!(read_pid(&lock_path(&target)), Some(std::process::id()));
+        drop(lock);
+        assert!(!lock_path(&target).exists());
+    }
+
+    #[test]
+    fn a_held_lock_makes_the_next_acquire_time_out() {
+        let tmp = TempDir::new("lock-held");
+        let target = tmp.path().join("queue.toml");
+        let _held = FileLock::acquire(&target).unwrap();
+
+        let err = FileLock::acquire(&target).err().unwrap();
+        assert!(
+            err.to_string()
+                .contains(&format!("(pid {})", std::process::id())),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn torn_state_falls_back_to_the_last_valid_record() {
+        let tmp = TempDir::new("lock-torn");
+        let path = tmp.path().join("journal.toml");
+        let complete = "[[records]]\nwriter = 0\nseq = 0\n\n[[records]]\nwriter = 0\nseq = 1\n";
+        fs::write(
+            &path,
+            format!("{}\n[[records]]\nwriter = 1\nseq = \"", complete),
+        )
+        .unwrap();
+
+        let journal: Journal = read_state(&path, "Journal").unwrap();
+        let seqs: Vec<_> = journal.records.iter().map(|record| record.seq).collect();
+        assert_eq!(seqs, [0, 1]);
+
+        // An intact file and a missing one are read as is.
+        fs::write(&path, complete).unwrap();
+        assert_eq!(
+            read_state::<Journal>(&path, "Journal")
+                .unwrap()
+                .records
+                .len(),
+            2
+        );
+        let missing = tmp.path().join("missing.toml");
+        assert!(
+            read_state::<Journal>(&missing, "Journal")
+                .unwrap()
+                .records
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn state_without_any_valid_prefix_is_an_error() {
+        let tmp = TempDir::new("lock-garbage");
+        let path = tmp.path().join("journal.toml");
+        fs::write(&path, "[[records\nwriter = 0\n").unwrap();
+
+        let err = read_state::<Journal>(&path, "Journal").unwrap_err();
+        assert!(err.to_string().starts_with("Journal "), "{}", err);
+        assert!(err.to_string().ends_with(" is invalid"), "{}", err);
+    }
+
+    #[test]
+    fn temp_file_names_carry_the_writer_pid() {
+        let cases = [
+            (".queue.toml.123.7.tmp", Some(123)),
+            (".a.b.toml.42.0.tmp", Some(42)),
+            (".queue.toml.123.tmp", None),
+            ("queue.toml.123.7.tmp", None),
+            (".queue.toml.123.7", None),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(temp_file_pid(name), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn stale_files_skip_held_locks_and_live_temp_files() {
+        let tmp = TempDir::new("lock-stale");
+        let dir = tmp.path();
+        let _held = FileLock::acquire(&dir.join("held.toml")).unwrap();
+        fs::write(dir.join("dead.toml.lock"), DEAD_PID.to_string()).unwrap();
+        fs::write(dir.join(format!(".dead.toml.{}.0.tmp", DEAD_PID)), "").unwrap();
+        fs::write(
+            dir.join(format!(".live.toml.{}.0.tmp", std::process::id())),
+            "",
+        )
+        .unwrap();
+
+        let mut stale: Vec<_> = stale_files(dir)
+            .into_iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        stale.sort();
+        assert_eq!(
+            stale,
+            [
+                format!(".dead.toml.{}.0.tmp", DEAD_PID),
+                "dead.toml.lock".to_string()
+            ]
+        );
+
+        assert!(remove_stale(&dir.join("dead.toml.lock")).unwrap());
+        assert!(!remove_stale(&dir.join("held.toml.lock")).unwrap());
+        assert!(dir.join("held.toml.lock").exists());
+    }
+
+    #[test]
+    fn process_alive_tells_this_process_from_an_unused_pid() {
+        assert!(process_alive(std::process::id()));
+        // Far above any pid_max the kernels we run on allow.
+        assert!(!process_alive(4_000_000_000));
+    }
+}
@@ -0,0 +1,321 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::lock;
+use crate::templates;
+
+const METADATA_FILENAME: &str = "agent-worktree.toml";
+const MAX_RUN_RECORDS: usize = 50;
+
+// Per-worktree details, kept in the worktree's private git dir so they never show up as changes.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WorktreeMetadata {
+    pub feature: Option<String>,
+    // Full text of a long feature description whose first line became `feature`.
+    pub feature_details: Option<String>,
+    pub merge_target: Option<String>,
+    // Branch the worktree was cut from, and the commit it pointed to at the time.
+    pub base_branch: Option<String>,
+    pub base_sha: Option<String>,
+    pub task_type: Option<String>,
+    // Source template the cached copy was rendered from, its sha256 at the time, and the values
+    // the user answered (secret-looking names left out) for re-rendering it later.
+    pub template_source: Option<PathBuf>,
+    pub template_source_hash: Option<String>,
+    pub template_answers: BTreeMap<String, String>,
+    // Unix time of the last agent launch, used to surface recent worktrees on the main menu.
+    pub last_run_at: Option<u64>,
+    pub attachments: Vec<String>,
+    pub tags: Vec<String>,
+    // Agent launches, oldest first, capped at MAX_RUN_RECORDS.
+    pub runs: Vec<RunRecord>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunRecord {
+    pub started_at: u64,
+    // Missing when AgentManager died while the agent was running.
+    pub ended_at: Option<u64>,
+    // Missing when the agent could not be launched or was killed by a signal.
+    pub exit_code: Option<i32>,
+    pub agent: String,
+    // sha256 of the rendered template the agent was launched with.
+    pub template_hash: String,
+}
+
+impl RunRecord {
+    pub fn duration_secs(&self) -> Option<u64> {
+        self.ended_at
+            .map(|ended_at| ended_at.saturating_sub(self.started_at))
+    }
+
+    // "14m, exit 0"
+    pub fn summary(&self) -> String {
+        let Some(duration) = self.duration_secs() else {
+            return "no end recorded".to_string();
+        };
+        let duration = match duration {
+            0..60 => format!("{}s", duration),
+            60..3_600 => format!("{}m", duration / 60),
+            _ => format!("{}h{:02}m", duration / 3_600, duration % 3_600 / 60),
+        };
+        match self.exit_code {
+            Some(code) => format!("{}, exit {}", duration, code),
+            None => format!("{}, no exit code", duration),
+        }
+    }
+}
+
+impl WorktreeMetadata {
+    pub fn load(worktree: &Path) -> Result<Self> {
+        lock::read_state(&metadata_path(worktree)?, "Worktree metadata")
+    }
+
+    pub fn save(&self, worktree: &Path) -> Result<()> {
+        let path = metadata_path(worktree)?;
+        let body = toml::to_string_pretty(self)?;
+        lock::write_atomic(&path, body)
+            .with_context(|| format!("Unable to write worktree metadata {}", path.display()))
+    }
+
+    pub fn last_run(&self) -> Option<&RunRecord> {
+        self.runs.last()
+    }
+
+    pub fn start_run(&mut self, record: RunRecord) {
+        self.last_run_at = Some(record.started_at);
+        self.runs.push(record);
+        let excess = self.runs.len().saturating_sub(MAX_RUN_RECORDS);
+        self.runs.drain(..excess);
+    }
+
+    pub fn finish_run(&mut self, started_at: u64, ended_at: u64, exit_code: Option<i32>) {
+        if let Some(record) = self
+            .runs
+            .iter_mut()
+            .rev()
+            .find(|record| record.started_at == started_at && record.ended_at.is_none())
+        {
+            record.ended_at = Some(ended_at);
+            record.exit_code = exit_code;
+        }
+    }
+
+    pub fn update(worktree: &Path, change: impl FnOnce(&mut Self)) -> Result<()> {
+        let _lock = lock::FileLock::acquire(&metadata_path(worktree)?)?;
+        let mut metadata = Self::load(worktree)?;
+        change(&mut metadata);
+        metadata.save(worktree)
+    }
+}
+
+// Comma-separated tags, with an optional leading `#`; empty entries and duplicates are dropped.
+pub fn parse_tags(input: &str) -> Vec<String> {
+    let mut tags = input
+        .split(',')
+        .map(|tag| tag.trim().trim_start_matches('#').trim().to_string())
+        .filter(|tag| !tag.is_empty())
+        .collect::<Vec<_>>();
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
+// How many worktrees carry each tag.
+pub fn tag_counts<'a>(
+    tag_lists: impl IntoIterator<Item = &'a [String]>,
+) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for tags in tag_lists {
+        for tag in tags {
+            *counts.entry(tag.clone()).or_default() += 1;
+        }
+    }
+    counts
+}
+
+fn metadata_path(worktree: &Path) -> Result<PathBuf> {
+    Ok(templates::git_dir_for_worktree(worktree)?.join(METADATA_FILENAME))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+    use std::fs;
+
+    fn worktree(tmp: &TempDir) -> PathBuf {
+        let worktree = tmp.path().join("worktree");
+        fs::create_dir_all(worktree.join(".git")).unwrap();
+        worktree
+    }
+
+    #[test]
+    fn metadata_round_trips_through_the_file() {
+        let tmp = TempDir::new("metadata-round-trip");
+        let worktree = worktree(&tmp);
+        let metadata = WorktreeMetadata {
+            feature: Some("Fix the \"login\" flow".to_string()),
+            feature_details: Some("Fix the login flow\n\n- keep sessions\n".to_string()),
+            merge_target: Some("release/1.2".to_string()),
+            base_branch: Some("main".to_string()),
+            base_sha: Some("0123abcd".to_string()),
+            task_type: Some("bugfix".to_string()),
+            template_source: Some(PathBuf::from("/templates/bugfix.md")),
+            template_source_hash: Some("feed".to_string()),
+            template_answers: BTreeMap::from([("service".to_string(), "billing".to_string())]),
+            last_run_at: Some(1_700_000_000),
+            attachments: vec!["docs/spec.md".to_string()],
+            tags: vec!["urgent".to_string()],
+            runs: vec![RunRecord {
+                started_at: 1_700_000_000,
+                ended_at: None,
+                exit_code: None,
+                agent: "claude".to_string(),
+                template_hash: "beef".to_string(),
+            }],
+        };
+
+        metadata.save(&worktree).unwrap();
+
+        assert_eq!(WorktreeMetadata::load(&worktree).unwrap(), metadata);
+    }
+
+    #[test]
+    fn missing_file_and_missing_fields_read_as_defaults() {
+        let tmp = TempDir::new("metadata-defaults");
+        let worktree = worktree(&tmp);
+        assert_eq!(
+            WorktreeMetadata::load(&worktree).unwrap(),
+            WorktreeMetadata::default()
+        );
+
+        // A file written before most fields existed.
+        fs::write(
+            worktree.join(".git").join(METADATA_FILENAME),
+            "feature = \"Login\"\n",
+        )
+        .unwrap();
+        let metadata = WorktreeMetadata::load(&worktree).unwrap();
+        assert_eq!(metadata.feature.as_deref(), Some("Login"));
+        assert!(metadata.runs.is_empty());
+        assert_eq!(metadata.merge_target, None);
+    }
+
+    #[test]
+    fn update_edits_the_details_in_place() {
+        let tmp = TempDir::new("metadata-update");
+        let worktree = worktree(&tmp);
+        WorktreeMetadata {
+            feature: Some("Login".to_string()),
+            tags: vec!["auth".to_string()],
+            ..WorktreeMetadata::default()
+        }
+        .save(&worktree)
+        .unwrap();
+
+        WorktreeMetadata::update(&worktree, |metadata| {
+            metadata.feature = Some("Login with SSO".to_string());
+            metadata.merge_target = Some("develop".to_string());
+        })
+        .unwrap();
+
+        let metadata = WorktreeMetadata::load(&worktree).unwrap();
+        assert_eq!(metadata.feature.as_deref(), Some("Login with SSO"));
+        assert_eq!(metadata.merge_target.as_deref(), Some("develop"));
+        assert_eq!(metadata.tags, ["auth"]);
+    }
+
+    #[test]
+    fn parse_tags_trims_hashes_and_sorts_unique_tags() {
+        let cases: [(&str, &[&str]); 5] = [
+            ("ui, #backend ,ui", &["backend", "ui"]),
+            ("  # spaced , ", &["spaced"]),
+            ("#", &[]),
+            ("", &[]),
+            ("b,a,B", &["B", "a", "b"]),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse_tags(input), expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn tag_counts_count_worktrees_per_tag() {
+        let lists = [parse_tags("ui,backend"), parse_tags("ui"), Vec::new()];
+        let counts = tag_counts(lists.iter().map(Vec::as_slice));
+        assert_eq!(
+            counts.into_iter().collect::<Vec<_>>(),
+            [("backend".to_string(), 1), ("ui".to_string(), 2)]
+        );
+    }
+
+    fn run(started_at: u64, ended_at: Option<u64>, exit_code: Option<i32>) -> RunRecord {
+        RunRecord {
+            started_at,
+            ended_at,
+            exit_code,
+            agent: "claude".to_string(),
+            template_hash: "beef".to_string(),
+        }
+    }
+
+    #[test]
+    fn run_records_round_trip_through_toml() {
+        let metadata = WorktreeMetadata {
+            runs: vec![
+                run(100, Some(160), Some(0)),
+                run(200, Some(201), None),
+                run(300, None, None),
+            ],
+            ..WorktreeMetadata::default()
+        };
+        let body = toml::to_string_pretty(&metadata).unwrap();
+        assert_eq!(toml::from_str::<WorktreeMetadata>(&body).unwrap(), metadata);
+    }
+
+    #[test]
+    fn run_summary_formats_duration_and_exit_code() {
+        let cases = [
+            (run(100, Some(159), Some(0)), Some(59), "59s, exit 0"),
+            (run(100, Some(160), Some(1)), Some(60), "1m, exit 1"),
+            (run(0, Some(3_599), None), Some(3_599), "59m, no exit code"),
+            (
+                run(0, Some(3_600 + 5 * 60), Some(0)),
+                Some(3_900),
+                "1h05m, exit 0",
+            ),
+            (run(100, None, None), None, "no end recorded"),
+            // A clock that went backwards does not underflow.
+            (run(100, Some(90), Some(0)), Some(0), "0s, exit 0"),
+        ];
+        for (record, duration, summary) in cases {
+            assert_eq!(record.duration_secs(), duration, "{:?}", record);
+            assert_eq!(record.summary(), summary, "{:?}", record);
+        }
+    }
+
+    #[test]
+    fn runs_are_finished_by_start_time_and_capped() {
+        let mut metadata = WorktreeMetadata::default();
+        for started_at in 0..MAX_RUN_RECORDS as u64 + 2 {
+            metadata.start_run(run(started_at, None, None));
+        }
+        assert_eq!(metadata.runs.len(), MAX_RUN_RECORDS);
+        assert_eq!(metadata.runs[0].started_at, 2);
+        assert_eq!(metadata.last_run_at, Some(MAX_RUN_RECORDS as u64 + 1));
+
+        metadata.finish_run(10, 70, Some(3));
+        metadata.finish_run(0, 70, Some(3));
+        let finished = metadata
+            .runs
+            .iter()
+            .filter(|record| record.ended_at.is_some())
+            .collect::<Vec<_>>();
+        assert_eq!(finished, [&run(10, Some(70), Some(3))]);
+    }
+}
@@ -0,0 +1,38 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::templates;
+
+pub const NOTES_FILENAME: &str = ".agent-notes.md";
+
+pub fn path(worktree: &Path) -> PathBuf {
+    worktree.join(NOTES_FILENAME)
+}
+
+// Creates the notes file, kept out of git, the first time it is needed.
+pub fn ensure(worktree: &Path) -> Result<PathBuf> {
+    let path = path(worktree);
+    if !path.exists() {
+        fs::write(&path, "")
+            .with_context(|| format!("Unable to create notes file {}", path.display()))?;
+    }
+    templates::ensure_excluded(worktree, NOTES_FILENAME)?;
+    Ok(path)
+}
+
+// Notes content, or None when there are no notes worth showing.
+pub fn read(worktree: &Path) -> Option<String> {
+    let content = fs::read_to_string(path(worktree)).ok()?;
+    let content = content.trim();
+    (!content.is_empty()).then(|| content.to_string())
+}
+
+pub fn summary(worktree: &Path) -> Option<String> {
+    read(worktree)?
+        .lines()
+        .map(|line| line.trim().trim_start_matches('#').trim())
+        .find(|line| !line.is_empty())
+        .map(|line| line.to_string())
+}
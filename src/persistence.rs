@@ -0,0 +1,172 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+// Overrides the platform configuration directory, e.g. when that one is read-only.
+pub const CONFIG_DIR_ENV: &str = "AGENT_MANAGER_CONFIG_DIR";
+
+// Whether the configuration directory can be written. When it cannot, AgentManager runs on
+// in-memory defaults and skips what needs to be saved there: the seeded config and default
+// template, template history, startup fetch markers and the merge queue.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Persistence {
+    Writable,
+    ReadOnly { dir: PathBuf, reason: String },
+}
+
+impl Persistence {
+    // Creates `dir` if needed and writes a probe file into it. Only storage failures (permissions,
+    // read-only or full filesystem, quota) degrade; anything else is an error.
+    pub fn probe(dir: &Path) -> Result<Self> {
+        match try_write(dir) {
+            Ok(()) => Ok(Persistence::Writable),
+            Err(err) => Self::degrade(dir, err.into()),
+        }
+    }
+
+    // Turns a failed write into `dir` into the read-only state when a storage failure caused it.
+    pub fn degrade(dir: &Path, err: anyhow::Error) -> Result<Self> {
+        let Some(cause) = err
+            .chain()
+            .filter_map(|cause| cause.downcast_ref::<io::Error>())
+            .find(|cause| is_storage_error(cause))
+        else {
+            return Err(err);
+        };
+        Ok(Persistence::ReadOnly {
+            dir: dir.to_path_buf(),
+            reason: cause.to_string(),
+        })
+    }
+
+    pub fn is_writable(&self) -> bool {
+        *self == Persistence::Writable
+    }
+
+    // The one warning shown at startup when running without persistence.
+    pub fn warning(&self) -> Option<String> {
+        match self {
+            Persistence::Writable => None,
+            Persistence::ReadOnly { dir, reason } => Some(format!(
+                "The configuration directory {} is not writable ({}). Running with the settings \
+                 that could be read; template history, startup fetch tracking and the merge queue \
+                 are disabled. Set {} to a writable directory to keep them.",
+                dir.display(),
+                reason,
+                CONFIG_DIR_ENV
+            )),
+        }
+    }
+}
+
+pub fn is_storage_error(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::PermissionDenied
+            | io::ErrorKind::ReadOnlyFilesystem
+            | io::ErrorKind::StorageFull
+            | io::ErrorKind::QuotaExceeded
+    )
+}
+
+fn try_write(dir: &Path) -> io::Result<()> {
+    if !dir.is_dir() {
+        fs::create_dir_all(dir)?;
+    }
+    let probe = dir.join(format!(".write-probe-{}", std::process::id()));
+    let result = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&probe)
+        .and_then(|mut file| file.write_all(b"\n"));
+    let _ = fs::remove_file(&probe);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    #[test]
+    fn probe_leaves_a_writable_dir_untouched() {
+        let tmp = TempDir::new("persistence-writable");
+        let dir = tmp.path().join("config");
+        assert_eq!(Persistence::probe(&dir).unwrap(), Persistence::Writable);
+        assert!(dir.is_dir());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        assert_eq!(Persistence::Writable.warning(), None);
+    }
+
+    #[test]
+    fn degrade_only_on_storage_errors() {
+        let dir = Path::new("/cfg");
+        let cases = [
+            (io::ErrorKind::PermissionDenied, true),
+            (io::ErrorKind::ReadOnlyFilesystem, true),
+            (io::ErrorKind::StorageFull, true),
+            (io::ErrorKind::QuotaExceeded, true),
+            (io::ErrorKind::NotFound, false),
+            (io::ErrorKind::InvalidData, false),
+        ];
+        for (kind, degrades) in cases {
+            // The io::Error is found under added context too.
+            let err = anyhow::Error::from(io::Error::new(kind, "boom")).context("Saving config");
+            let result = Persistence::degrade(dir, err);
+            assert_eq!(result.is_ok(), degrades, "{:?}", kind);
+            if let Ok(state) = result {
+                assert_eq!(
+                    state,
+                    Persistence::ReadOnly {
+                        dir: dir.to_path_buf(),
+                        reason: "boom".to_string()
+                    }
+                );
+                assert!(!state.is_writable());
+            }
+        }
+    }
+
+    #[test]
+    fn read_only_warning_names_dir_reason_and_override() {
+        let state = Persistence::ReadOnly {
+            dir: PathBuf::from("/cfg"),
+            reason: "Permission denied".to_string(),
+        };
+        let warning = state.warning().unwrap();
+        assert!(
+            warning.contains("/cfg is not writable (Permission denied)"),
+            "{}",
+            warning
+        );
+        assert!(warning.contains(CONFIG_DIR_ENV), "{}", warning);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn probe_degrades_in_a_dir_without_write_permission() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = TempDir::new("persistence-read-only");
+        let dir = tmp.path().join("config");
+        fs::create_dir(&dir).unwrap();
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o555)).unwrap();
+        // Root ignores permission bits, so there is nothing to observe.
+        let ignored = fs::write(dir.join("root"), "").is_ok();
+        let state = (!ignored).then(|| Persistence::probe(&dir).unwrap());
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+        let Some(state) = state else {
+            return;
+        };
+
+        match &state {
+            Persistence::ReadOnly { dir: reported, .. } => assert_eq!(reported, &dir),
+            other => panic!("expected read-only, got {:?}", other),
+        }
+        assert!(!state.is_writable());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,302 @@
+use std::borrow::Cow;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+
+use anyhow::{Context, Result, anyhow};
+use skim::prelude::*;
+
+use crate::ui::TracedCommand;
+
+struct PickerItem {
+    index: usize,
+    text: String,
+    preview: Option<String>,
+}
+
+impl SkimItem for PickerItem {
+    fn text(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.text)
+    }
+
+    fn preview(&self, _context: PreviewContext) -> ItemPreview {
+        match &self.preview {
+            Some(preview) => ItemPreview::Text(preview.clone()),
+            None => ItemPreview::Global,
+        }
+    }
+}
+
+struct PickerEntry<T> {
+    label: String,
+    preview: Option<String>,
+    value: T,
+}
+
+// Collects labelled payloads for a skim picker; the selected payload is handed back directly.
+pub struct Picker<T> {
+    prompt: String,
+    entries: Vec<PickerEntry<T>>,
+}
+
+impl<T> Picker<T> {
+    pub fn new(prompt: &str) -> Self {
+        Self {
+            prompt: prompt.to_string(),
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn item(mut self, label: impl Into<String>, value: T) -> Self {
+        self.entries.push(PickerEntry {
+            label: label.into(),
+            preview: None,
+            value,
+        });
+        self
+    }
+
+    pub fn item_with_preview(
+        mut self,
+        label: impl Into<String>,
+        preview: impl Into<String>,
+        value: T,
+    ) -> Self {
+        self.entries.push(PickerEntry {
+            label: label.into(),
+            preview: Some(preview.into()),
+            value,
+        });
+        self
+    }
+
+    pub fn items(self, items: impl IntoIterator<Item = (String, T)>) -> Self {
+        items
+            .into_iter()
+            .fold(self, |picker, (label, value)| picker.item(label, value))
+    }
+
+    // Stable, so entries with equal keys keep their insertion order.
+    pub fn sort_by_key<K: Ord>(mut self, mut key: impl FnMut(&T) -> K) -> Self {
+        self.entries.sort_by_key(|entry| key(&entry.value));
+        self
+    }
+
+    pub fn pick(self) -> Result<Option<T>> {
+        Ok(self.run(false)?.into_iter().next())
+    }
+
+    // Tab toggles entries; Enter alone returns the highlighted one.
+    pub fn pick_many(self) -> Result<Vec<T>> {
+        self.run(true)
+    }
+
+    fn run(self, multi: bool) -> Result<Vec<T>> {
+        let prompt = self.prompt.clone();
+        let (items, values) = self.into_parts();
+        let selected = run_skim(items, &prompt, multi)?;
+        Ok(selected_values(values, selected))
+    }
+
+    // What skim is fed, and the payloads indexed like its items.
+    fn into_parts(self) -> (Vec<(String, Option<String>)>, Vec<T>) {
+        self.entries
+            .into_iter()
+            .map(|entry| ((entry.label, entry.preview), entry.value))
+            .unzip()
+    }
+}
+
+// Payloads at the item indexes skim reports, in item order whatever the selection order.
+fn selected_values<T>(values: Vec<T>, mut selected: Vec<usize>) -> Vec<T> {
+    selected.sort_unstable();
+    values
+        .into_iter()
+        .enumerate()
+        .filter(|(index, _)| selected.binary_search(index).is_ok())
+        .map(|(_, value)| value)
+        .collect()
+}
+
+// Items with a last-use time first, most recent first; the others keep their order after them.
+pub fn order_by_recency<T>(items: Vec<T>, last_used: impl Fn(&T) -> Option<u64>) -> Vec<T> {
+    let (mut used, unused): (Vec<_>, Vec<_>) = items
+        .into_iter()
+        .partition(|item| last_used(item).is_some());
+    used.sort_by_key(|item| std::cmp::Reverse(last_used(item)));
+    used.extend(unused);
+    used
+}
+
+pub fn pick<T>(items: Vec<(String, T)>, prompt: &str) -> Result<Option<T>> {
+    Picker::new(prompt).items(items).pick()
+}
+
+fn run_skim(items: Vec<(String, Option<String>)>, prompt: &str, multi: bool) -> Result<Vec<usize>> {
+    if items.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut builder = SkimOptionsBuilder::default();
+    builder
+        .multi(multi)
+        .height(Some("30%"))
+        .prompt(Some(prompt));
+    if items.iter().any(|(_, preview)| preview.is_some()) {
+        builder
+            .preview(Some(""))
+            .preview_window(Some("down:3:wrap"));
+    }
+    let options = builder
+        .build()
+        .map_err(|err| anyhow!("Invalid skim configuration: {}", err))?;
+
+    let (sender, receiver): (SkimItemSender, SkimItemReceiver) = unbounded();
+    for (index, (label, preview)) in items.into_iter().enumerate() {
+        let picker_item = PickerItem {
+            index,
+            text: label.replace('\n', " "),
+            preview,
+        };
+        sender
+            .send(Arc::new(picker_item))
+            .map_err(|err| anyhow!("Unable to feed the picker: {}", err))?;
+    }
+    drop(sender);
+
+    let output = Skim::run_with(&options, Some(receiver));
+    let Some(out) = output else {
+        return Ok(Vec::new());
+    };
+    if out.is_abort {
+        return Ok(Vec::new());
+    }
+    Ok(out
+        .selected_items
+        .iter()
+        .filter_map(|item| {
+            (**item)
+                .as_any()
+                .downcast_ref::<PickerItem>()
+                .map(|picker_item| picker_item.index)
+        })
+        .collect())
+}
+
+pub fn copy_to_clipboard(command: &str, text: &str) -> Result<()> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .traced_spawn()
+        .with_context(|| format!("Failed to launch clipboard command `{}`", command))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text.as_bytes())?;
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(anyhow!(
+            "Clipboard command `{}` exited with status {}",
+            command,
+            status
+        ));
+    }
+    Ok(())
+}
+
+pub fn page_output(pager: &str, content: &[u8]) -> Result<()> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(pager)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .traced_spawn()
+        .with_context(|| format!("Failed to launch pager `{}`", pager))?;
+    if let Some(mut stdin) = child.stdin.take()
+        && let Err(err) = stdin.write_all(content)
+        && err.kind() != std::io::ErrorKind::BrokenPipe
+    {
+        return Err(err.into());
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(anyhow!("Pager `{}` exited with status {}", pager, status));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duplicate_labels_map_back_to_their_own_payload() {
+        let picker = Picker::new("> ")
+            .item("fix-login", 1)
+            .item("fix-login", 2)
+            .item("docs", 3);
+        let (items, values) = picker.into_parts();
+
+        assert_eq!(items[0].0, items[1].0);
+        assert_eq!(selected_values(values.clone(), vec![1]), [2]);
+        assert_eq!(selected_values(values, vec![0]), [1]);
+    }
+
+    #[test]
+    fn sorting_keeps_labels_previews_and_payloads_together() {
+        let picker = Picker::new("> ")
+            .item_with_preview("b-old", "preview b", ("b", 30))
+            .item("a-new", ("a", 10))
+            .item_with_preview("c-mid", "preview c", ("c", 20))
+            .item("a-dup", ("a", 20))
+            .sort_by_key(|(_, age)| *age);
+        let (items, values) = picker.into_parts();
+
+        assert_eq!(
+            items,
+            [
+                ("a-new".to_string(), None),
+                ("c-mid".to_string(), Some("preview c".to_string())),
+                ("a-dup".to_string(), None),
+                ("b-old".to_string(), Some("preview b".to_string())),
+            ]
+        );
+        // Equal keys keep their insertion order.
+        assert_eq!(values, [("a", 10), ("c", 20), ("a", 20), ("b", 30)]);
+        assert_eq!(selected_values(values, vec![3]), [("b", 30)]);
+    }
+
+    #[test]
+    fn multi_selection_returns_payloads_in_item_order() {
+        let values = vec!["a", "b", "c", "d"];
+        assert_eq!(
+            selected_values(values.clone(), vec![3, 0, 2]),
+            ["a", "c", "d"]
+        );
+        assert!(selected_values(values.clone(), Vec::new()).is_empty());
+        // Out-of-range indexes select nothing.
+        assert_eq!(selected_values(values, vec![7, 1]), ["b"]);
+    }
+
+    #[test]
+    fn order_by_recency_puts_used_items_first_newest_first() {
+        let items = vec!["never", "old", "new", "unused"];
+        let last_used = |item: &&str| match *item {
+            "old" => Some(10),
+            "new" => Some(20),
+            _ => None,
+        };
+        assert_eq!(
+            order_by_recency(items, last_used),
+            ["new", "old", "never", "unused"]
+        );
+    }
+
+    #[test]
+    fn an_empty_picker_selects_nothing_without_running_skim() {
+        assert!(Picker::<u8>::new("> ").pick().unwrap().is_none());
+        assert!(pick(Vec::<(String, u8)>::new(), "> ").unwrap().is_none());
+    }
+}
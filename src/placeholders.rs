@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use regex::{Captures, Regex};
+
+// Placeholder syntax for `agent_args` entries:
+//   `{name}`             replaced by the value of `name`
+//   `{name:keep-empty}`  same, but the entry is kept even when it renders to ""
+// Unknown names are left verbatim. An entry containing placeholders that renders to an
+// empty string is dropped from argv unless one of them carries `:keep-empty`.
+fn pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| Regex::new(r"\{([a-z_]+)(:keep-empty)?\}").expect("valid regex"))
+}
+
+pub fn expand_arg(arg: &str, values: &HashMap<&str, String>) -> Option<String> {
+    let mut substituted = false;
+    let mut keep_empty = false;
+    let rendered = pattern().replace_all(arg, |caps: &Captures| match values.get(&caps[1]) {
+        Some(value) => {
+            substituted = true;
+            keep_empty |= caps.get(2).is_some();
+            value.clone()
+        }
+        None => caps[0].to_string(),
+    });
+    if substituted && !keep_empty && rendered.is_empty() {
+        return None;
+    }
+    Some(rendered.into_owned())
+}
+
+pub fn mentions(arg: &str, name: &str) -> bool {
+    pattern().captures_iter(arg).any(|caps| &caps[1] == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values() -> HashMap<&'static str, String> {
+        HashMap::from([
+            ("feature", String::new()),
+            ("branch", "agent/login".to_string()),
+            ("template", "/w/.agent-template".to_string()),
+        ])
+    }
+
+    #[test]
+    fn expand_arg_table() {
+        let cases = [
+            // No placeholder: kept verbatim, even when empty.
+            ("--yes", Some("--yes")),
+            ("", Some("")),
+            ("{branch}", Some("agent/login")),
+            ("--branch={branch}", Some("--branch=agent/login")),
+            // An entry that renders to nothing is dropped...
+            ("{feature}", None),
+            ("{feature}{feature}", None),
+            // ...unless one of its placeholders keeps it.
+            ("{feature:keep-empty}", Some("")),
+            ("{feature}{feature:keep-empty}", Some("")),
+            ("{branch:keep-empty}", Some("agent/login")),
+            // Mixed with literal text it is never empty.
+            ("--feature={feature}", Some("--feature=")),
+            ("{feature} ", Some(" ")),
+            ("{branch}:{feature}", Some("agent/login:")),
+            // Unknown names and other syntax stay as written.
+            ("{unknown}", Some("{unknown}")),
+            ("{unknown}{feature}", Some("{unknown}")),
+            ("{Feature}", Some("{Feature}")),
+            ("{feature:other}", Some("{feature:other}")),
+            ("${branch}", Some("$agent/login")),
+        ];
+        for (arg, expected) in cases {
+            assert_eq!(expand_arg(arg, &values()).as_deref(), expected, "{:?}", arg);
+        }
+    }
+
+    #[test]
+    fn mentions_matches_whole_names_with_or_without_the_modifier() {
+        assert!(mentions("{template}", "template"));
+        assert!(mentions("--file={template:keep-empty}", "template"));
+        assert!(!mentions("{template_content}", "template"));
+        assert!(mentions("{template_content}", "template_content"));
+        assert!(!mentions("template", "template"));
+    }
+}
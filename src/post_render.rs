@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+
+use crate::config::ConfigState;
+use crate::templates;
+use crate::ui::{self, TracedCommand};
+use agent_manager::placeholders;
+
+pub const FRONT_MATTER_KEY: &str = "post_render_command";
+
+pub struct Outcome {
+    // None when the command was killed after the timeout.
+    pub status: Option<ExitStatus>,
+    pub output: String,
+}
+
+impl Outcome {
+    pub fn passed(&self) -> bool {
+        self.status.is_some_and(|status| status.success())
+    }
+}
+
+// The template's own command wins over the configured one.
+pub fn command_for(cfg: &ConfigState, raw_template: &str) -> Option<String> {
+    templates::front_matter_value(raw_template, FRONT_MATTER_KEY).or_else(|| {
+        cfg.config
+            .post_render_command
+            .clone()
+            .filter(|command| !command.trim().is_empty())
+    })
+}
+
+// Placeholders use the agent_args syntax; values are shell-quoted because the command goes
+// through `sh -c`.
+pub fn expand(command: &str, values: &HashMap<&str, String>) -> String {
+    let quoted = values
+        .iter()
+        .map(|(name, value)| (*name, ui::shell_quote(value)))
+        .collect();
+    placeholders::expand_arg(command, &quoted).unwrap_or_default()
+}
+
+pub fn run(command: &str, cwd: &Path, timeout: Duration) -> Result<Outcome> {
+    // Both streams in one pipe keep the output in the order it was written.
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(format!("exec 2>&1\n{}", command))
+        .current_dir(cwd)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .traced_spawn()
+        .with_context(|| format!("Failed to execute `{}`", command))?;
+
+    // Drained on the side so a chatty validator cannot fill the pipe and stall.
+    let output = Arc::new(Mutex::new(Vec::new()));
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let reader = {
+        let output = Arc::clone(&output);
+        thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            while let Ok(read) = stdout.read(&mut buf)
+                && read > 0
+            {
+                output
+                    .lock()
+                    .expect("output lock")
+                    .extend_from_slice(&buf[..read]);
+            }
+        })
+    };
+
+    let started = Instant::now();
+    let status = loop {
+        match child.try_wait()? {
+            Some(status) => break Some(status),
+            None if started.elapsed() > timeout => {
+                let _ = child.kill();
+                let _ = child.wait();
+                break None;
+            }
+            None => thread::sleep(Duration::from_millis(25)),
+        }
+    };
+    // After a timeout, a process the command left behind may still hold the pipe open.
+    if status.is_some() {
+        let _ = reader.join();
+    }
+    let output = String::from_utf8_lossy(&output.lock().expect("output lock")).into_owned();
+    Ok(Outcome { status, output })
+}
+
+// The validator runs through `sh -c`.
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    #[test]
+    fn the_templates_front_matter_command_wins_over_the_config() {
+        let tmp = TempDir::new("post-render-command");
+        let mut cfg = ConfigState::load_from(&tmp.path().join("config")).unwrap();
+        let with_front_matter = "---\npost_render_command: \"make lint\"\n---\nBody\n";
+
+        assert_eq!(command_for(&cfg, "Body"), None);
+        cfg.config.post_render_command = Some("  ".to_string());
+        assert_eq!(command_for(&cfg, "Body"), None);
+        cfg.config.post_render_command = Some("true".to_string());
+        assert_eq!(command_for(&cfg, "Body").as_deref(), Some("true"));
+        assert_eq!(
+            command_for(&cfg, with_front_matter).as_deref(),
+            Some("make lint")
+        );
+    }
+
+    #[test]
+    fn expanded_values_are_shell_quoted() {
+        let values = HashMap::from([
+            ("template", "/work/it's here/.agent-template".to_string()),
+            ("branch", "agent/login".to_string()),
+        ]);
+        assert_eq!(
+            expand("check {template} --on {branch}", &values),
+            "check '/work/it'\\''s here/.agent-template' --on agent/login"
+        );
+    }
+
+    #[test]
+    fn run_captures_both_streams_in_order_and_the_status() {
+        let tmp = TempDir::new("post-render-run");
+        let outcome = run(
+            "echo out; echo err >&2; pwd; exit 3",
+            tmp.path(),
+            Duration::from_secs(10),
+        )
+        .unwrap();
+
+        assert!(!outcome.passed());
+        assert_eq!(outcome.status.and_then(|status| status.code()), Some(3));
+        let cwd = tmp.path().canonicalize().unwrap();
+        assert_eq!(outcome.output, format!("out\nerr\n{}\n", cwd.display()));
+        assert!(
+            run("true", tmp.path(), Duration::from_secs(10))
+                .unwrap()
+                .passed()
+        );
+    }
+
+    #[test]
+    fn run_kills_a_command_that_outlives_the_timeout() {
+        let tmp = TempDir::new("post-render-timeout");
+        let started = Instant::now();
+
+        let outcome = run(
+            "echo started; exec sleep 10",
+            tmp.path(),
+            Duration::from_millis(200),
+        )
+        .unwrap();
+
+        assert!(outcome.status.is_none());
+        assert!(!outcome.passed());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,81 @@
+use std::fmt;
+use std::io::Read;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::ui::{self, TracedCommand};
+
+pub const DEFAULT_PR_STATUS_COMMAND: &str = "gh pr view {branch} --json state -q .state";
+const QUERY_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrState {
+    Open,
+    Merged,
+    Closed,
+}
+
+impl PrState {
+    fn parse(output: &str) -> Option<Self> {
+        match output.trim().to_ascii_lowercase().as_str() {
+            "open" | "opened" => Some(PrState::Open),
+            "merged" => Some(PrState::Merged),
+            "closed" => Some(PrState::Closed),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for PrState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            PrState::Open => "open",
+            PrState::Merged => "merged",
+            PrState::Closed => "closed",
+        };
+        f.write_str(label)
+    }
+}
+
+// `None` in the config means "use gh when it is installed"; an empty string disables the lookup.
+pub fn resolve_command(configured: Option<&str>) -> Option<String> {
+    match configured {
+        Some(command) if command.trim().is_empty() => None,
+        Some(command) => Some(command.to_string()),
+        None => ui::program_on_path("gh").then(|| DEFAULT_PR_STATUS_COMMAND.to_string()),
+    }
+}
+
+// Any failure (missing tool, timeout, unexpected output) yields `None` so callers show no marker.
+pub fn query(command: &str, branch: &str, cwd: &Path) -> Option<PrState> {
+    let rendered = command.replace("{branch}", &ui::shell_quote(branch));
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(&rendered)
+        .current_dir(cwd)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .traced_spawn()
+        .ok()?;
+
+    let started = Instant::now();
+    loop {
+        match child.try_wait().ok()? {
+            Some(status) if status.success() => break,
+            Some(_) => return None,
+            None if started.elapsed() > QUERY_TIMEOUT => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return None;
+            }
+            None => thread::sleep(Duration::from_millis(25)),
+        }
+    }
+
+    let mut output = String::new();
+    child.stdout.take()?.read_to_string(&mut output).ok()?;
+    PrState::parse(&output)
+}
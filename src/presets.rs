@@ -0,0 +1,204 @@
+//! Built-in agent presets: `agent_preset = "<name>"` in the configuration fills the agent fields
+//! the file does not set itself.
+
+use anyhow::{Result, anyhow};
+
+use crate::config::AgentInput;
+use crate::ui;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AgentPreset {
+    pub name: &'static str,
+    pub display_name: &'static str,
+    pub command: &'static str,
+    pub args: &'static [&'static str],
+    pub input: AgentInput,
+    pub interactive: bool,
+}
+
+// Adding an agent is one entry here.
+pub const PRESETS: &[AgentPreset] = &[
+    AgentPreset {
+        name: "codex",
+        display_name: "Codex",
+        command: "codex",
+        args: &["{template_content}"],
+        input: AgentInput::Arg,
+        interactive: true,
+    },
+    AgentPreset {
+        name: "claude",
+        display_name: "Claude",
+        command: "claude",
+        args: &["{template_content}"],
+        input: AgentInput::Arg,
+        interactive: true,
+    },
+    AgentPreset {
+        name: "aider",
+        display_name: "Aider",
+        command: "aider",
+        args: &["--message-file", "{template}"],
+        input: AgentInput::Arg,
+        interactive: false,
+    },
+    AgentPreset {
+        name: "gemini",
+        display_name: "Gemini",
+        command: "gemini",
+        args: &["--prompt-interactive", "{template_content}"],
+        input: AgentInput::Arg,
+        interactive: true,
+    },
+];
+
+pub fn find(name: &str) -> Result<&'static AgentPreset> {
+    PRESETS
+        .iter()
+        .find(|preset| preset.name == name)
+        .ok_or_else(|| {
+            anyhow!(
+                "Unknown agent_preset {}; available presets: {}",
+                name,
+                PRESETS
+                    .iter()
+                    .map(|preset| preset.name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        })
+}
+
+// Presets whose command is installed.
+pub fn installed() -> Vec<&'static AgentPreset> {
+    PRESETS
+        .iter()
+        .filter(|preset| ui::program_on_path(preset.command))
+        .collect()
+}
+
+impl AgentPreset {
+    // Fills the preset's keys that `table` does not set: explicit fields win.
+    pub fn apply(&self, table: &mut toml::Table) {
+        for (key, value) in self.values() {
+            table.entry(key).or_insert(value);
+        }
+    }
+
+    // The configuration keys the preset provides, with its values.
+    pub fn values(&self) -> [(&'static str, toml::Value); 5] {
+        let input = match self.input {
+            AgentInput::Arg => "arg",
+            AgentInput::Stdin => "stdin",
+            AgentInput::None => "none",
+        };
+        [
+            ("agent_command", self.command.into()),
+            (
+                "agent_args",
+                toml::Value::Array(self.args.iter().map(|arg| (*arg).into()).collect()),
+            ),
+            ("agent_display_name", self.display_name.into()),
+            ("agent_input", input.into()),
+            ("agent_interactive", self.interactive.into()),
+        ]
+    }
+
+    // One-line summary for listings: the command line and the launch mode.
+    pub fn describe(&self) -> String {
+        let command = std::iter::once(self.command)
+            .chain(self.args.iter().copied())
+            .map(ui::shell_quote)
+            .collect::<Vec<_>>()
+            .join(" ");
+        format!(
+            "{} ({}, {})",
+            command,
+            self.display_name,
+            if self.interactive {
+                "interactive"
+            } else {
+                "not interactive"
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preset_names_are_unique_and_findable() {
+        for (i, preset) in PRESETS.iter().enumerate() {
+            assert_eq!(find(preset.name).unwrap(), preset);
+            assert!(
+                PRESETS[..i].iter().all(|other| other.name != preset.name),
+                "{}",
+                preset.name
+            );
+        }
+        let err = find("cursor").unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "Unknown agent_preset cursor; available presets: codex, claude, aider, gemini"
+        );
+    }
+
+    #[test]
+    fn apply_expands_into_an_empty_table() {
+        let mut table = toml::Table::new();
+        find("aider").unwrap().apply(&mut table);
+        let expected: toml::Table = toml::from_str(
+            r#"
+            agent_command = "aider"
+            agent_args = ["--message-file", "{template}"]
+            agent_display_name = "Aider"
+            agent_input = "arg"
+            agent_interactive = false
+            "#,
+        )
+        .unwrap();
+        assert_eq!(table, expected);
+    }
+
+    #[test]
+    fn apply_keeps_fields_the_table_sets() {
+        let mut table: toml::Table = toml::from_str(
+            r#"
+            agent_preset = "claude"
+            agent_args = ["--print", "{template_content}"]
+            agent_interactive = false
+            merge_target = "develop"
+            "#,
+        )
+        .unwrap();
+        find("claude").unwrap().apply(&mut table);
+        let cases = [
+            ("agent_command", toml::Value::from("claude")),
+            (
+                "agent_args",
+                toml::Value::from(vec!["--print", "{template_content}"]),
+            ),
+            ("agent_display_name", toml::Value::from("Claude")),
+            ("agent_input", toml::Value::from("arg")),
+            ("agent_interactive", toml::Value::from(false)),
+            ("merge_target", toml::Value::from("develop")),
+        ];
+        for (key, expected) in cases {
+            assert_eq!(table.get(key), Some(&expected), "{:?}", key);
+        }
+    }
+
+    #[test]
+    fn describe_quotes_the_command_line() {
+        assert_eq!(
+            find("gemini").unwrap().describe(),
+            "gemini --prompt-interactive '{template_content}' (Gemini, interactive)"
+        );
+        assert_eq!(
+            find("aider").unwrap().describe(),
+            "aider --message-file '{template}' (Aider, not interactive)"
+        );
+    }
+}
@@ -0,0 +1,652 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::SystemTime;
+
+use anyhow::{Context, Result, anyhow};
+use console::style;
+use dialoguer::{Confirm, Input, Select, theme::ColorfulTheme};
+
+use crate::archive;
+use crate::config::ConfigState;
+use crate::picker;
+use crate::shared_templates;
+use crate::template_usage::TemplateUsage;
+use crate::templates::{
+    self, NameMatch, TemplateEntry, TemplateStats, VariableKind, VariablePreset, VariablePresets,
+    VariableResolver, available_templates, collect_templates, parse_bool, parse_int,
+    placeholder_pattern, project_templates_dir, read_template, substitute_variables,
+    template_label, variable_names,
+};
+use crate::terminal::TerminalGuard;
+use crate::ui::{self, TracedCommand};
+
+// Asks the user for each variable `prompts::collect_variables` cannot fill on its own.
+pub struct Prompter<'a> {
+    theme: &'a ColorfulTheme,
+    announced: bool,
+    // Earlier answers offered as the default instead of the preset's.
+    previous: Option<&'a HashMap<String, String>>,
+}
+
+impl<'a> Prompter<'a> {
+    pub fn new(theme: &'a ColorfulTheme) -> Self {
+        Self {
+            theme,
+            announced: false,
+            previous: None,
+        }
+    }
+}
+
+impl VariableResolver for Prompter<'_> {
+    fn resolve(&mut self, name: &str, preset: Option<&VariablePreset>) -> Result<String> {
+        if !self.announced {
+            ui::info!(
+                "{}",
+                style("Template variables detected, please provide their values.").dim()
+            );
+            self.announced = true;
+        }
+        let previous = self
+            .previous
+            .and_then(|previous| previous.get(name))
+            .map(String::as_str);
+        prompt_variable(self.theme, name, preset, previous)
+    }
+}
+
+pub fn collect_variables(
+    content: &str,
+    theme: &ColorfulTheme,
+    auto_variables: &HashMap<String, String>,
+    presets: &VariablePresets,
+) -> Result<HashMap<String, String>> {
+    templates::collect_variables(content, auto_variables, presets, &mut Prompter::new(theme))
+}
+
+// Same, with `previous` answers pre-filled as the defaults.
+pub fn collect_variables_again(
+    content: &str,
+    theme: &ColorfulTheme,
+    auto_variables: &HashMap<String, String>,
+    presets: &VariablePresets,
+    previous: &HashMap<String, String>,
+) -> Result<HashMap<String, String>> {
+    let mut prompter = Prompter {
+        previous: Some(previous),
+        ..Prompter::new(theme)
+    };
+    templates::collect_variables(content, auto_variables, presets, &mut prompter)
+}
+
+// Resolves a template file name, or a fragment of one, with the picker's precedence so project
+// templates win. Several matches are offered in a picker when a terminal is attached and are an
+// error otherwise.
+pub fn find_template(
+    cfg: &ConfigState,
+    project_root: &Path,
+    name: &str,
+) -> Result<Option<SelectedTemplate>> {
+    let templates = available_templates(cfg, project_root)?;
+    let path = match templates::resolve_by_name(&templates, name) {
+        NameMatch::Found(entry) => entry.path.clone(),
+        NameMatch::NotFound => return Ok(None),
+        NameMatch::Ambiguous(candidates) => {
+            if !console::user_attended() {
+                return Err(anyhow!(
+                    "Template name {:?} matches several templates: {}",
+                    name,
+                    candidates
+                        .iter()
+                        .map(|entry| entry.label())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ));
+            }
+            let items = candidates
+                .into_iter()
+                .map(|entry| (entry.label(), entry.path.clone()))
+                .collect();
+            match picker::pick(items, &format!("Template matching {}> ", name))? {
+                Some(path) => path,
+                None => return Ok(None),
+            }
+        }
+    };
+    Ok(Some(SelectedTemplate::new(path)))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum TemplateChoice {
+    Template(PathBuf),
+    Create,
+    Rescan,
+}
+
+const STALE_SHARED_TEMPLATES_SECS: u64 = 7 * 86_400;
+const CREATE_TEMPLATE_ENTRY: &str = "+ Create a new template";
+const RESCAN_TEMPLATES_ENTRY: &str = "~ Rescan template directories";
+
+#[derive(Debug, Clone)]
+pub struct SelectedTemplate {
+    pub path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl SelectedTemplate {
+    fn new(path: PathBuf) -> Self {
+        let modified = modified_time(&path);
+        Self { path, modified }
+    }
+
+    pub fn warn_if_changed(&self) {
+        if self.changed() {
+            ui::warning!(
+                "Template {} changed on disk since it was selected; using the current content.",
+                self.path.display()
+            );
+        }
+    }
+
+    fn changed(&self) -> bool {
+        modified_time(&self.path) != self.modified
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+// `preferred` names a template file that is listed first, making it the default pick.
+pub fn choose_template(
+    cfg: &ConfigState,
+    project_root: &Path,
+    theme: &ColorfulTheme,
+    preferred: Option<&str>,
+) -> Result<Option<SelectedTemplate>> {
+    let usage = TemplateUsage::for_repo(cfg, project_root);
+    loop {
+        let templates = available_templates(cfg, project_root)?;
+        if templates.is_empty() {
+            if let Some(project_templates_dir) = project_templates_dir(project_root) {
+                ui::warning!(
+                    "No template found in {} or {}",
+                    project_templates_dir.display(),
+                    cfg.templates_dir().display()
+                );
+            } else {
+                ui::warning!("No template found in {}", cfg.templates_dir().display());
+            }
+        }
+
+        for repo in shared_templates::repos(cfg) {
+            if let Some(synced) = repo.last_synced()
+                && archive::now_secs().saturating_sub(synced) > STALE_SHARED_TEMPLATES_SECS
+            {
+                ui::hint!(
+                    "Shared templates from {} were last synced {}.",
+                    repo.name,
+                    archive::format_age(synced)
+                );
+            }
+        }
+
+        let last_used = usage
+            .as_ref()
+            .and_then(|usage| {
+                usage
+                    .last_used()
+                    .inspect_err(|err| ui::debug!("Template usage unavailable: {:#}", err))
+                    .ok()
+            })
+            .unwrap_or_default();
+        let items = template_items(templates, &last_used, preferred);
+
+        let path = match picker::pick(items, "Template> ")? {
+            None => return Ok(None),
+            Some(TemplateChoice::Template(path)) => path,
+            Some(TemplateChoice::Create) => {
+                let dir = project_templates_dir(project_root)
+                    .unwrap_or_else(|| cfg.templates_dir().to_path_buf());
+                let Some(path) = create_template(&dir, theme)? else {
+                    continue;
+                };
+                edit_template(&cfg.config.template_editor, &path)?;
+                path
+            }
+            Some(TemplateChoice::Rescan) => continue,
+        };
+        if let Some(usage) = &usage
+            && let Err(err) = usage.record(&path)
+        {
+            ui::debug!("Unable to remember the template choice: {:#}", err);
+        }
+        return Ok(Some(SelectedTemplate::new(path)));
+    }
+}
+
+// Picker entries for `choose_template`, always followed by the synthetic entries, even for a
+// single template, so a template added meanwhile stays reachable.
+fn template_items(
+    templates: Vec<TemplateEntry>,
+    last_used: &BTreeMap<PathBuf, u64>,
+    preferred: Option<&str>,
+) -> Vec<(String, TemplateChoice)> {
+    let most_recent = last_used
+        .iter()
+        .max_by_key(|(_, used_at)| **used_at)
+        .map(|(path, _)| path.clone());
+    let mut items =
+        picker::order_by_recency(templates, |entry| last_used.get(&entry.path).copied())
+            .into_iter()
+            .map(|entry| {
+                let mut label = entry.label();
+                if most_recent.as_ref() == Some(&entry.path) {
+                    label.push_str(" [last used]");
+                }
+                (label, TemplateChoice::Template(entry.path))
+            })
+            .collect::<Vec<_>>();
+    if let Some(preferred) = preferred {
+        items.sort_by_key(|(label, _)| label.trim_end_matches(" [last used]") != preferred);
+    }
+    // Synthetic entries go last so the first template stays the default.
+    items.push((CREATE_TEMPLATE_ENTRY.to_string(), TemplateChoice::Create));
+    items.push((RESCAN_TEMPLATES_ENTRY.to_string(), TemplateChoice::Rescan));
+    items
+}
+
+fn create_template(dir: &Path, theme: &ColorfulTheme) -> Result<Option<PathBuf>> {
+    let name: String = Input::with_theme(theme)
+        .with_prompt("Template file name")
+        .default("new-template.md".to_string())
+        .interact_text()?;
+    let name = name.trim();
+    if name.is_empty() || name.contains(['/', '\\']) {
+        ui::warning!("Invalid template name, aborting.");
+        return Ok(None);
+    }
+    let path = dir.join(name);
+    if !path.exists() {
+        fs::write(&path, "${feature}\n")
+            .with_context(|| format!("Unable to create template {}", path.display()))?;
+    }
+    Ok(Some(path))
+}
+
+// Pages the rendered template until the user proceeds (Some(rendered)) or aborts (None).
+// Re-prompting only touches the variables the user picks; `values` keeps the answers.
+pub fn preview_rendered(
+    template: &Path,
+    content: &str,
+    theme: &ColorfulTheme,
+    values: &mut HashMap<String, String>,
+    presets: &VariablePresets,
+    pager: &str,
+    proceed_label: &str,
+) -> Result<Option<String>> {
+    loop {
+        let rendered = substitute_variables(content, values)?;
+        let header = format!(
+            "--- {} rendered: {} bytes, {} lines ---\n\n",
+            template_label(template),
+            rendered.len(),
+            rendered.lines().count()
+        );
+        picker::page_output(pager, format!("{}{}", header, rendered).as_bytes())?;
+
+        let options = [proceed_label, "Edit variables", "Abort"];
+        match Select::with_theme(theme)
+            .with_prompt("Rendered template")
+            .items(&options)
+            .default(0)
+            .interact()?
+        {
+            0 => return Ok(Some(rendered)),
+            1 => {
+                let names = variable_names(content)?;
+                if names.is_empty() {
+                    ui::notice!("The template has no variables.");
+                    continue;
+                }
+                let picked = picker::Picker::new("Variables> ")
+                    .items(names.into_iter().map(|name| {
+                        let label = match values.get(&name) {
+                            Some(value) => format!("{} = {}", name, value),
+                            None => name.clone(),
+                        };
+                        (label, name)
+                    }))
+                    .pick_many()?;
+                for name in picked {
+                    let value = prompt_variable(
+                        theme,
+                        &name,
+                        presets.get(&name),
+                        values.get(&name).map(String::as_str),
+                    )?;
+                    values.insert(name, value);
+                }
+            }
+            _ => return Ok(None),
+        }
+    }
+}
+
+pub fn manage_global_templates(cfg: &ConfigState, theme: &ColorfulTheme) -> Result<()> {
+    let mut items = vec![(CREATE_TEMPLATE_ENTRY.to_string(), None)];
+    items.extend(
+        collect_templates(cfg.templates_dir())?
+            .into_iter()
+            .map(|path| (template_label(&path), Some(path))),
+    );
+
+    let Some(selection) = picker::pick(items, "Templates> ")? else {
+        return Ok(());
+    };
+
+    let target = match selection {
+        Some(path) => path,
+        None => match create_template(cfg.templates_dir(), theme)? {
+            Some(path) => path,
+            None => return Ok(()),
+        },
+    };
+
+    edit_template(&cfg.config.template_editor, &target)?;
+    ui::success!("Template saved at {}", target.display());
+    Ok(())
+}
+
+pub fn render_template_in_place(
+    cfg: &ConfigState,
+    template: &Path,
+    theme: &ColorfulTheme,
+    auto_variables: &HashMap<String, String>,
+    presets: &VariablePresets,
+) -> Result<()> {
+    let raw_template = read_template(cfg, template)?;
+    if !placeholder_pattern()?.is_match(&raw_template) {
+        return Ok(());
+    }
+    let rendered_template = render_template(&raw_template, theme, auto_variables, presets)?;
+    if rendered_template != raw_template {
+        fs::write(template, rendered_template)
+            .with_context(|| format!("Failed to update template {}", template.display()))?;
+    }
+    Ok(())
+}
+
+pub fn report_template_budget(
+    cfg: &ConfigState,
+    theme: &ColorfulTheme,
+    template: &Path,
+) -> Result<()> {
+    loop {
+        let content = read_template(cfg, template)?;
+        let mut stats = TemplateStats::compute(&content);
+        let mut source = "estimated";
+        if let Some(command) = &cfg.config.token_estimate_command {
+            match external_token_count(command, &content) {
+                Ok(count) => {
+                    stats.estimated_tokens = count;
+                    source = "counted";
+                }
+                Err(err) => ui::warning!("{}", err),
+            }
+        }
+
+        ui::info!(
+            "Template size: {} bytes, {} lines, {} words, ~{} tokens ({})",
+            stats.bytes,
+            stats.lines,
+            stats.words,
+            stats.estimated_tokens,
+            source
+        );
+
+        let limit = cfg.config.template_token_warning;
+        if !stats.exceeds(limit) {
+            return Ok(());
+        }
+
+        let warning = format!(
+            "Template is ~{} tokens, above the {} token warning threshold.",
+            stats.estimated_tokens, limit
+        );
+        if !console::user_attended() {
+            ui::warning!("{}", warning);
+            return Ok(());
+        }
+        ui::notice!("{}", warning);
+        if !Confirm::with_theme(theme)
+            .with_prompt("Open the editor to trim the template?")
+            .default(true)
+            .interact()?
+        {
+            return Ok(());
+        }
+        edit_template(&cfg.config.template_editor, template)?;
+    }
+}
+
+fn external_token_count(command: &str, content: &str) -> Result<usize> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .traced_spawn()
+        .with_context(|| format!("Failed to launch token counter `{}`", command))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(content.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Token counter `{}` exited with status {}",
+            command,
+            output.status
+        ));
+    }
+    let text = String::from_utf8_lossy(&output.stdout);
+    text.trim()
+        .parse()
+        .map_err(|_| anyhow!("Token counter `{}` printed `{}`", command, text.trim()))
+}
+
+pub fn edit_template(editor: &str, template_path: &Path) -> Result<()> {
+    let guard = TerminalGuard::capture();
+    let status = Command::new(editor)
+        .arg(template_path)
+        .traced_status()
+        .with_context(|| format!("Failed to launch editor {}", editor))?;
+    guard.after_exit(&status);
+    if !status.success() {
+        return Err(anyhow!("Editor {} exited with a non zero status", editor));
+    }
+    Ok(())
+}
+
+fn render_template(
+    content: &str,
+    theme: &ColorfulTheme,
+    auto_variables: &HashMap<String, String>,
+    presets: &VariablePresets,
+) -> Result<String> {
+    templates::render(content, auto_variables, presets, &mut Prompter::new(theme))
+}
+
+fn prompt_variable(
+    theme: &ColorfulTheme,
+    name: &str,
+    preset: Option<&VariablePreset>,
+    current: Option<&str>,
+) -> Result<String> {
+    let default = current.or_else(|| preset.and_then(VariablePreset::default_value));
+    let choices = preset.map(VariablePreset::choices).unwrap_or_default();
+    let prompt = format!("Value for {}", name);
+    match preset.and_then(|preset| preset.kind().map(|kind| (kind, preset))) {
+        Some((VariableKind::Bool, preset)) => {
+            // A rendered current value (e.g. a custom "yes") maps back through the preset strings.
+            let default = default
+                .map(|value| {
+                    parse_bool(value).unwrap_or_else(|_| value == preset.render_bool(true))
+                })
+                .unwrap_or(false);
+            let value = Confirm::with_theme(theme)
+                .with_prompt(prompt)
+                .default(default)
+                .interact()?;
+            return Ok(preset.render_bool(value));
+        }
+        Some((VariableKind::Int, _)) => {
+            let mut input = Input::<String>::with_theme(theme)
+                .with_prompt(prompt)
+                .validate_with(|value: &String| parse_int(value).map(|_| ()));
+            if let Some(default) = default {
+                input = input.default(default.to_string());
+            }
+            let value = input.interact_text()?;
+            return parse_int(&value)
+                .map(|value| value.to_string())
+                .map_err(|err| anyhow!(err));
+        }
+        Some((VariableKind::Enum, _)) if !choices.is_empty() => {
+            let picked = picker::Picker::new(&format!("{}> ", name))
+                .items(
+                    choices
+                        .iter()
+                        .map(|choice| (choice.clone(), choice.clone())),
+                )
+                .sort_by_key(|choice| Some(choice.as_str()) != default)
+                .pick()?;
+            return picked
+                .or_else(|| default.map(str::to_string))
+                .ok_or_else(|| anyhow!("No value picked for {}", name));
+        }
+        _ => {}
+    }
+    if !choices.is_empty() {
+        let selected = default
+            .and_then(|value| choices.iter().position(|choice| choice == value))
+            .unwrap_or(0);
+        let idx = Select::with_theme(theme)
+            .with_prompt(prompt)
+            .items(choices)
+            .default(selected)
+            .interact()?;
+        return Ok(choices[idx].clone());
+    }
+
+    let mut input = Input::<String>::with_theme(theme)
+        .with_prompt(prompt)
+        .allow_empty(true);
+    if let Some(default) = default {
+        input = input.default(default.to_string());
+    }
+    Ok(input.interact_text()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::templates::TemplateSource;
+    use crate::test_support::TempDir;
+
+    fn entry(path: &str) -> TemplateEntry {
+        TemplateEntry {
+            path: PathBuf::from(path),
+            source: TemplateSource::Project,
+            shadowed: false,
+        }
+    }
+
+    fn labels(items: &[(String, TemplateChoice)]) -> Vec<&str> {
+        items.iter().map(|(label, _)| label.as_str()).collect()
+    }
+
+    #[test]
+    fn a_single_template_still_offers_the_synthetic_entries() {
+        let items = template_items(vec![entry("/t/feature.md")], &BTreeMap::new(), None);
+
+        assert_eq!(
+            labels(&items),
+            ["feature.md", CREATE_TEMPLATE_ENTRY, RESCAN_TEMPLATES_ENTRY]
+        );
+        let choices: Vec<_> = items.into_iter().map(|(_, choice)| choice).collect();
+        assert_eq!(
+            choices,
+            [
+                TemplateChoice::Template(PathBuf::from("/t/feature.md")),
+                TemplateChoice::Create,
+                TemplateChoice::Rescan,
+            ]
+        );
+    }
+
+    #[test]
+    fn no_template_leaves_only_the_synthetic_entries() {
+        let items = template_items(Vec::new(), &BTreeMap::new(), None);
+        assert_eq!(
+            labels(&items),
+            [CREATE_TEMPLATE_ENTRY, RESCAN_TEMPLATES_ENTRY]
+        );
+    }
+
+    #[test]
+    fn recent_and_preferred_templates_come_first_before_the_synthetic_entries() {
+        let templates = vec![
+            entry("/t/bugfix.md"),
+            entry("/t/docs.md"),
+            entry("/t/feature.md"),
+        ];
+        let last_used = BTreeMap::from([
+            (PathBuf::from("/t/docs.md"), 10),
+            (PathBuf::from("/t/feature.md"), 20),
+        ]);
+
+        let items = template_items(templates.clone(), &last_used, None);
+        assert_eq!(
+            labels(&items),
+            [
+                "feature.md [last used]",
+                "docs.md",
+                "bugfix.md",
+                CREATE_TEMPLATE_ENTRY,
+                RESCAN_TEMPLATES_ENTRY,
+            ]
+        );
+
+        let items = template_items(templates, &last_used, Some("bugfix.md"));
+        assert_eq!(
+            labels(&items)[..3],
+            ["bugfix.md", "feature.md [last used]", "docs.md"]
+        );
+        assert_eq!(items[3].1, TemplateChoice::Create);
+        assert_eq!(items[4].1, TemplateChoice::Rescan);
+    }
+
+    #[test]
+    fn a_selected_template_reports_changes_on_disk() {
+        let tmp = TempDir::new("selected-template");
+        let path = tmp.path().join("feature.md");
+        fs::write(&path, "${feature}\n").unwrap();
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000))
+            .unwrap();
+
+        let selected = SelectedTemplate::new(path.clone());
+        assert!(!selected.changed());
+
+        file.set_modified(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(2_000))
+            .unwrap();
+        assert!(selected.changed());
+
+        fs::remove_file(&path).unwrap();
+        assert!(selected.changed());
+    }
+}
@@ -0,0 +1,145 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+use crate::lock::{FileLock, read_state, write_atomic};
+
+const QUEUES_DIR: &str = "merge-queues";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueuedBranch {
+    pub branch: String,
+    pub queued_at: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct QueueFile {
+    #[serde(default)]
+    entries: Vec<QueuedBranch>,
+}
+
+// Branches marked ready to merge, persisted per repository under the config dir.
+pub struct MergeQueue {
+    path: PathBuf,
+}
+
+impl MergeQueue {
+    pub fn for_repo(config_dir: &Path, repo_root: &Path) -> Self {
+        Self {
+            path: config_dir
+                .join(QUEUES_DIR)
+                .join(format!("{}.toml", config::repo_key(repo_root))),
+        }
+    }
+
+    pub fn entries(&self) -> Result<Vec<QueuedBranch>> {
+        Ok(self.read()?.entries)
+    }
+
+    // Returns false when the branch was already queued.
+    pub fn push(&self, branch: &str) -> Result<bool> {
+        self.update(|file| {
+            if file.entries.iter().any(|entry| entry.branch == branch) {
+                return false;
+            }
+            file.entries.push(QueuedBranch {
+                branch: branch.to_string(),
+                queued_at: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|elapsed| elapsed.as_secs())
+                    .unwrap_or_default(),
+            });
+            true
+        })
+    }
+
+    pub fn dir(&self) -> Option<&Path> {
+        self.path.parent()
+    }
+
+    // Drops entries whose branch fails `keep`, returning the dropped branch names.
+    pub fn retain(&self, keep: impl Fn(&str) -> bool) -> Result<Vec<String>> {
+        if self.read()?.entries.iter().all(|entry| keep(&entry.branch)) {
+            return Ok(Vec::new());
+        }
+        self.update(|file| {
+            let (kept, dropped): (Vec<_>, Vec<_>) = std::mem::take(&mut file.entries)
+                .into_iter()
+                .partition(|entry| keep(&entry.branch));
+            file.entries = kept;
+            dropped.into_iter().map(|entry| entry.branch).collect()
+        })
+    }
+
+    pub fn remove(&self, branch: &str) -> Result<()> {
+        self.update(|file| file.entries.retain(|entry| entry.branch != branch))
+    }
+
+    fn update<T>(&self, change: impl FnOnce(&mut QueueFile) -> T) -> Result<T> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Unable to create queue directory {}", dir.display()))?;
+        }
+        let _lock = FileLock::acquire(&self.path)?;
+        let mut file = self.read()?;
+        let result = change(&mut file);
+        write_atomic(&self.path, toml::to_string_pretty(&file)?)
+            .with_context(|| format!("Unable to write merge queue {}", self.path.display()))?;
+        Ok(result)
+    }
+
+    fn read(&self) -> Result<QueueFile> {
+        read_state(&self.path, "Merge queue")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    fn queue(tmp: &TempDir) -> MergeQueue {
+        MergeQueue::for_repo(&tmp.path().join("config"), &tmp.path().join("repo"))
+    }
+
+    fn branches(queue: &MergeQueue) -> Vec<String> {
+        queue
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.branch)
+            .collect()
+    }
+
+    #[test]
+    fn retain_drops_entries_for_deleted_branches() {
+        let tmp = TempDir::new("queue-retain");
+        let queue = queue(&tmp);
+        for branch in ["agent/a", "agent/gone", "agent/b"] {
+            assert!(queue.push(branch).unwrap());
+        }
+        assert!(!queue.push("agent/a").unwrap());
+
+        let dropped = queue.retain(|branch| branch != "agent/gone").unwrap();
+
+        assert_eq!(dropped, ["agent/gone"]);
+        assert_eq!(branches(&queue), ["agent/a", "agent/b"]);
+    }
+
+    #[test]
+    fn retain_keeping_everything_leaves_the_file_untouched() {
+        let tmp = TempDir::new("queue-retain-noop");
+        let queue = queue(&tmp);
+
+        assert!(queue.retain(|_| false).unwrap().is_empty());
+        assert!(!queue.path.exists());
+
+        queue.push("agent/a").unwrap();
+        assert!(queue.retain(|_| true).unwrap().is_empty());
+        assert_eq!(branches(&queue), ["agent/a"]);
+    }
+}
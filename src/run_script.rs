@@ -0,0 +1,196 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+
+use crate::invocation::AgentInvocation;
+use crate::templates;
+use crate::ui;
+
+pub const RUN_SCRIPT_FILENAME: &str = ".agent-run.sh";
+
+// Stands for the template content in script arguments; it is never a valid argument itself.
+pub const CONTENT_SENTINEL: &str = "\u{0}template_content\u{0}";
+const CONTENT_REFERENCE: &str = "\"$(cat \"$AGENT_TEMPLATE_PATH\")\"";
+
+// Writes a standalone script reproducing the agent launch, so it can be re-run without
+// AgentManager. The template content is read back from the template file instead of being inlined.
+pub fn write(invocation: &AgentInvocation) -> Result<PathBuf> {
+    let worktree = invocation.cwd.as_path();
+    let mut script =
+        String::from("#!/bin/sh\n# Generated by AgentManager on every agent launch.\n");
+    script.push_str(&format!(
+        "cd {} || exit 1\n",
+        ui::shell_quote(&worktree.to_string_lossy())
+    ));
+    for (name, value) in &invocation.env {
+        script.push_str(&format!("export {}={}\n", name, quote_word(value)));
+    }
+    script.push_str("exec ");
+    script.push_str(
+        &std::iter::once(ui::shell_quote(&invocation.command))
+            .chain(invocation.args.iter().map(|arg| quote_word(arg)))
+            .collect::<Vec<_>>()
+            .join(" "),
+    );
+    if invocation.template_on_stdin {
+        script.push_str(" < \"$AGENT_TEMPLATE_PATH\"");
+    }
+    script.push('\n');
+
+    let path = worktree.join(RUN_SCRIPT_FILENAME);
+    fs::write(&path, script)
+        .with_context(|| format!("Unable to write run script {}", path.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))
+            .with_context(|| format!("Unable to make {} executable", path.display()))?;
+    }
+    templates::ensure_excluded(worktree, RUN_SCRIPT_FILENAME)?;
+    Ok(path)
+}
+
+fn quote_word(value: &str) -> String {
+    if !value.contains(CONTENT_SENTINEL) {
+        return ui::shell_quote(value);
+    }
+    value
+        .split(CONTENT_SENTINEL)
+        .map(|part| {
+            if part.is_empty() {
+                String::new()
+            } else {
+                ui::shell_quote(part)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(CONTENT_REFERENCE)
+}
+
+// The scripts are run with `sh`.
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+    use std::path::Path;
+    use std::process::Command;
+
+    const TEMPLATE: &str = "Fix 'login' for \"$USER\"\n";
+
+    // An invocation in a worktree holding a rendered template; the agent prints its argv and
+    // one environment variable, each entry terminated by `|`.
+    fn invocation(tmp: &TempDir, args: &[&str], env_value: &str) -> AgentInvocation {
+        let worktree = tmp.path().join("work tree");
+        fs::create_dir_all(worktree.join(".git")).unwrap();
+        let template = worktree.join(templates::TEMPLATE_FILENAME);
+        fs::write(&template, TEMPLATE).unwrap();
+        let mut argv = vec![
+            "-c".to_string(),
+            "printf '%s|' \"$@\"; printf 'env=%s|' \"$AGENT_EXTRA\"".to_string(),
+            "agent".to_string(),
+        ];
+        argv.extend(args.iter().map(|arg| arg.to_string()));
+        AgentInvocation {
+            command: "sh".to_string(),
+            args: argv,
+            env: vec![
+                (
+                    "AGENT_TEMPLATE_PATH".to_string(),
+                    template.to_string_lossy().into_owned(),
+                ),
+                ("AGENT_EXTRA".to_string(), env_value.to_string()),
+            ],
+            cwd: worktree,
+            template_on_stdin: false,
+            template_content: TEMPLATE.to_string(),
+            limits: Vec::new(),
+        }
+    }
+
+    fn run(script: &Path) -> String {
+        let output = Command::new("sh")
+            .arg(script)
+            .env_remove("AGENT_EXTRA")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[test]
+    fn script_round_trips_quotes_spaces_and_dollars() {
+        let tmp = TempDir::new("run-script-quoting");
+        let args = [
+            "a b",
+            "it's",
+            "$HOME",
+            "${PATH}",
+            "`id`",
+            "$(id)",
+            "\"double\"",
+            "back\\slash",
+            "line\nbreak",
+            "*",
+            "",
+        ];
+        let env_value = "$HOME 'single' \"double\" `id`";
+        let invocation = invocation(&tmp, &args, env_value);
+
+        let script = write(&invocation).unwrap();
+
+        let expected: String = args
+            .iter()
+            .map(|arg| format!("{}|", arg))
+            .chain([format!("env={}|", env_value)])
+            .collect();
+        assert_eq!(run(&script), expected);
+        assert_eq!(script, invocation.cwd.join(RUN_SCRIPT_FILENAME));
+    }
+
+    #[test]
+    fn script_reads_the_template_content_back_from_the_file() {
+        let tmp = TempDir::new("run-script-content");
+        let invocation = invocation(
+            &tmp,
+            &[
+                CONTENT_SENTINEL,
+                &format!("--prompt={}", CONTENT_SENTINEL),
+                &format!("{}-{}", CONTENT_SENTINEL, CONTENT_SENTINEL),
+            ],
+            "",
+        );
+
+        let script = write(&invocation).unwrap();
+
+        let body = fs::read_to_string(&script).unwrap();
+        assert!(!body.contains(TEMPLATE.trim_end()), "{}", body);
+        // `$(cat ...)` drops the trailing newline, as any shell substitution would.
+        let content = TEMPLATE.trim_end_matches('\n');
+        assert_eq!(
+            run(&script),
+            format!(
+                "{}|--prompt={}|{}-{}|env=|",
+                content, content, content, content
+            )
+        );
+    }
+
+    #[test]
+    fn script_feeds_the_template_on_stdin_and_is_executable() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = TempDir::new("run-script-stdin");
+        let mut invocation = invocation(&tmp, &[], "");
+        invocation.args = vec!["-c".to_string(), "cat".to_string()];
+        invocation.template_on_stdin = true;
+
+        let script = write(&invocation).unwrap();
+
+        assert_eq!(run(&script), TEMPLATE);
+        let mode = fs::metadata(&script).unwrap().permissions().mode();
+        assert_eq!(mode & 0o111, 0o111);
+        let exclude = fs::read_to_string(invocation.cwd.join(".git/info/exclude")).unwrap();
+        assert!(exclude.lines().any(|line| line == RUN_SCRIPT_FILENAME));
+    }
+}
@@ -0,0 +1,104 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::archive;
+use crate::config::ConfigState;
+use crate::git;
+use crate::lock;
+
+const REMOTE_TEMPLATES_DIR: &str = "remote-templates";
+// Repositories with a `templates/` directory only expose that directory.
+const TEMPLATES_SUBDIR: &str = "templates";
+
+// A `template_repos` entry and where its clone lives under the config dir.
+pub struct RemoteRepo {
+    pub url: String,
+    // Short name shown in picker labels.
+    pub name: String,
+    dir: PathBuf,
+    synced_marker: PathBuf,
+}
+
+pub fn repos(cfg: &ConfigState) -> Vec<RemoteRepo> {
+    let Some(config_dir) = cfg.config_file.parent() else {
+        return Vec::new();
+    };
+    let root = config_dir.join(REMOTE_TEMPLATES_DIR);
+    cfg.config
+        .template_repos
+        .iter()
+        .map(|url| {
+            let slug = slug(url);
+            RemoteRepo {
+                url: url.clone(),
+                name: repo_name(url),
+                dir: root.join(&slug),
+                synced_marker: root.join(format!("{}.synced", slug)),
+            }
+        })
+        .collect()
+}
+
+impl RemoteRepo {
+    pub fn templates_dir(&self) -> PathBuf {
+        let subdir = self.dir.join(TEMPLATES_SUBDIR);
+        if subdir.is_dir() {
+            subdir
+        } else {
+            self.dir.clone()
+        }
+    }
+
+    pub fn is_cloned(&self) -> bool {
+        self.dir.join(".git").exists()
+    }
+
+    // Unix time of the last successful sync.
+    pub fn last_synced(&self) -> Option<u64> {
+        fs::read_to_string(&self.synced_marker)
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    }
+
+    // Shallow-clones the repository on first use, then fast-forwards it.
+    pub fn sync(&self, timeout_secs: Option<u64>) -> Result<()> {
+        if self.is_cloned() {
+            git::pull_fast_forward(&self.dir, timeout_secs)?;
+        } else {
+            if let Some(parent) = self.dir.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Unable to create {}", parent.display()))?;
+            }
+            git::shallow_clone(&self.url, &self.dir, timeout_secs)?;
+        }
+        lock::write_atomic(&self.synced_marker, archive::now_secs().to_string())
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+fn repo_name(url: &str) -> String {
+    let last = url
+        .trim_end_matches('/')
+        .rsplit(['/', ':'])
+        .next()
+        .unwrap_or(url);
+    last.strip_suffix(".git").unwrap_or(last).to_string()
+}
+
+// The whole URL goes into the directory name so two `prompts` repositories do not collide.
+fn slug(url: &str) -> String {
+    let url = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let slug = url
+        .trim_end_matches(".git")
+        .chars()
+        .map(|ch| if ch.is_ascii_alphanumeric() { ch } else { '-' })
+        .collect::<String>();
+    slug.trim_matches('-').to_string()
+}
@@ -0,0 +1,124 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+
+use crate::archive;
+use crate::config::{self, ConfigState, FetchOnStartup};
+use crate::git;
+use crate::lock;
+
+const STATE_DIR: &str = "fetch-state";
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+// Whether a startup fetch is due, given when the last successful one finished.
+pub fn is_due(policy: FetchOnStartup, last_fetch: Option<u64>, now: u64) -> bool {
+    match policy {
+        FetchOnStartup::Never => false,
+        FetchOnStartup::Always => true,
+        FetchOnStartup::Daily => last_fetch.is_none_or(|last| now.saturating_sub(last) >= DAY_SECS),
+    }
+}
+
+// A fetch running in the background; its outcome is reported once, when polled after it ends.
+pub struct StartupFetch {
+    receiver: Receiver<(Result<()>, Duration)>,
+}
+
+impl StartupFetch {
+    // Starts `git fetch --prune` when `fetch_on_startup` says it is due for `repo_root`.
+    pub fn start(cfg: &ConfigState, repo_root: &Path) -> Option<Self> {
+        let policy = cfg.config.fetch_on_startup;
+        if policy == FetchOnStartup::Never {
+            return None;
+        }
+        let marker = marker_path(cfg, repo_root)?;
+        if !is_due(policy, last_fetch(&marker), archive::now_secs()) {
+            return None;
+        }
+
+        let root = repo_root.to_path_buf();
+        let timeout_secs = cfg.config.git_network_timeout_secs;
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            let started = Instant::now();
+            let result = git::quiet_fetch(&root, timeout_secs).and_then(|()| record_fetch(&marker));
+            let _ = sender.send((result, started.elapsed()));
+        });
+        Some(Self { receiver })
+    }
+
+    // None while the fetch is still running.
+    pub fn finished(&self) -> Option<(Result<()>, Duration)> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+// None when the configuration directory cannot be written: the fetch could not be recorded.
+fn marker_path(cfg: &ConfigState, repo_root: &Path) -> Option<PathBuf> {
+    if !cfg.persistence.is_writable() {
+        return None;
+    }
+    let config_dir = cfg.config_file.parent()?;
+    Some(config_dir.join(STATE_DIR).join(config::repo_key(repo_root)))
+}
+
+// A missing or unreadable marker counts as never fetched.
+fn last_fetch(marker: &Path) -> Option<u64> {
+    fs::read_to_string(marker)
+        .ok()
+        .and_then(|content| content.trim().parse().ok())
+}
+
+fn record_fetch(marker: &Path) -> Result<()> {
+    if let Some(dir) = marker.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Unable to create directory {}", dir.display()))?;
+    }
+    lock::write_atomic(marker, archive::now_secs().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    #[test]
+    fn daily_fetch_is_due_from_exactly_one_day_after_the_last() {
+        let now = 10 * DAY_SECS;
+        let cases = [
+            (FetchOnStartup::Daily, None, true),
+            (FetchOnStartup::Daily, Some(now - DAY_SECS + 1), false),
+            (FetchOnStartup::Daily, Some(now - DAY_SECS), true),
+            (FetchOnStartup::Daily, Some(now - 3 * DAY_SECS), true),
+            (FetchOnStartup::Daily, Some(now), false),
+            // A marker from the future (clock skew) waits rather than fetching every start.
+            (FetchOnStartup::Daily, Some(now + 60), false),
+            (FetchOnStartup::Always, Some(now), true),
+            (FetchOnStartup::Never, None, false),
+        ];
+        for (policy, last, due) in cases {
+            assert_eq!(is_due(policy, last, now), due, "{:?} {:?}", policy, last);
+        }
+    }
+
+    #[test]
+    fn a_recorded_fetch_is_not_due_again_the_same_day() {
+        let tmp = TempDir::new("startup-fetch-marker");
+        let marker = tmp.path().join(STATE_DIR).join("repo");
+        assert_eq!(last_fetch(&marker), None);
+
+        record_fetch(&marker).unwrap();
+
+        let recorded = last_fetch(&marker).unwrap();
+        assert!(!is_due(
+            FetchOnStartup::Daily,
+            Some(recorded),
+            archive::now_secs()
+        ));
+        fs::write(&marker, "not a timestamp").unwrap();
+        assert_eq!(last_fetch(&marker), None);
+    }
+}
@@ -0,0 +1,75 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::archive;
+use crate::config::{self, ConfigState};
+use crate::lock::{FileLock, read_state, write_atomic};
+
+const USAGE_DIR: &str = "template-usage";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct UsageFile {
+    // Template path -> unix time it was last picked.
+    #[serde(default)]
+    last_used: BTreeMap<PathBuf, u64>,
+}
+
+// Templates picked in a repository, persisted per repository under the config dir.
+pub struct TemplateUsage {
+    path: PathBuf,
+}
+
+impl TemplateUsage {
+    // None when the configuration directory cannot be written.
+    pub fn for_repo(cfg: &ConfigState, repo_root: &Path) -> Option<Self> {
+        if !cfg.persistence.is_writable() {
+            return None;
+        }
+        let config_dir = cfg.config_file.parent()?;
+        Some(Self {
+            path: config_dir
+                .join(USAGE_DIR)
+                .join(format!("{}.toml", config::repo_key(repo_root))),
+        })
+    }
+
+    // Entries whose template file is gone are dropped.
+    pub fn last_used(&self) -> Result<BTreeMap<PathBuf, u64>> {
+        let file = self.read()?;
+        if file.last_used.keys().all(|template| template.is_file()) {
+            return Ok(file.last_used);
+        }
+        self.update(|file| {
+            file.last_used.retain(|template, _| template.is_file());
+            file.last_used.clone()
+        })
+    }
+
+    pub fn record(&self, template: &Path) -> Result<()> {
+        self.update(|file| {
+            file.last_used
+                .insert(template.to_path_buf(), archive::now_secs());
+        })
+    }
+
+    fn update<T>(&self, change: impl FnOnce(&mut UsageFile) -> T) -> Result<T> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Unable to create directory {}", dir.display()))?;
+        }
+        let _lock = FileLock::acquire(&self.path)?;
+        let mut file = self.read()?;
+        let result = change(&mut file);
+        write_atomic(&self.path, toml::to_string_pretty(&file)?)
+            .with_context(|| format!("Unable to write template usage {}", self.path.display()))?;
+        Ok(result)
+    }
+
+    fn read(&self) -> Result<UsageFile> {
+        read_state(&self.path, "Template usage")
+    }
+}
@@ -1,77 +1,297 @@
+//! Template discovery, variable presets and rendering, without any prompting.
+
 use std::collections::HashMap;
 use std::fs::{self, OpenOptions};
-use std::io::{self, Write};
+use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
-use std::process::Command;
 
-use anyhow::{Context, Result, anyhow};
-use console::style;
-use dialoguer::{Input, theme::ColorfulTheme};
+use anyhow::{Context, Result, anyhow, bail};
 use regex::Regex;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
 
 use crate::config::ConfigState;
+use crate::git;
+use crate::shared_templates;
 use crate::ui;
 
 pub const TEMPLATE_FILENAME: &str = ".agent-template";
+pub const REVIEW_FILENAME: &str = ".agent-review";
 pub const PROJECT_TEMPLATES_DIR: &str = ".agent-templates";
+pub const ENV_VARIABLE_PREFIX: &str = "env.";
+pub const VARIABLE_PRESETS_FILENAME: &str = "variables.toml";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum VariablePreset {
+    Value(String),
+    Detailed {
+        value: Option<String>,
+        #[serde(default)]
+        choices: Vec<String>,
+        #[serde(default)]
+        force_prompt: bool,
+        #[serde(rename = "type")]
+        kind: Option<VariableKind>,
+        // Strings a `bool` variable renders as; `true`/`false` by default.
+        true_value: Option<String>,
+        false_value: Option<String>,
+    },
+}
 
-pub fn available_templates(cfg: &ConfigState, project_root: &Path) -> Result<Vec<PathBuf>> {
-    if let Some(project_templates) = project_templates(project_root)? {
-        return Ok(project_templates);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VariableKind {
+    Bool,
+    Int,
+    Enum,
+}
+
+pub fn parse_bool(raw: &str) -> Result<bool, String> {
+    match raw.trim().to_ascii_lowercase().as_str() {
+        "y" | "yes" | "true" | "on" | "1" => Ok(true),
+        "n" | "no" | "false" | "off" | "0" => Ok(false),
+        other => Err(format!("{:?} is not a yes/no value", other)),
     }
-    collect_templates(&cfg.templates_dir)
 }
 
-pub fn choose_template(cfg: &ConfigState, project_root: &Path) -> Result<Option<PathBuf>> {
-    let project_templates_dir = project_root.join(PROJECT_TEMPLATES_DIR);
-    let templates = available_templates(cfg, project_root)?;
-    if templates.is_empty() {
-        if project_templates_dir.is_dir() {
-            println!(
-                "{} No template found in {} or {}",
-                style("!").yellow(),
-                project_templates_dir.display(),
-                cfg.templates_dir.display()
-            );
-        } else {
-            println!(
-                "{} No template found in {}",
-                style("!").yellow(),
-                cfg.templates_dir.display()
-            );
+pub fn parse_int(raw: &str) -> Result<i64, String> {
+    raw.trim()
+        .parse::<i64>()
+        .map_err(|err| format!("{:?} is not a whole number: {}", raw.trim(), err))
+}
+
+impl VariablePreset {
+    pub fn fixed_value(&self) -> Option<&str> {
+        match self {
+            VariablePreset::Value(value) => Some(value),
+            VariablePreset::Detailed {
+                value,
+                force_prompt: false,
+                ..
+            } => value.as_deref(),
+            VariablePreset::Detailed { .. } => None,
         }
-        return Ok(None);
     }
 
-    if templates.len() == 1 {
-        return Ok(Some(templates[0].clone()));
+    pub fn default_value(&self) -> Option<&str> {
+        match self {
+            VariablePreset::Value(value) => Some(value),
+            VariablePreset::Detailed { value, .. } => value.as_deref(),
+        }
     }
 
-    let items = templates
-        .iter()
-        .map(|p| {
-            p.file_name()
-                .unwrap_or_default()
-                .to_string_lossy()
-                .to_string()
+    pub fn choices(&self) -> &[String] {
+        match self {
+            VariablePreset::Value(_) => &[],
+            VariablePreset::Detailed { choices, .. } => choices,
+        }
+    }
+
+    pub fn kind(&self) -> Option<VariableKind> {
+        match self {
+            VariablePreset::Value(_) => None,
+            VariablePreset::Detailed { kind, .. } => *kind,
+        }
+    }
+
+    pub fn render_bool(&self, value: bool) -> String {
+        let (true_value, false_value) = match self {
+            VariablePreset::Detailed {
+                true_value,
+                false_value,
+                ..
+            } => (true_value.as_deref(), false_value.as_deref()),
+            VariablePreset::Value(_) => (None, None),
+        };
+        match value {
+            true => true_value.unwrap_or("true").to_string(),
+            false => false_value.unwrap_or("false").to_string(),
+        }
+    }
+
+    // Canonical string for `raw` according to the declared type; untyped values pass through.
+    pub fn canonicalize(&self, raw: &str) -> Result<String, String> {
+        match self.kind() {
+            None => Ok(raw.to_string()),
+            Some(VariableKind::Bool) => parse_bool(raw).map(|value| self.render_bool(value)),
+            Some(VariableKind::Int) => parse_int(raw).map(|value| value.to_string()),
+            Some(VariableKind::Enum) => {
+                if self.choices().iter().any(|choice| choice == raw) {
+                    Ok(raw.to_string())
+                } else {
+                    Err(format!(
+                        "{:?} is not one of {}",
+                        raw,
+                        self.choices().join(", ")
+                    ))
+                }
+            }
+        }
+    }
+}
+
+pub type VariablePresets = HashMap<String, VariablePreset>;
+
+pub fn load_variable_presets(project_root: &Path) -> Result<VariablePresets> {
+    let path = project_root
+        .join(PROJECT_TEMPLATES_DIR)
+        .join(VARIABLE_PRESETS_FILENAME);
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(VariablePresets::new()),
+        Err(err) => {
+            return Err(err).with_context(|| format!("Unable to read {}", path.display()));
+        }
+    };
+    toml::from_str(&content)
+        .with_context(|| format!("Invalid variable presets in {}", path.display()))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemplateEntry {
+    pub path: PathBuf,
+    pub source: TemplateSource,
+    // A template with the same file name from a higher-precedence source wins.
+    pub shadowed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateSource {
+    Project,
+    Global,
+    // Name of the shared template repository the template was synced from.
+    Remote(String),
+}
+
+impl TemplateEntry {
+    pub fn label(&self) -> String {
+        let source = match &self.source {
+            TemplateSource::Project => None,
+            TemplateSource::Global => self.shadowed.then(|| "global".to_string()),
+            TemplateSource::Remote(repo) => Some(format!("remote:{}", repo)),
+        };
+        match (source, self.shadowed) {
+            (Some(source), true) => {
+                format!("{} [{}, shadowed]", template_label(&self.path), source)
+            }
+            (Some(source), false) => format!("{} [{}]", template_label(&self.path), source),
+            (None, _) => template_label(&self.path),
+        }
+    }
+}
+
+// Project templates first, then global ones, then shared remote repositories, each alphabetical.
+// Templates sharing a file name with one from an earlier source are shadowed and only listed
+// with `show_shadowed_templates`.
+pub fn available_templates(cfg: &ConfigState, project_root: &Path) -> Result<Vec<TemplateEntry>> {
+    let mut tiers = vec![
+        (
+            TemplateSource::Project,
+            project_templates(project_root)?.unwrap_or_default(),
+        ),
+        (
+            TemplateSource::Global,
+            collect_templates(cfg.templates_dir())?,
+        ),
+    ];
+    for repo in shared_templates::repos(cfg) {
+        match collect_templates(&repo.templates_dir()) {
+            Ok(templates) => tiers.push((
+                TemplateSource::Remote(repo.name.clone()),
+                templates
+                    .into_iter()
+                    .filter(|path| !is_hidden_or_presets(path))
+                    .collect(),
+            )),
+            Err(err) => ui::warning!("Shared templates from {} unavailable: {:#}", repo.url, err),
+        }
+    }
+    Ok(merge_templates(tiers, cfg.config.show_shadowed_templates))
+}
+
+// Outcome of looking a template up by (part of) its file name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NameMatch<'a> {
+    Found(&'a TemplateEntry),
+    Ambiguous(Vec<&'a TemplateEntry>),
+    NotFound,
+}
+
+// An exact file name wins; otherwise a case-insensitive substring must match a single template.
+// Shadowed entries are skipped, so a name present in several sources resolves to the one with
+// the highest precedence.
+pub fn resolve_by_name<'a>(entries: &'a [TemplateEntry], name: &str) -> NameMatch<'a> {
+    let visible = entries.iter().filter(|entry| !entry.shadowed);
+    if let Some(exact) = visible
+        .clone()
+        .find(|entry| template_label(&entry.path) == name)
+    {
+        return NameMatch::Found(exact);
+    }
+    let fragment = name.to_lowercase();
+    let mut matches = visible
+        .filter(|entry| {
+            template_label(&entry.path)
+                .to_lowercase()
+                .contains(&fragment)
         })
         .collect::<Vec<_>>();
+    match matches.len() {
+        0 => NameMatch::NotFound,
+        1 => NameMatch::Found(matches.remove(0)),
+        _ => NameMatch::Ambiguous(matches),
+    }
+}
 
-    let selection = ui::skim_select(&items, "Template> ")?;
-    Ok(selection.map(|idx| templates[idx].clone()))
+fn merge_templates(
+    tiers: Vec<(TemplateSource, Vec<PathBuf>)>,
+    show_shadowed: bool,
+) -> Vec<TemplateEntry> {
+    let mut seen = Vec::new();
+    let mut entries = Vec::new();
+    for (source, mut paths) in tiers {
+        paths.sort_by_key(|path| template_label(path));
+        let names = paths
+            .iter()
+            .map(|path| template_label(path))
+            .collect::<Vec<_>>();
+        entries.extend(
+            paths
+                .into_iter()
+                .map(|path| TemplateEntry {
+                    shadowed: seen.contains(&template_label(&path)),
+                    source: source.clone(),
+                    path,
+                })
+                .filter(|entry| show_shadowed || !entry.shadowed),
+        );
+        seen.extend(names);
+    }
+    entries
 }
 
-pub fn copy_template_to_worktree(
-    template: &Path,
-    worktree: &Path,
-    theme: &ColorfulTheme,
-    auto_variables: &HashMap<String, String>,
-) -> Result<PathBuf> {
+fn is_hidden_or_presets(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| {
+        name.to_string_lossy().starts_with('.') || name == VARIABLE_PRESETS_FILENAME
+    })
+}
+
+pub fn project_templates_dir(project_root: &Path) -> Option<PathBuf> {
+    let dir = project_root.join(PROJECT_TEMPLATES_DIR);
+    if dir.is_dir() { Some(dir) } else { None }
+}
+
+pub fn template_label(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string()
+}
+
+pub fn write_rendered_template(worktree: &Path, rendered: &str) -> Result<PathBuf> {
     let destination = worktree.join(TEMPLATE_FILENAME);
-    let raw_template = fs::read_to_string(template)
-        .with_context(|| format!("Unable to read template {}", template.display()))?;
-    let rendered_template = render_template(&raw_template, theme, auto_variables)?;
-    fs::write(&destination, rendered_template).with_context(|| {
+    fs::write(&destination, rendered).with_context(|| {
         format!(
             "Failed to write rendered template to {}",
             destination.display()
@@ -81,80 +301,453 @@ pub fn copy_template_to_worktree(
     Ok(destination)
 }
 
-pub fn edit_template(editor: &str, template_path: &Path) -> Result<()> {
-    let status = Command::new(editor)
-        .arg(template_path)
-        .status()
-        .with_context(|| format!("Failed to launch editor {}", editor))?;
-    if !status.success() {
-        return Err(anyhow!("Editor {} exited with a non zero status", editor));
+// Unset variables are skipped so `${env.NAME}` falls back to a regular prompt.
+pub fn env_variables(passthrough: &[String]) -> HashMap<String, String> {
+    passthrough
+        .iter()
+        .filter_map(|entry| {
+            let name = env_variable_name(entry)?;
+            let value = std::env::var(name).ok()?;
+            Some((format!("{}{}", ENV_VARIABLE_PREFIX, name), value))
+        })
+        .collect()
+}
+
+fn env_variable_name(entry: &str) -> Option<&str> {
+    let trimmed = entry.trim();
+    let name = trimmed.strip_prefix(ENV_VARIABLE_PREFIX).unwrap_or(trimmed);
+    if name.is_empty() { None } else { Some(name) }
+}
+
+pub fn today() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    let days = (secs / 86_400) as i64;
+
+    // Civil-from-days conversion (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TemplateStats {
+    pub bytes: usize,
+    pub lines: usize,
+    pub words: usize,
+    pub estimated_tokens: usize,
+}
+
+impl TemplateStats {
+    pub fn compute(content: &str) -> Self {
+        let chars = content.chars().count();
+        Self {
+            bytes: content.len(),
+            lines: content.lines().count(),
+            words: content.split_whitespace().count(),
+            estimated_tokens: chars.div_ceil(4),
+        }
     }
-    Ok(())
+
+    // Whether the estimate is above the `template_token_warning` threshold; zero disables it.
+    pub fn exceeds(&self, limit: usize) -> bool {
+        limit != 0 && self.estimated_tokens > limit
+    }
+}
+
+// Cuts `content` at a line boundary so it stays within `max_tokens` (same estimate as
+// `TemplateStats`). A zero budget means no limit.
+pub fn truncate_to_tokens(content: &str, max_tokens: usize) -> String {
+    if max_tokens == 0 || TemplateStats::compute(content).estimated_tokens <= max_tokens {
+        return content.to_string();
+    }
+    let max_chars = max_tokens.saturating_mul(4);
+    let mut kept = String::new();
+    let mut chars = 0;
+    let mut lines = content.lines();
+    for line in lines.by_ref() {
+        let line_chars = line.chars().count() + 1;
+        if chars + line_chars > max_chars {
+            break;
+        }
+        chars += line_chars;
+        kept.push_str(line);
+        kept.push('\n');
+    }
+    let dropped = lines.count() + 1;
+    kept.push_str(&format!("[... truncated: {} more lines ...]\n", dropped));
+    kept
 }
 
-fn render_template(
+/// Supplies the value of a placeholder that has neither an automatic value nor a fixed preset.
+/// The binary prompts the user; programmatic callers can pass a map of values.
+pub trait VariableResolver {
+    fn resolve(&mut self, name: &str, preset: Option<&VariablePreset>) -> Result<String>;
+}
+
+impl VariableResolver for HashMap<String, String> {
+    fn resolve(&mut self, name: &str, preset: Option<&VariablePreset>) -> Result<String> {
+        self.get(name)
+            .cloned()
+            .or_else(|| {
+                preset
+                    .and_then(VariablePreset::default_value)
+                    .map(str::to_string)
+            })
+            .ok_or_else(|| anyhow!("No value for template variable {}", name))
+    }
+}
+
+/// Resolves every placeholder of `content`: automatic values first, then fixed presets, then
+/// `resolver`.
+pub fn collect_variables(
     content: &str,
-    theme: &ColorfulTheme,
     auto_variables: &HashMap<String, String>,
-) -> Result<String> {
-    let pattern = Regex::new(r"\$\{([^}]+)\}")?;
-    let mut prompts = Vec::new();
-
-    for caps in pattern.captures_iter(content) {
-        let name = caps.get(1).map(|m| m.as_str().trim()).unwrap_or_default();
-        if name.is_empty() {
-            continue;
-        }
-        if auto_variables.contains_key(name) {
+    presets: &VariablePresets,
+    resolver: &mut dyn VariableResolver,
+) -> Result<HashMap<String, String>> {
+    let mut values: HashMap<String, String> = auto_variables.clone();
+    let prompts = interactive_variables(content, auto_variables, presets)?;
+    for name in variable_names(content)? {
+        if values.contains_key(&name) || prompts.contains(&name) {
             continue;
         }
-        if !prompts.iter().any(|existing| existing == name) {
-            prompts.push(name.to_string());
+        if let Some(preset) = presets.get(&name)
+            && let Some(value) = preset.fixed_value()
+        {
+            let value = preset.canonicalize(value).unwrap_or_else(|err| {
+                ui::warning!("Preset for {}: {}; using it as-is.", name, err);
+                value.to_string()
+            });
+            values.insert(name, value);
         }
     }
 
-    if prompts.is_empty() && auto_variables.is_empty() {
-        return Ok(content.to_string());
+    for prompt in prompts {
+        let value = resolver.resolve(&prompt, presets.get(&prompt))?;
+        values.insert(prompt, value);
     }
+    Ok(values)
+}
 
-    let mut values: HashMap<String, String> = auto_variables.clone();
+// Answers recorded earlier; variables without one are left as placeholders.
+struct RecordedAnswers<'a>(&'a HashMap<String, String>);
 
-    if !prompts.is_empty() {
-        println!(
-            "{} {}",
-            style("[info]").blue(),
-            style("Template variables detected, please provide their values.").dim()
-        );
+impl VariableResolver for RecordedAnswers<'_> {
+    fn resolve(&mut self, name: &str, _preset: Option<&VariablePreset>) -> Result<String> {
+        Ok(self
+            .0
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| format!("${{{}}}", name)))
+    }
+}
+
+// What `content` renders to with previously recorded answers and no prompting, to compare a
+// cached copy with its updated source.
+pub fn render_with_answers(
+    content: &str,
+    auto_variables: &HashMap<String, String>,
+    presets: &VariablePresets,
+    answers: &HashMap<String, String>,
+) -> Result<String> {
+    render(
+        content,
+        auto_variables,
+        presets,
+        &mut RecordedAnswers(answers),
+    )
+}
 
-        for prompt in prompts {
-            let value: String = Input::with_theme(theme)
-                .with_prompt(format!("Value for {}", prompt))
-                .allow_empty(true)
-                .interact_text()?;
-            values.insert(prompt, value);
+/// Renders `content` with the values `collect_variables` resolves.
+pub fn render(
+    content: &str,
+    auto_variables: &HashMap<String, String>,
+    presets: &VariablePresets,
+    resolver: &mut dyn VariableResolver,
+) -> Result<String> {
+    let values = collect_variables(content, auto_variables, presets, resolver)?;
+    substitute_variables(content, &values)
+}
+
+// Variables `collect_variables` will ask the user for: neither automatic nor fixed by a preset.
+pub fn interactive_variables(
+    content: &str,
+    auto_variables: &HashMap<String, String>,
+    presets: &VariablePresets,
+) -> Result<Vec<String>> {
+    Ok(variable_names(content)?
+        .into_iter()
+        .filter(|name| {
+            !auto_variables.contains_key(name)
+                && presets
+                    .get(name)
+                    .and_then(VariablePreset::fixed_value)
+                    .is_none()
+        })
+        .collect())
+}
+
+// Placeholders of `template` that `values` leaves unfilled. Judged on the template rather than
+// the rendered text, so placeholders inside substituted values (a pasted diff, notes) never count.
+pub fn unresolved_placeholders(
+    template: &str,
+    values: &HashMap<String, String>,
+) -> Result<Vec<String>> {
+    let mut names: Vec<String> = Vec::new();
+    for caps in placeholder_pattern()?.captures_iter(template) {
+        let key = caps.get(1).map(|m| m.as_str().trim()).unwrap_or_default();
+        let name = placeholder_name(key);
+        if placeholder_value(key, values)?.is_none() && !names.iter().any(|seen| seen == name) {
+            names.push(name.to_string());
         }
     }
+    Ok(names)
+}
 
-    let rendered = pattern.replace_all(content, |caps: &regex::Captures| {
+pub fn substitute_variables(content: &str, values: &HashMap<String, String>) -> Result<String> {
+    let mut error = None;
+    let rendered = placeholder_pattern()?.replace_all(content, |caps: &regex::Captures| {
         let key = caps.get(1).map(|m| m.as_str().trim()).unwrap_or_default();
-        values.get(key).cloned().unwrap_or_else(|| {
-            caps.get(0)
-                .map(|m| m.as_str())
+        match placeholder_value(key, values) {
+            Ok(Some(value)) => value,
+            Ok(None) => caps[0].to_string(),
+            Err(err) => {
+                error.get_or_insert(err);
+                caps[0].to_string()
+            }
+        }
+    });
+    match error {
+        Some(err) => Err(err),
+        None => Ok(rendered.into_owned()),
+    }
+}
+
+// Value of a placeholder body such as `service` or `service|upper`: the variable's value with the
+// transforms applied left to right. None when the variable has no value.
+pub fn placeholder_value(key: &str, values: &HashMap<String, String>) -> Result<Option<String>> {
+    let mut parts = key.split('|').map(str::trim);
+    let Some(value) = parts.next().and_then(|name| values.get(name)) else {
+        return Ok(None);
+    };
+    parts
+        .try_fold(value.clone(), |value, name| {
+            let transform = Transform::parse(name).ok_or_else(|| {
+                anyhow!(
+                    "Unknown transform {} in ${{{}}}; expected one of {}",
+                    name,
+                    key,
+                    Transform::NAMES.join(", ")
+                )
+            })?;
+            Ok(transform.apply(&value))
+        })
+        .map(Some)
+}
+
+// The variable a placeholder body refers to: `service|upper` only needs `service`.
+fn placeholder_name(key: &str) -> &str {
+    key.split('|').next().unwrap_or_default().trim()
+}
+
+// Case transforms usable in placeholders as `${name|transform}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transform {
+    Upper,
+    Lower,
+    Snake,
+    Kebab,
+    Camel,
+    Slug,
+}
+
+impl Transform {
+    pub const NAMES: [&str; 6] = ["upper", "lower", "snake", "kebab", "camel", "slug"];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "upper" => Some(Transform::Upper),
+            "lower" => Some(Transform::Lower),
+            "snake" => Some(Transform::Snake),
+            "kebab" => Some(Transform::Kebab),
+            "camel" => Some(Transform::Camel),
+            "slug" => Some(Transform::Slug),
+            _ => None,
+        }
+    }
+
+    pub fn apply(self, value: &str) -> String {
+        match self {
+            Transform::Upper => value.to_uppercase(),
+            Transform::Lower => value.to_lowercase(),
+            Transform::Snake => to_snake_case(value),
+            Transform::Kebab => to_kebab_case(value),
+            Transform::Camel => to_camel_case(value),
+            Transform::Slug => sanitize_name(value),
+        }
+    }
+}
+
+// Words of an identifier or phrase: split on anything that is not a letter or digit and on case
+// changes (`fooBar` -> foo, Bar; `HTTPServer` -> HTTP, Server).
+fn words(value: &str) -> Vec<String> {
+    let chars = value.chars().collect::<Vec<_>>();
+    let mut words = Vec::new();
+    let mut current = String::new();
+    for (idx, &ch) in chars.iter().enumerate() {
+        if !ch.is_alphanumeric() {
+            if !current.is_empty() {
+                words.push(std::mem::take(&mut current));
+            }
+            continue;
+        }
+        if let Some(&previous) = idx.checked_sub(1).and_then(|prev| chars.get(prev))
+            && ch.is_uppercase()
+            && !current.is_empty()
+        {
+            let next_is_lower = chars.get(idx + 1).is_some_and(|next| next.is_lowercase());
+            if previous.is_lowercase()
+                || previous.is_numeric()
+                || (previous.is_uppercase() && next_is_lower)
+            {
+                words.push(std::mem::take(&mut current));
+            }
+        }
+        current.push(ch);
+    }
+    if !current.is_empty() {
+        words.push(current);
+    }
+    words
+}
+
+pub fn to_snake_case(value: &str) -> String {
+    words(value)
+        .iter()
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+pub fn to_kebab_case(value: &str) -> String {
+    words(value)
+        .iter()
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+pub fn to_camel_case(value: &str) -> String {
+    words(value)
+        .iter()
+        .enumerate()
+        .map(|(idx, word)| {
+            let lower = word.to_lowercase();
+            if idx == 0 {
+                return lower;
+            }
+            let mut chars = lower.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect())
                 .unwrap_or_default()
-                .to_string()
         })
-    });
+        .collect()
+}
+
+// Branch names, directory names and `${name|slug}`: lowercase ASCII letters, digits, `-` and `_`.
+pub fn sanitize_name(input: &str) -> String {
+    let mut slug = String::new();
+    let mut last_dash = false;
+    for ch in input.chars() {
+        let lower = ch.to_ascii_lowercase();
+        if lower.is_ascii_alphanumeric() {
+            slug.push(lower);
+            last_dash = false;
+        } else if "-_".contains(lower) {
+            slug.push(lower);
+            last_dash = lower == '-';
+        } else {
+            if !last_dash {
+                slug.push('-');
+                last_dash = true;
+            }
+        }
+    }
+    let trimmed = slug.trim_matches('-');
+    if trimmed.is_empty() {
+        "feature".to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
+
+// Variable names in order of first appearance; transformed placeholders count as their base
+// variable.
+pub fn variable_names(content: &str) -> Result<Vec<String>> {
+    let mut names: Vec<String> = Vec::new();
+    for caps in placeholder_pattern()?.captures_iter(content) {
+        let name = placeholder_name(caps.get(1).map(|m| m.as_str()).unwrap_or_default());
+        if !name.is_empty() && !names.iter().any(|existing| existing == name) {
+            names.push(name.to_string());
+        }
+    }
+    Ok(names)
+}
+
+// A scalar `key: value` from a leading `---` front-matter block. Only flat keys are read; quotes
+// around the value are dropped.
+pub fn front_matter_value(content: &str, key: &str) -> Option<String> {
+    let mut lines = content.lines();
+    if lines.next()?.trim_end() != "---" {
+        return None;
+    }
+    for line in lines {
+        if line.trim_end() == "---" {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        if name.trim_end() != key || name.starts_with(char::is_whitespace) {
+            continue;
+        }
+        let value = value.trim();
+        let value = [('"', '"'), ('\'', '\'')]
+            .iter()
+            .find_map(|(open, close)| value.strip_prefix(*open)?.strip_suffix(*close))
+            .unwrap_or(value);
+        return (!value.is_empty()).then(|| value.to_string());
+    }
+    None
+}
 
-    Ok(rendered.into_owned())
+pub fn placeholder_pattern() -> Result<Regex> {
+    Ok(Regex::new(r"\$\{([^}]+)\}")?)
 }
 
 fn project_templates(project_root: &Path) -> Result<Option<Vec<PathBuf>>> {
-    let project_templates_dir = project_root.join(PROJECT_TEMPLATES_DIR);
-    if !project_templates_dir.is_dir() {
+    let Some(project_templates_dir) = project_templates_dir(project_root) else {
         return Ok(None);
-    }
+    };
 
-    let templates = collect_templates(&project_templates_dir)?;
+    let templates = collect_templates(&project_templates_dir)?
+        .into_iter()
+        .filter(|path| {
+            path.file_name()
+                .is_none_or(|name| name != VARIABLE_PRESETS_FILENAME)
+        })
+        .collect::<Vec<_>>();
     if templates.is_empty() {
         Ok(None)
     } else {
@@ -162,7 +755,7 @@ fn project_templates(project_root: &Path) -> Result<Option<Vec<PathBuf>>> {
     }
 }
 
-fn collect_templates(dir: &Path) -> Result<Vec<PathBuf>> {
+pub fn collect_templates(dir: &Path) -> Result<Vec<PathBuf>> {
     if !dir.exists() {
         return Ok(Vec::new());
     }
@@ -181,6 +774,11 @@ fn collect_templates(dir: &Path) -> Result<Vec<PathBuf>> {
 }
 
 pub fn ensure_template_ignored(worktree: &Path) -> Result<()> {
+    ensure_excluded(worktree, TEMPLATE_FILENAME)
+}
+
+// Adds `file_name` to the worktree's `info/exclude` so it never shows up as an untracked change.
+pub fn ensure_excluded(worktree: &Path, file_name: &str) -> Result<()> {
     let git_dir = git_dir_for_worktree(worktree)?;
     let info_dir = git_dir.join("info");
     fs::create_dir_all(&info_dir)
@@ -193,11 +791,11 @@ pub fn ensure_template_ignored(worktree: &Path) -> Result<()> {
         Err(err) => return Err(err.into()),
     };
 
-    let alt_pattern = format!("./{}", TEMPLATE_FILENAME);
+    let alt_pattern = format!("./{}", file_name);
     let already_present = existing
         .lines()
         .map(|line| line.trim())
-        .any(|line| line == TEMPLATE_FILENAME || line == alt_pattern);
+        .any(|line| line == file_name || line == alt_pattern);
     if already_present {
         return Ok(());
     }
@@ -217,13 +815,12 @@ pub fn ensure_template_ignored(worktree: &Path) -> Result<()> {
         })?;
     }
 
-    file.write_all(TEMPLATE_FILENAME.as_bytes())
-        .with_context(|| {
-            format!(
-                "Unable to update git exclude file {}",
-                exclude_path.display()
-            )
-        })?;
+    file.write_all(file_name.as_bytes()).with_context(|| {
+        format!(
+            "Unable to update git exclude file {}",
+            exclude_path.display()
+        )
+    })?;
     file.write_all(b"\n").with_context(|| {
         format!(
             "Unable to update git exclude file {}",
@@ -234,7 +831,82 @@ pub fn ensure_template_ignored(worktree: &Path) -> Result<()> {
     Ok(())
 }
 
-fn git_dir_for_worktree(worktree: &Path) -> Result<PathBuf> {
+// Identifies a rendered template, so a relaunch can tell whether it changed since the last run.
+pub fn content_hash(content: &str) -> String {
+    format!("{:x}", Sha256::digest(content.as_bytes()))
+}
+
+// Whether a source template no longer matches the hash recorded when it was rendered.
+pub fn source_changed(recorded_hash: &str, current: &str) -> bool {
+    content_hash(current) != recorded_hash
+}
+
+// Reads a template without trusting it: reading stops past `template_max_bytes`, and a symlinked
+// template must resolve inside a templates directory or the worktree holding it.
+pub fn read_template(cfg: &ConfigState, path: &Path) -> Result<String> {
+    let mut roots = vec![cfg.templates_dir().to_path_buf()];
+    roots.extend(
+        shared_templates::repos(cfg)
+            .iter()
+            .map(|repo| repo.dir().to_path_buf()),
+    );
+    roots.extend(
+        path.ancestors()
+            .skip(1)
+            .find(|dir| dir.join(".git").exists())
+            .map(Path::to_path_buf),
+    );
+    let bytes = read_bounded(path, cfg.config.template_max_bytes, &roots)?;
+    String::from_utf8(bytes)
+        .with_context(|| format!("Template {} is not valid UTF-8", path.display()))
+}
+
+// Reads at most `limit` bytes so a huge file or a device never hangs the tool. Symlinks are only
+// followed when their target lies under one of `roots`.
+pub fn read_bounded(path: &Path, limit: u64, roots: &[PathBuf]) -> Result<Vec<u8>> {
+    let meta =
+        fs::symlink_metadata(path).with_context(|| format!("Unable to read {}", path.display()))?;
+    if meta.file_type().is_symlink() {
+        let target = fs::canonicalize(path)
+            .with_context(|| format!("Unable to resolve symlink {}", path.display()))?;
+        if !roots
+            .iter()
+            .filter_map(|root| fs::canonicalize(root).ok())
+            .any(|root| target.starts_with(root))
+        {
+            bail!(
+                "{} is a symlink to {}, outside the templates and worktree directories",
+                path.display(),
+                target.display()
+            );
+        }
+    }
+
+    let file =
+        fs::File::open(path).with_context(|| format!("Unable to read {}", path.display()))?;
+    let mut bytes = Vec::new();
+    file.take(limit.saturating_add(1))
+        .read_to_end(&mut bytes)
+        .with_context(|| format!("Unable to read {}", path.display()))?;
+    if bytes.len() as u64 > limit {
+        let size = fs::metadata(path)
+            .ok()
+            .filter(|meta| meta.is_file())
+            .map_or_else(
+                || format!("more than {}", limit),
+                |meta| meta.len().to_string(),
+            );
+        bail!(
+            "{} is {} bytes, over the {} byte limit (template_max_bytes)",
+            path.display(),
+            size,
+            limit
+        );
+    }
+    Ok(bytes)
+}
+
+pub fn git_dir_for_worktree(worktree: &Path) -> Result<PathBuf> {
     let git_entry = worktree.join(".git");
     if git_entry.is_dir() {
         return Ok(git_entry);
@@ -255,10 +927,726 @@ fn git_dir_for_worktree(worktree: &Path) -> Result<PathBuf> {
         ));
     };
     let path_str = path_spec.trim();
-    let mut git_dir = PathBuf::from(path_str);
+    // Written by git, so it may be a path as seen through `git_command_prefix`.
+    let mut git_dir = git::unmap_path(Path::new(path_str));
     if git_dir.is_relative() {
         git_dir = worktree.join(git_dir);
     }
 
     Ok(git_dir)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{TempDir, commit_file, git, init_repo};
+
+    fn entries() -> Vec<TemplateEntry> {
+        let paths = |paths: &[&str]| paths.iter().map(PathBuf::from).collect::<Vec<_>>();
+        merge_templates(
+            vec![
+                (
+                    TemplateSource::Project,
+                    paths(&["/project/02-bugfix-with-tests.md", "/project/refactor.md"]),
+                ),
+                (
+                    TemplateSource::Global,
+                    paths(&[
+                        "/global/refactor.md",
+                        "/global/feature.md",
+                        "/global/old-docs.md",
+                    ]),
+                ),
+                (
+                    TemplateSource::Remote("team".to_string()),
+                    paths(&["/team/feature.md", "/team/docs.md"]),
+                ),
+            ],
+            true,
+        )
+    }
+
+    fn found(outcome: NameMatch<'_>) -> &Path {
+        match outcome {
+            NameMatch::Found(entry) => &entry.path,
+            other => panic!("expected a single template, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn resolves_an_exact_file_name() {
+        let entries = entries();
+        assert_eq!(
+            found(resolve_by_name(&entries, "02-bugfix-with-tests.md")),
+            Path::new("/project/02-bugfix-with-tests.md")
+        );
+        // "docs.md" is also part of "old-docs.md", but the exact name wins.
+        assert_eq!(
+            found(resolve_by_name(&entries, "docs.md")),
+            Path::new("/team/docs.md")
+        );
+    }
+
+    #[test]
+    fn resolves_a_unique_fragment_case_insensitively() {
+        let entries = entries();
+        assert_eq!(
+            found(resolve_by_name(&entries, "bugfix")),
+            Path::new("/project/02-bugfix-with-tests.md")
+        );
+        assert_eq!(
+            found(resolve_by_name(&entries, "OLD-DOCS")),
+            Path::new("/global/old-docs.md")
+        );
+    }
+
+    #[test]
+    fn breaks_ties_by_source_precedence() {
+        let entries = entries();
+        assert_eq!(
+            found(resolve_by_name(&entries, "refactor.md")),
+            Path::new("/project/refactor.md")
+        );
+        assert_eq!(
+            found(resolve_by_name(&entries, "feature")),
+            Path::new("/global/feature.md")
+        );
+    }
+
+    #[test]
+    fn reports_ambiguous_fragments() {
+        let entries = entries();
+        let NameMatch::Ambiguous(matches) = resolve_by_name(&entries, "re") else {
+            panic!("\"re\" should be ambiguous");
+        };
+        assert_eq!(
+            matches
+                .iter()
+                .map(|entry| entry.path.as_path())
+                .collect::<Vec<_>>(),
+            [
+                Path::new("/project/refactor.md"),
+                Path::new("/global/feature.md")
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_unknown_names() {
+        assert_eq!(
+            resolve_by_name(&entries(), "migration"),
+            NameMatch::NotFound
+        );
+        assert_eq!(resolve_by_name(&[], "bugfix"), NameMatch::NotFound);
+    }
+
+    fn write_templates(dir: &Path, names: &[&str]) {
+        fs::create_dir_all(dir).unwrap();
+        for name in names {
+            fs::write(dir.join(name), format!("{}\n", name)).unwrap();
+        }
+    }
+
+    fn listing(entries: &[TemplateEntry]) -> Vec<(String, TemplateSource, bool)> {
+        entries
+            .iter()
+            .map(|entry| {
+                (
+                    template_label(&entry.path),
+                    entry.source.clone(),
+                    entry.shadowed,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn project_templates_shadow_global_and_remote_ones() {
+        let tmp = TempDir::new("template-tiers");
+        let mut cfg = ConfigState::load_from(&tmp.path().join("config")).unwrap();
+        cfg.config.template_repos = vec!["https://example.com/team/prompts.git".to_string()];
+        let project = tmp.path().join("project");
+        write_templates(
+            &project.join(PROJECT_TEMPLATES_DIR),
+            &["shared.md", "bugfix.md", VARIABLE_PRESETS_FILENAME],
+        );
+        write_templates(cfg.templates_dir(), &["shared.md", "global.md"]);
+        // A synced clone, faked as a plain directory with a `templates/` subdirectory.
+        let remote = &shared_templates::repos(&cfg)[0];
+        write_templates(remote.dir(), &["ignored.md"]);
+        write_templates(
+            &remote.dir().join("templates"),
+            &[
+                "shared.md",
+                "global.md",
+                "remote.md",
+                ".hidden.md",
+                VARIABLE_PRESETS_FILENAME,
+            ],
+        );
+        let team = TemplateSource::Remote("prompts".to_string());
+
+        assert_eq!(
+            listing(&available_templates(&cfg, &project).unwrap()),
+            [
+                ("bugfix.md".to_string(), TemplateSource::Project, false),
+                ("shared.md".to_string(), TemplateSource::Project, false),
+                ("default.md".to_string(), TemplateSource::Global, false),
+                ("global.md".to_string(), TemplateSource::Global, false),
+                ("remote.md".to_string(), team.clone(), false),
+            ]
+        );
+
+        cfg.config.show_shadowed_templates = true;
+        let entries = available_templates(&cfg, &project).unwrap();
+        assert_eq!(
+            listing(&entries)
+                .into_iter()
+                .filter(|(_, _, shadowed)| *shadowed)
+                .collect::<Vec<_>>(),
+            [
+                ("shared.md".to_string(), TemplateSource::Global, true),
+                ("global.md".to_string(), team.clone(), true),
+                ("shared.md".to_string(), team, true),
+            ]
+        );
+        assert_eq!(
+            entries.last().unwrap().label(),
+            "shared.md [remote:prompts, shadowed]"
+        );
+    }
+
+    #[test]
+    fn shared_templates_sync_from_a_local_remote() {
+        let tmp = TempDir::new("template-sync");
+        let source = tmp.path().join("prompts");
+        init_repo(&source);
+        commit_file(&source, "templates/review.md", "Review\n", "review");
+        let mut cfg = ConfigState::load_from(&tmp.path().join("config")).unwrap();
+        cfg.config.template_repos = vec![format!("file://{}", source.display())];
+        let repo = &shared_templates::repos(&cfg)[0];
+        assert!(!repo.is_cloned());
+        assert_eq!(repo.last_synced(), None);
+
+        repo.sync(None).unwrap();
+        assert!(repo.is_cloned());
+        assert!(repo.last_synced().is_some());
+        let names = |cfg: &ConfigState| {
+            available_templates(cfg, tmp.path())
+                .unwrap()
+                .into_iter()
+                .filter(|entry| matches!(entry.source, TemplateSource::Remote(_)))
+                .map(|entry| template_label(&entry.path))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&cfg), ["review.md"]);
+
+        commit_file(&source, "templates/bugfix.md", "Fix\n", "bugfix");
+        repo.sync(None).unwrap();
+        assert_eq!(names(&cfg), ["bugfix.md", "review.md"]);
+
+        // A failed sync keeps the cached copy usable.
+        git(tmp.path(), &["init", "-q", "--bare", "unrelated.git"]);
+        git(
+            repo.dir(),
+            &[
+                "remote",
+                "set-url",
+                "origin",
+                tmp.path().join("unrelated.git").to_str().unwrap(),
+            ],
+        );
+        assert!(repo.sync(None).is_err());
+        assert_eq!(names(&cfg), ["bugfix.md", "review.md"]);
+    }
+
+    #[test]
+    fn env_passthrough_entries_accept_an_optional_prefix() {
+        let cases = [
+            ("HOME", Some("HOME")),
+            ("env.HOME", Some("HOME")),
+            ("  env.CI  ", Some("CI")),
+            ("env.", None),
+            ("", None),
+            ("   ", None),
+            // Only one prefix is stripped.
+            ("env.env.X", Some("env.X")),
+        ];
+        for (entry, expected) in cases {
+            assert_eq!(env_variable_name(entry), expected, "{:?}", entry);
+        }
+    }
+
+    #[test]
+    fn env_variables_expose_set_variables_under_the_prefix() {
+        let path = std::env::var("PATH").expect("PATH is set for tests");
+        let passthrough = [
+            "env.PATH".to_string(),
+            "AGENT_MANAGER_TEST_SURELY_UNSET".to_string(),
+            String::new(),
+        ];
+
+        let variables = env_variables(&passthrough);
+
+        assert_eq!(variables, HashMap::from([("env.PATH".to_string(), path)]));
+    }
+
+    #[test]
+    fn unset_env_placeholders_are_prompted_for() {
+        let content = "${env.PATH} ${env.AGENT_MANAGER_TEST_SURELY_UNSET} ${feature}";
+        let auto = env_variables(&[
+            "PATH".to_string(),
+            "AGENT_MANAGER_TEST_SURELY_UNSET".to_string(),
+        ]);
+
+        let prompts = interactive_variables(content, &auto, &VariablePresets::new()).unwrap();
+
+        assert_eq!(prompts, ["env.AGENT_MANAGER_TEST_SURELY_UNSET", "feature"]);
+    }
+
+    #[test]
+    fn template_stats_count_bytes_lines_words_and_tokens() {
+        let cases = [
+            ("", (0, 0, 0, 0)),
+            ("abcd", (4, 1, 1, 1)),
+            ("abcde", (5, 1, 1, 2)),
+            ("one two\nthree\n", (14, 2, 3, 4)),
+            // Tokens follow characters, not bytes.
+            ("\u{e9}t\u{e9} \u{e0} \u{1f600}", (13, 1, 3, 2)),
+        ];
+        for (content, (bytes, lines, words, tokens)) in cases {
+            let stats = TemplateStats::compute(content);
+            assert_eq!(
+                (
+                    stats.bytes,
+                    stats.lines,
+                    stats.words,
+                    stats.estimated_tokens
+                ),
+                (bytes, lines, words, tokens),
+                "{:?}",
+                content
+            );
+        }
+    }
+
+    #[test]
+    fn token_warning_threshold_is_exclusive_and_zero_disables_it() {
+        let stats = TemplateStats::compute(&"a".repeat(400));
+        assert_eq!(stats.estimated_tokens, 100);
+        assert!(stats.exceeds(99));
+        assert!(!stats.exceeds(100));
+        assert!(!stats.exceeds(101));
+        assert!(!stats.exceeds(0));
+    }
+
+    #[test]
+    fn truncate_to_tokens_cuts_at_a_line_boundary() {
+        let content = "aaaa\nbbbb\ncccc\ndddd\n";
+        assert_eq!(truncate_to_tokens(content, 0), content);
+        assert_eq!(truncate_to_tokens(content, 5), content);
+
+        let truncated = truncate_to_tokens(content, 3);
+        assert_eq!(truncated, "aaaa\nbbbb\n[... truncated: 2 more lines ...]\n");
+    }
+
+    // Answers prompts from a map and records which variables were asked, with their preset default.
+    #[derive(Default)]
+    struct RecordingResolver {
+        answers: HashMap<String, String>,
+        asked: Vec<(String, Option<String>)>,
+    }
+
+    impl VariableResolver for RecordingResolver {
+        fn resolve(&mut self, name: &str, preset: Option<&VariablePreset>) -> Result<String> {
+            let default = preset.and_then(VariablePreset::default_value);
+            self.asked
+                .push((name.to_string(), default.map(str::to_string)));
+            Ok(self
+                .answers
+                .get(name)
+                .cloned()
+                .unwrap_or_else(|| format!("prompted-{}", name)))
+        }
+    }
+
+    fn presets(toml: &str) -> VariablePresets {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn cli_values_win_over_presets_which_win_over_prompts() {
+        let content = "${service} ${team} ${region} ${owner}";
+        let presets = presets(
+            r#"
+            service = "preset-service"
+            team = "preset-team"
+            region = { value = "eu-west", force_prompt = true }
+            "#,
+        );
+        // CLI `--var` values reach `collect_variables` as automatic values.
+        let cli = HashMap::from([("service".to_string(), "cli-service".to_string())]);
+        let mut resolver = RecordingResolver::default();
+
+        let values = collect_variables(content, &cli, &presets, &mut resolver).unwrap();
+
+        assert_eq!(values["service"], "cli-service");
+        assert_eq!(values["team"], "preset-team");
+        assert_eq!(values["region"], "prompted-region");
+        assert_eq!(values["owner"], "prompted-owner");
+        // A forced prompt offers the preset as its default; fixed presets are never asked.
+        assert_eq!(
+            resolver.asked,
+            [
+                ("region".to_string(), Some("eu-west".to_string())),
+                ("owner".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn cli_values_also_override_forced_prompts() {
+        let presets = presets(r#"region = { value = "eu-west", force_prompt = true }"#);
+        let cli = HashMap::from([("region".to_string(), "us-east".to_string())]);
+        let mut resolver = RecordingResolver::default();
+
+        let rendered = render("${region}", &cli, &presets, &mut resolver).unwrap();
+
+        assert_eq!(rendered, "us-east");
+        assert!(resolver.asked.is_empty());
+    }
+
+    #[test]
+    fn variable_presets_load_from_the_project_and_report_parse_errors() {
+        let tmp = TempDir::new("variable-presets");
+        assert!(load_variable_presets(tmp.path()).unwrap().is_empty());
+
+        let dir = tmp.path().join(PROJECT_TEMPLATES_DIR);
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join(VARIABLE_PRESETS_FILENAME);
+        fs::write(
+            &file,
+            "service = \"billing\"\nteam = { value = \"core\", choices = [\"core\", \"infra\"] }\n",
+        )
+        .unwrap();
+        let loaded = load_variable_presets(tmp.path()).unwrap();
+        assert_eq!(loaded["service"].fixed_value(), Some("billing"));
+        assert_eq!(loaded["team"].choices(), ["core", "infra"]);
+
+        fs::write(&file, "service = \"billing\"\nteam = \n").unwrap();
+        let err = format!("{:#}", load_variable_presets(tmp.path()).unwrap_err());
+        assert!(err.contains("Invalid variable presets"), "{}", err);
+        assert!(err.contains("line 2"), "{}", err);
+    }
+
+    #[test]
+    fn parse_bool_accepts_the_usual_spellings() {
+        for raw in ["y", "YES", " true ", "On", "1"] {
+            assert_eq!(parse_bool(raw), Ok(true), "{:?}", raw);
+        }
+        for raw in ["n", "No", "FALSE", "off", "0"] {
+            assert_eq!(parse_bool(raw), Ok(false), "{:?}", raw);
+        }
+        for raw in ["", "maybe", "2", "yess"] {
+            assert!(parse_bool(raw).is_err(), "{:?}", raw);
+        }
+    }
+
+    #[test]
+    fn parse_int_reads_signed_whole_numbers() {
+        assert_eq!(parse_int("42"), Ok(42));
+        assert_eq!(parse_int(" -7 "), Ok(-7));
+        assert_eq!(parse_int("+3"), Ok(3));
+        let err = parse_int("4.5").unwrap_err();
+        assert!(err.starts_with("\"4.5\" is not a whole number"), "{}", err);
+        assert!(parse_int("").is_err());
+        assert!(parse_int("99999999999999999999").is_err());
+    }
+
+    #[test]
+    fn typed_presets_canonicalize_answers() {
+        let presets = presets(
+            r#"
+            include_tests = { type = "bool" }
+            confirm = { type = "bool", true_value = "yes", false_value = "no" }
+            retries = { type = "int", value = "3" }
+            env = { type = "enum", choices = ["dev", "prod"] }
+            plain = "anything"
+            "#,
+        );
+
+        assert_eq!(presets["include_tests"].kind(), Some(VariableKind::Bool));
+        assert_eq!(
+            presets["include_tests"].canonicalize("Y"),
+            Ok("true".to_string())
+        );
+        assert_eq!(
+            presets["include_tests"].canonicalize("off"),
+            Ok("false".to_string())
+        );
+        assert_eq!(
+            presets["confirm"].canonicalize("true"),
+            Ok("yes".to_string())
+        );
+        assert_eq!(presets["confirm"].canonicalize("0"), Ok("no".to_string()));
+        assert_eq!(presets["retries"].canonicalize(" 05 "), Ok("5".to_string()));
+        assert!(presets["retries"].canonicalize("five").is_err());
+        assert_eq!(presets["env"].canonicalize("prod"), Ok("prod".to_string()));
+        assert_eq!(
+            presets["env"].canonicalize("staging"),
+            Err("\"staging\" is not one of dev, prod".to_string())
+        );
+        assert_eq!(presets["plain"].kind(), None);
+        assert_eq!(
+            presets["plain"].canonicalize("  as is "),
+            Ok("  as is ".to_string())
+        );
+    }
+
+    #[test]
+    fn unknown_variable_types_are_rejected() {
+        let parsed = toml::from_str::<VariablePresets>(r#"x = { type = "float" }"#);
+        assert!(parsed.is_err());
+    }
+
+    #[test]
+    fn fixed_presets_are_canonicalized_when_collected() {
+        let presets = presets(r#"include_tests = { type = "bool", value = "yes" }"#);
+        let values = collect_variables(
+            "${include_tests}",
+            &HashMap::new(),
+            &presets,
+            &mut RecordingResolver::default(),
+        )
+        .unwrap();
+        assert_eq!(values["include_tests"], "true");
+    }
+
+    #[test]
+    fn interactive_variables_skip_automatic_values_and_fixed_presets() {
+        let presets = presets(
+            r#"
+            team = "platform"
+            region = { value = "eu-west", force_prompt = true }
+            "#,
+        );
+        let auto = HashMap::from([("branch".to_string(), "agent/login".to_string())]);
+        let content = "${branch} ${team} ${region} ${owner|upper} ${owner}";
+
+        assert_eq!(
+            interactive_variables(content, &auto, &presets).unwrap(),
+            ["region", "owner"]
+        );
+        assert!(
+            interactive_variables("no placeholders", &auto, &presets)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn unresolved_placeholders_only_report_the_templates_own_names() {
+        let template = "Fix ${issue} for ${owner|upper}.\n\n${diff}";
+        let values = HashMap::from([
+            ("issue".to_string(), "#12".to_string()),
+            // A pasted diff may contain placeholders of its own.
+            ("diff".to_string(), "+echo ${HOME} ${issue}".to_string()),
+        ]);
+
+        assert_eq!(
+            unresolved_placeholders(template, &values).unwrap(),
+            ["owner"]
+        );
+        let values = HashMap::from([
+            ("issue".to_string(), "#12".to_string()),
+            ("owner".to_string(), "ana".to_string()),
+            ("diff".to_string(), String::new()),
+        ]);
+        assert!(
+            unresolved_placeholders(template, &values)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn dollar_dollar_is_plain_text_not_an_escape() {
+        let values = HashMap::from([("price".to_string(), "5".to_string())]);
+        let template = "echo $$ costs $${price}";
+
+        let rendered = substitute_variables(template, &values).unwrap();
+
+        assert_eq!(rendered, "echo $$ costs $5");
+        assert!(
+            unresolved_placeholders(template, &values)
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(
+            unresolved_placeholders(template, &HashMap::new()).unwrap(),
+            ["price"]
+        );
+    }
+
+    #[test]
+    fn read_bounded_accepts_a_file_at_the_limit_and_rejects_one_byte_more() {
+        let tmp = TempDir::new("read-bounded-size");
+        let path = tmp.path().join("template.md");
+        fs::write(&path, "0123456789").unwrap();
+
+        assert_eq!(read_bounded(&path, 10, &[]).unwrap(), b"0123456789");
+        let err = read_bounded(&path, 9, &[]).unwrap_err().to_string();
+        assert!(
+            err.contains("is 10 bytes, over the 9 byte limit"),
+            "{}",
+            err
+        );
+        assert!(read_bounded(&tmp.path().join("missing.md"), 10, &[]).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn read_bounded_follows_symlinks_only_inside_the_roots() {
+        use std::os::unix::fs::symlink;
+
+        let tmp = TempDir::new("read-bounded-symlink");
+        let templates = tmp.path().join("templates");
+        let outside = tmp.path().join("outside");
+        fs::create_dir_all(templates.join("shared")).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(templates.join("shared/base.md"), "base").unwrap();
+        fs::write(outside.join("secret.txt"), "secret").unwrap();
+        symlink(
+            templates.join("shared/base.md"),
+            templates.join("inside.md"),
+        )
+        .unwrap();
+        symlink(outside.join("secret.txt"), templates.join("escape.md")).unwrap();
+        // `..` hops are resolved before the roots are compared.
+        symlink("../outside/secret.txt", templates.join("relative.md")).unwrap();
+        let roots = [templates.clone()];
+
+        assert_eq!(
+            read_bounded(&templates.join("inside.md"), 100, &roots).unwrap(),
+            b"base"
+        );
+        for link in ["escape.md", "relative.md"] {
+            let err = read_bounded(&templates.join(link), 100, &roots)
+                .unwrap_err()
+                .to_string();
+            assert!(err.contains("outside the templates"), "{}: {}", link, err);
+        }
+        // A regular file is read whatever the roots.
+        assert_eq!(
+            read_bounded(&outside.join("secret.txt"), 100, &roots).unwrap(),
+            b"secret"
+        );
+    }
+
+    #[test]
+    fn content_hash_is_the_sha256_of_the_text() {
+        assert_eq!(
+            content_hash(""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(content_hash("Fix ${issue}"), content_hash("Fix ${issue}"));
+        assert_ne!(content_hash("Fix ${issue}"), content_hash("Fix ${issue}\n"));
+    }
+
+    #[test]
+    fn source_changed_compares_against_the_recorded_hash() {
+        let recorded = content_hash("Fix ${issue}");
+        assert!(!source_changed(&recorded, "Fix ${issue}"));
+        assert!(source_changed(&recorded, "Fix ${issue} quickly"));
+        assert!(source_changed("", "Fix ${issue}"));
+    }
+
+    #[test]
+    fn words_split_on_separators_and_case_changes() {
+        let cases: [(&str, &[&str]); 10] = [
+            ("fooBar", &["foo", "Bar"]),
+            ("HTTPServer", &["HTTP", "Server"]),
+            ("parseHTTP2Response", &["parse", "HTTP2", "Response"]),
+            ("v2Api", &["v2", "Api"]),
+            (
+                "  add--login  form_page ",
+                &["add", "login", "form", "page"],
+            ),
+            ("ÉtéChaud", &["Été", "Chaud"]),
+            ("naïve café", &["naïve", "café"]),
+            ("日本語 テスト", &["日本語", "テスト"]),
+            ("ALLCAPS", &["ALLCAPS"]),
+            ("--", &[]),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(words(input), expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn case_transforms_table() {
+        let cases = [
+            (
+                "Add login form",
+                "add_login_form",
+                "add-login-form",
+                "addLoginForm",
+            ),
+            (
+                "parseHTTPResponse",
+                "parse_http_response",
+                "parse-http-response",
+                "parseHttpResponse",
+            ),
+            ("user_id", "user_id", "user-id", "userId"),
+            (
+                "ÉtéChaud été",
+                "été_chaud_été",
+                "été-chaud-été",
+                "étéChaudÉté",
+            ),
+            ("", "", "", ""),
+        ];
+        for (input, snake, kebab, camel) in cases {
+            assert_eq!(to_snake_case(input), snake, "{:?}", input);
+            assert_eq!(to_kebab_case(input), kebab, "{:?}", input);
+            assert_eq!(to_camel_case(input), camel, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn sanitize_name_keeps_ascii_and_falls_back_to_feature() {
+        let cases = [
+            ("Add Login Form!", "add-login-form"),
+            ("fix/login_page", "fix-login_page"),
+            ("--login  !? page--", "login-page"),
+            // Non-ASCII letters are separators, not transliterated.
+            ("café au lait", "caf-au-lait"),
+            ("日本語", "feature"),
+            ("", "feature"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(sanitize_name(input), expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn transforms_parse_apply_and_chain_in_placeholders() {
+        for name in Transform::NAMES {
+            assert!(Transform::parse(name).is_some(), "{}", name);
+        }
+        assert_eq!(Transform::parse("title"), None);
+        assert_eq!(Transform::Upper.apply("straße"), "STRASSE");
+        assert_eq!(Transform::Lower.apply("ÉTÉ"), "été");
+        assert_eq!(Transform::Slug.apply("Login Form"), "login-form");
+
+        let values = HashMap::from([("feature".to_string(), "Add login form".to_string())]);
+        let rendered =
+            substitute_variables("${feature|snake|upper} ${ feature | camel }", &values).unwrap();
+        assert_eq!(rendered, "ADD_LOGIN_FORM addLoginForm");
+        let err = substitute_variables("${feature|title}", &values)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Unknown transform title"), "{}", err);
+    }
+}
@@ -0,0 +1,99 @@
+use std::fs::File;
+use std::io::{self, IsTerminal, Write};
+use std::process::{Command, ExitStatus, Stdio};
+
+// Leave the alternate screen, then show the cursor.
+const RESET_SEQUENCE: &str = "\x1b[?1049l\x1b[?25h";
+
+// Held around an interactive child program (agent, editor, shell, lazygit). The termios settings
+// are saved before the launch and put back when the guard drops, so a child that crashes or is
+// killed mid-way cannot leave the terminal without echo for the prompts that follow.
+pub struct TerminalGuard {
+    saved: Option<String>,
+}
+
+impl TerminalGuard {
+    pub fn capture() -> Self {
+        let saved = if io::stdout().is_terminal() {
+            stty(&["-g"])
+        } else {
+            None
+        };
+        Self { saved }
+    }
+
+    // A child that failed may not have left the alternate screen or shown the cursor again.
+    pub fn after_exit(&self, status: &ExitStatus) {
+        if needs_screen_reset(self.saved.is_some(), status.success()) {
+            let _ = write_screen_reset(&mut io::stdout());
+        }
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        if let Some(saved) = &self.saved {
+            stty(&[saved.as_str()]);
+        }
+    }
+}
+
+pub fn needs_screen_reset(is_tty: bool, success: bool) -> bool {
+    is_tty && !success
+}
+
+pub fn write_screen_reset(out: &mut impl Write) -> io::Result<()> {
+    out.write_all(RESET_SEQUENCE.as_bytes())?;
+    out.flush()
+}
+
+// `stty` acts on its stdin, which must be the controlling terminal even when ours is redirected.
+#[cfg(unix)]
+fn stty(args: &[&str]) -> Option<String> {
+    let tty = File::open("/dev/tty").ok()?;
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::from(tty))
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(not(unix))]
+fn stty(_args: &[&str]) -> Option<String> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_a_failed_child_on_a_terminal_needs_a_screen_reset() {
+        let cases = [
+            (true, false, true),
+            (true, true, false),
+            (false, false, false),
+            (false, true, false),
+        ];
+        for (is_tty, success, reset) in cases {
+            assert_eq!(
+                needs_screen_reset(is_tty, success),
+                reset,
+                "{:?}",
+                (is_tty, success)
+            );
+        }
+    }
+
+    #[test]
+    fn screen_reset_leaves_the_alternate_screen_then_shows_the_cursor() {
+        let mut out = Vec::new();
+        write_screen_reset(&mut out).unwrap();
+        assert_eq!(out, b"\x1b[?1049l\x1b[?25h");
+    }
+}
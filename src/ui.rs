@@ -1,40 +1,594 @@
-use std::io::Cursor;
-
-use anyhow::{Result, anyhow};
-use skim::prelude::*;
-
-pub fn skim_select(items: &[String], prompt: &str) -> Result<Option<usize>> {
-    if items.is_empty() {
-        return Ok(None);
-    }
-
-    let options = SkimOptionsBuilder::default()
-        .multi(false)
-        .height(Some("30%"))
-        .prompt(Some(prompt))
-        .build()
-        .map_err(|err| anyhow!("Invalid skim configuration: {}", err))?;
-
-    let display = items
-        .iter()
-        .map(|item| item.replace('\n', " "))
-        .collect::<Vec<_>>();
-    let input = display.join("\n");
-
-    let reader = Cursor::new(input);
-    let item_reader = SkimItemReader::default().of_bufread(reader);
-    let output = Skim::run_with(&options, Some(item_reader));
-    if let Some(out) = output {
-        if out.is_abort {
-            return Ok(None);
-        }
-        if let Some(item) = out.selected_items.first() {
-            let value = item.output().to_string();
-            if let Some(idx) = display.iter().position(|candidate| candidate == &value) {
-                return Ok(Some(idx));
+use std::io::{self, BufRead, BufReader, IsTerminal, Read, Write};
+use std::path::Path;
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::Verbosity;
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+pub fn verbosity() -> Verbosity {
+    match VERBOSITY.load(Ordering::Relaxed) {
+        level if level == Verbosity::Quiet as u8 => Verbosity::Quiet,
+        level if level == Verbosity::Verbose as u8 => Verbosity::Verbose,
+        _ => Verbosity::Normal,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Info,
+    Success,
+    Hint,
+    Notice,
+    Warn,
+    Error,
+    Debug,
+}
+
+// Central output facade: quiet mode drops info/success/hint lines, warnings and errors always show,
+// debug lines only appear in verbose mode. Everything goes to stderr so stdout only carries data.
+pub fn log(level: Level, message: &str) {
+    if verbosity() == Verbosity::Quiet
+        && matches!(level, Level::Info | Level::Success | Level::Hint)
+    {
+        return;
+    }
+    match level {
+        Level::Info => eprintln!("{} {}", paint("[info]", Color::Blue), message),
+        Level::Success => eprintln!("{} {}", paint("[ok]", Color::Green), message),
+        Level::Hint => eprintln!("{}", paint(message, Color::Dim)),
+        Level::Notice => eprintln!("{}", paint(message, Color::Yellow)),
+        Level::Warn => eprintln!("{} {}", paint("!", Color::Yellow), message),
+        Level::Error => eprintln!("{} {}", paint("!", Color::Red), message),
+        Level::Debug => {
+            if verbosity() == Verbosity::Verbose {
+                eprintln!("{} {}", paint("[debug]", Color::Magenta), message);
             }
         }
     }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Color {
+    Blue,
+    Green,
+    Yellow,
+    Red,
+    Magenta,
+    Dim,
+}
+
+#[cfg(feature = "cli")]
+fn paint(text: &str, color: Color) -> String {
+    let styled = console::style(text).for_stderr();
+    match color {
+        Color::Blue => styled.blue(),
+        Color::Green => styled.green(),
+        Color::Yellow => styled.yellow(),
+        Color::Red => styled.red(),
+        Color::Magenta => styled.magenta(),
+        Color::Dim => styled.dim(),
+    }
+    .to_string()
+}
+
+// Without the `cli` feature the library prints plain text.
+#[cfg(not(feature = "cli"))]
+fn paint(text: &str, _color: Color) -> String {
+    text.to_string()
+}
+
+#[cfg(feature = "cli")]
+fn text_width(text: &str) -> usize {
+    console::measure_text_width(text)
+}
+
+#[cfg(not(feature = "cli"))]
+fn text_width(text: &str) -> usize {
+    text.chars().count()
+}
+
+#[macro_export]
+#[doc(hidden)]
+macro_rules! info {
+    ($($arg:tt)*) => {
+        $crate::ui::log($crate::ui::Level::Info, &format!($($arg)*))
+    };
+}
+
+#[macro_export]
+#[doc(hidden)]
+macro_rules! success {
+    ($($arg:tt)*) => {
+        $crate::ui::log($crate::ui::Level::Success, &format!($($arg)*))
+    };
+}
+
+#[macro_export]
+#[doc(hidden)]
+macro_rules! hint {
+    ($($arg:tt)*) => {
+        $crate::ui::log($crate::ui::Level::Hint, &format!($($arg)*))
+    };
+}
+
+#[macro_export]
+#[doc(hidden)]
+macro_rules! notice {
+    ($($arg:tt)*) => {
+        $crate::ui::log($crate::ui::Level::Notice, &format!($($arg)*))
+    };
+}
+
+#[macro_export]
+#[doc(hidden)]
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        $crate::ui::log($crate::ui::Level::Warn, &format!($($arg)*))
+    };
+}
+
+#[macro_export]
+#[doc(hidden)]
+macro_rules! error {
+    ($($arg:tt)*) => {
+        $crate::ui::log($crate::ui::Level::Error, &format!($($arg)*))
+    };
+}
+
+// Only shown in verbose mode, on stderr.
+#[macro_export]
+#[doc(hidden)]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        $crate::ui::log($crate::ui::Level::Debug, &format!($($arg)*))
+    };
+}
+
+// Regular program output (menus, listings, help) that is not subject to verbosity.
+#[macro_export]
+#[doc(hidden)]
+macro_rules! say {
+    ($($arg:tt)*) => {
+        println!($($arg)*)
+    };
+}
+
+// Exported at the crate root by `macro_export`; use them through this module.
+pub use {debug, error, hint, info, notice, say, success, warning};
+
+// Drop-in replacements for the `Command` runners that go through `traced`.
+pub trait TracedCommand {
+    fn traced_status(&mut self) -> io::Result<ExitStatus>;
+    fn traced_output(&mut self) -> io::Result<Output>;
+    fn traced_spawn(&mut self) -> io::Result<Child>;
+}
+
+impl TracedCommand for Command {
+    fn traced_status(&mut self) -> io::Result<ExitStatus> {
+        traced(self, Command::status)
+    }
+
+    fn traced_output(&mut self) -> io::Result<Output> {
+        traced(self, Command::output)
+    }
+
+    // Only the spawn itself is timed; the caller owns the child's lifetime.
+    fn traced_spawn(&mut self) -> io::Result<Child> {
+        traced(self, Command::spawn)
+    }
+}
+
+// Verbose mode logs every external command with its argv and duration, on stderr so stdout
+// consumers stay clean.
+fn traced<T>(
+    command: &mut Command,
+    run: impl FnOnce(&mut Command) -> io::Result<T>,
+) -> io::Result<T> {
+    if verbosity() != Verbosity::Verbose {
+        return run(command);
+    }
+    let argv = describe_command(command);
+    let started = Instant::now();
+    let result = run(command);
+    eprintln!(
+        "{} {} ({})",
+        paint("[exec]", Color::Magenta),
+        argv,
+        format_elapsed(started.elapsed())
+    );
+    result
+}
+
+fn describe_command(command: &Command) -> String {
+    let mut argv = vec![shell_quote(&command.get_program().to_string_lossy())];
+    argv.extend(
+        command
+            .get_args()
+            .map(|arg| shell_quote(&arg.to_string_lossy())),
+    );
+    match command.get_current_dir() {
+        Some(dir) => format!("{} (in {})", argv.join(" "), dir.display()),
+        None => argv.join(" "),
+    }
+}
+
+// Renders `path` relative to `base` (or `~`) and middle-truncates it to `max_width` columns.
+pub fn shorten_path(
+    path: &Path,
+    base: Option<&Path>,
+    home: Option<&Path>,
+    max_width: usize,
+) -> String {
+    let display = if let Some(relative) = base.and_then(|base| path.strip_prefix(base).ok())
+        && !relative.as_os_str().is_empty()
+    {
+        relative.display().to_string()
+    } else if let Some(relative) = home.and_then(|home| path.strip_prefix(home).ok()) {
+        if relative.as_os_str().is_empty() {
+            "~".to_string()
+        } else {
+            format!("~/{}", relative.display())
+        }
+    } else {
+        path.display().to_string()
+    };
+    truncate_middle(&display, max_width)
+}
+
+pub fn truncate_middle(text: &str, max_width: usize) -> String {
+    if max_width == 0 || text_width(text) <= max_width {
+        return text.to_string();
+    }
+    const ELLIPSIS: char = '…';
+    let budget = max_width.saturating_sub(1);
+    let head_budget = budget.div_ceil(2);
+    let tail_budget = budget - head_budget;
+
+    let mut head = String::new();
+    let mut used = 0;
+    for ch in text.chars() {
+        let width = char_width(ch);
+        if used + width > head_budget {
+            break;
+        }
+        head.push(ch);
+        used += width;
+    }
+
+    let mut tail = Vec::new();
+    let mut used = 0;
+    for ch in text.chars().rev() {
+        let width = char_width(ch);
+        if used + width > tail_budget {
+            break;
+        }
+        tail.push(ch);
+        used += width;
+    }
+
+    let mut result = head;
+    result.push(ELLIPSIS);
+    result.extend(tail.into_iter().rev());
+    result
+}
 
-    Ok(None)
+fn char_width(ch: char) -> usize {
+    let mut buf = [0u8; 4];
+    text_width(ch.encode_utf8(&mut buf))
+}
+
+// Recently used values, newest first, without duplicates and at most `capacity` long.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MruList {
+    entries: Vec<String>,
+    capacity: usize,
+}
+
+impl MruList {
+    // `entries` are taken newest first; later duplicates and blanks are dropped.
+    pub fn new(entries: Vec<String>, capacity: usize) -> Self {
+        let mut list = Self {
+            entries: Vec::new(),
+            capacity,
+        };
+        for entry in entries {
+            if !entry.trim().is_empty() && !list.entries.contains(&entry) {
+                list.entries.push(entry);
+            }
+        }
+        list.entries.truncate(capacity);
+        list
+    }
+
+    // Moves `value` to the front, evicting the oldest entry past the capacity.
+    pub fn record(&mut self, value: &str) {
+        let value = value.trim();
+        if value.is_empty() {
+            return;
+        }
+        self.entries.retain(|entry| entry != value);
+        self.entries.insert(0, value.to_string());
+        self.entries.truncate(self.capacity);
+    }
+
+    // Drops the entries `keep` rejects; true when any was dropped.
+    pub fn retain(&mut self, mut keep: impl FnMut(&str) -> bool) -> bool {
+        let before = self.entries.len();
+        self.entries.retain(|entry| keep(entry));
+        self.entries.len() != before
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    // The entries followed by the `defaults` not among them, for a picker that always offers
+    // the configured values.
+    pub fn with_defaults(&self, defaults: &[&str]) -> Vec<String> {
+        let mut values = self.entries.clone();
+        for default in defaults {
+            if !default.trim().is_empty() && !values.iter().any(|value| value == default) {
+                values.push(default.to_string());
+            }
+        }
+        values
+    }
+}
+
+// Runs `command` with a spinner and elapsed time on stderr; child output is forwarded line by line
+// with the spinner line cleared first so the two never interleave.
+pub fn run_with_progress(label: &str, command: &mut Command) -> io::Result<ExitStatus> {
+    traced(command, |command| spin_until_done(label, command))
+}
+
+fn spin_until_done(label: &str, command: &mut Command) -> io::Result<ExitStatus> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let started = Instant::now();
+    let terminal = Arc::new(Mutex::new(()));
+    let done = Arc::new(AtomicBool::new(false));
+    let animated = io::stderr().is_terminal();
+
+    let mut forwarders = Vec::new();
+    if let Some(stdout) = child.stdout.take() {
+        let terminal = Arc::clone(&terminal);
+        forwarders.push(thread::spawn(move || {
+            forward_lines(stdout, &terminal, animated, |line| {
+                let mut out = io::stdout();
+                let _ = writeln!(out, "{}", line);
+                let _ = out.flush();
+            })
+        }));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        let terminal = Arc::clone(&terminal);
+        forwarders.push(thread::spawn(move || {
+            forward_lines(stderr, &terminal, animated, |line| {
+                let _ = writeln!(io::stderr(), "{}", line);
+            })
+        }));
+    }
+
+    let ticker = animated.then(|| {
+        let terminal = Arc::clone(&terminal);
+        let done = Arc::clone(&done);
+        let label = label.to_string();
+        thread::spawn(move || {
+            const FRAMES: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+            let mut frame = 0;
+            while !done.load(Ordering::Relaxed) {
+                if let Ok(_guard) = terminal.lock() {
+                    let _ = write!(
+                        io::stderr(),
+                        "\r\x1b[2K{} {} ({})",
+                        FRAMES[frame % FRAMES.len()],
+                        label,
+                        format_elapsed(started.elapsed())
+                    );
+                }
+                frame += 1;
+                thread::sleep(Duration::from_millis(100));
+            }
+        })
+    });
+
+    let status = child.wait();
+    for forwarder in forwarders {
+        let _ = forwarder.join();
+    }
+    done.store(true, Ordering::Relaxed);
+    if let Some(ticker) = ticker {
+        let _ = ticker.join();
+        let _ = write!(io::stderr(), "\r\x1b[2K");
+    }
+    status
+}
+
+fn forward_lines(
+    stream: impl Read,
+    terminal: &Mutex<()>,
+    animated: bool,
+    mut emit: impl FnMut(&str),
+) {
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        let _guard = terminal.lock();
+        if animated {
+            let _ = write!(io::stderr(), "\r\x1b[2K");
+        }
+        emit(&line);
+    }
+}
+
+pub fn format_elapsed(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    if secs >= 60 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else if secs >= 10 {
+        format!("{}s", secs)
+    } else {
+        format!("{:.1}s", elapsed.as_secs_f64())
+    }
+}
+
+pub fn format_ago(secs: u64) -> String {
+    match secs {
+        0..60 => "just now".to_string(),
+        60..3_600 => format!("{}m ago", secs / 60),
+        3_600..86_400 => format!("{}h ago", secs / 3_600),
+        _ => format!("{}d ago", secs / 86_400),
+    }
+}
+
+pub fn program_on_path(program: &str) -> bool {
+    std::env::var_os("PATH")
+        .map(|paths| std::env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+        .unwrap_or(false)
+}
+
+pub fn shell_quote(value: &str) -> String {
+    if !value.is_empty()
+        && value
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || "/._-+:@%".contains(ch))
+    {
+        return value.to_string();
+    }
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(entries: &[&str], capacity: usize) -> MruList {
+        MruList::new(
+            entries.iter().map(|entry| entry.to_string()).collect(),
+            capacity,
+        )
+    }
+
+    #[test]
+    fn mru_list_drops_duplicates_blanks_and_overflow() {
+        let list = list(
+            &[
+                "release/2",
+                "",
+                "main",
+                "release/2",
+                "  ",
+                "release/1",
+                "dev",
+            ],
+            3,
+        );
+        assert_eq!(list.entries(), ["release/2", "main", "release/1"]);
+    }
+
+    #[test]
+    fn mru_list_moves_recorded_values_to_the_front() {
+        let mut list = list(&["release/2", "main", "release/1"], 3);
+        list.record("release/1");
+        assert_eq!(list.entries(), ["release/1", "release/2", "main"]);
+        list.record(" release/3 ");
+        assert_eq!(list.entries(), ["release/3", "release/1", "release/2"]);
+        list.record("");
+        assert_eq!(list.entries(), ["release/3", "release/1", "release/2"]);
+    }
+
+    #[test]
+    fn mru_list_prunes_rejected_entries() {
+        let mut list = list(&["release/2", "gone", "main"], 5);
+        assert!(list.retain(|entry| entry != "gone"));
+        assert_eq!(list.entries(), ["release/2", "main"]);
+        assert!(!list.retain(|_| true));
+    }
+
+    #[test]
+    fn mru_list_appends_missing_defaults() {
+        let list = list(&["release/2", "main"], 5);
+        assert_eq!(
+            list.with_defaults(&["main", "develop", ""]),
+            ["release/2", "main", "develop"]
+        );
+        assert_eq!(MruList::default().with_defaults(&["main"]), ["main"]);
+    }
+
+    #[test]
+    fn shorten_path_prefers_the_base_then_home() {
+        let base = Path::new("/home/me/work/api-worktree-agents");
+        let home = Path::new("/home/me");
+        let cases = [
+            ("/home/me/work/api-worktree-agents/fix-login", "fix-login"),
+            (
+                "/home/me/work/api-worktree-agents/r\u{e9}sum\u{e9}-\u{4fee}\u{6b63}",
+                "r\u{e9}sum\u{e9}-\u{4fee}\u{6b63}",
+            ),
+            // The base itself is not reduced to an empty label.
+            (
+                "/home/me/work/api-worktree-agents",
+                "~/work/api-worktree-agents",
+            ),
+            ("/home/me/other/api", "~/other/api"),
+            ("/home/me", "~"),
+            ("/srv/agents/api", "/srv/agents/api"),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(
+                shorten_path(Path::new(path), Some(base), Some(home), 0),
+                expected,
+                "{}",
+                path
+            );
+        }
+        assert_eq!(
+            shorten_path(Path::new("/srv/agents/api"), None, None, 0),
+            "/srv/agents/api"
+        );
+    }
+
+    #[test]
+    fn shorten_path_truncates_what_is_still_too_wide() {
+        let path = Path::new("/home/me/projects/company/service/fix-login");
+        let short = shorten_path(path, None, Some(Path::new("/home/me")), 20);
+        assert_eq!(short, "~/projects\u{2026}fix-login");
+        assert_eq!(short.chars().count(), 20);
+    }
+
+    #[test]
+    fn truncate_middle_keeps_both_ends() {
+        let cases = [
+            ("abcdefghij", 0, "abcdefghij"),
+            ("abcdefghij", 10, "abcdefghij"),
+            ("abcdefghij", 9, "abcd\u{2026}ghij"),
+            ("abcdefghij", 5, "ab\u{2026}ij"),
+            ("abcdefghij", 1, "\u{2026}"),
+            ("d\u{e9}j\u{e0}-vu/caf\u{e9}", 7, "d\u{e9}j\u{2026}af\u{e9}"),
+        ];
+        for (text, width, expected) in cases {
+            assert_eq!(truncate_middle(text, width), expected, "{} {}", text, width);
+        }
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn truncate_middle_counts_wide_characters_as_two_columns() {
+        let truncated = truncate_middle("\u{65e5}\u{672c}\u{8a9e}\u{306e}\u{30d1}\u{30b9}", 7);
+        assert_eq!(truncated, "\u{65e5}\u{2026}\u{30b9}");
+        assert!(text_width(&truncated) <= 7);
+    }
 }
@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use agent_manager::Error;
+use agent_manager::git::GitBackend;
+use agent_manager::templates::VariablePresets;
+
+// A throwaway directory under the system temp dir, removed on drop.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("agent-manager-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self(path.canonicalize().unwrap())
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn git(dir: &Path, args: &[&str]) {
+    let status = Command::new("git")
+        .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+        .args([
+            "-c",
+            "init.defaultBranch=main",
+            "-c",
+            "commit.gpgsign=false",
+        ])
+        .args(args)
+        .current_dir(dir)
+        .status()
+        .unwrap();
+    assert!(status.success(), "git {:?} failed", args);
+}
+
+fn init_repo(dir: &Path) {
+    git(dir, &["init", "-q"]);
+    fs::write(dir.join("README.md"), "hello\n").unwrap();
+    git(dir, &["add", "README.md"]);
+    git(dir, &["commit", "-q", "-m", "initial"]);
+}
+
+#[test]
+fn creates_a_worktree_on_a_new_branch() {
+    let tmp = TempDir::new("library-worktree");
+    let root = tmp.0.join("repo");
+    fs::create_dir(&root).unwrap();
+    init_repo(&root);
+
+    let repo = agent_manager::open_repository(&root).unwrap();
+    assert_eq!(repo.root(), root);
+
+    let worktree_dir = tmp.0.join("feature");
+    agent_manager::create_worktree(&repo, "feature", &worktree_dir, "main").unwrap();
+
+    assert!(worktree_dir.join("README.md").is_file());
+    let worktrees = repo.list_worktrees().unwrap();
+    assert!(worktrees.iter().any(|worktree| {
+        worktree.path == worktree_dir && worktree.branch.as_deref() == Some("feature")
+    }));
+}
+
+#[test]
+fn rejects_a_directory_outside_any_repository() {
+    let tmp = TempDir::new("library-no-repo");
+    let err = agent_manager::open_repository(&tmp.0).unwrap_err();
+    assert!(matches!(err, Error::NotARepository { ref dir, .. } if *dir == tmp.0));
+}
+
+#[test]
+fn renders_a_template_from_a_map() {
+    let variables = HashMap::from([("feature".to_string(), "login".to_string())]);
+    let mut answers = HashMap::from([("owner".to_string(), "alice".to_string())]);
+    let rendered = agent_manager::render_template(
+        "Build ${feature} for ${owner}.",
+        &variables,
+        &VariablePresets::default(),
+        &mut answers,
+    )
+    .unwrap();
+    assert_eq!(rendered, "Build login for alice.");
+}